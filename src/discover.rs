@@ -0,0 +1,62 @@
+use crate::util::annotation;
+use crate::util::fasta::{self, Alphabet};
+use crate::util::motif::{self, DiscoveredMotif, MotifSearch, SiteModel};
+use crate::util::motif_io::{self, Motif, MotifFormat};
+use rand::Rng;
+use std::io::{self, Error};
+
+/// Looks for motifs by EM and by Gibbs sampling, in the records of a FASTA file or, for a GenBank file, in the
+/// `upstream` bases before every CDS. Prints the background, then each motif's sites and log-likelihood, and finally
+/// every motif in MEME format so they can be handed to other tools.
+pub fn run<R: Rng>(file_path: &str, width: usize, motifs: usize, site_model: SiteModel, upstream: usize, rng: &mut R) -> Result<(), Error> {
+    let (names, seqs) = load_sequences(file_path, upstream)?;
+    let search = MotifSearch::new(width).site_model(site_model).motifs(motifs);
+    let background = search.background_for(&seqs);
+    println!("Sequences: {}", seqs.len());
+    println!(
+        "Background: A={:.4} C={:.4} G={:.4} T={:.4}",
+        background[0], background[1], background[2], background[3]
+    );
+
+    let mut found = Vec::new();
+    for (method, discovered) in [("EM", search.em(&seqs)), ("Gibbs", search.gibbs(&seqs, rng))] {
+        for (i, d) in discovered.into_iter().enumerate() {
+            print_motif(method, i + 1, &d, &names, &seqs, width);
+            found.push(Motif {
+                id: format!("{}_{}", method.to_lowercase(), i + 1),
+                name: d.pwm.consensus(),
+                pwm: d.pwm,
+            });
+        }
+    }
+
+    println!("\nMotifs in MEME format:");
+    return motif_io::write_motifs(&mut io::stdout().lock(), &found, MotifFormat::Meme);
+}
+
+fn load_sequences(file_path: &str, upstream: usize) -> Result<(Vec<String>, Vec<Vec<u8>>), Error> {
+    if annotation::is_genbank(file_path) {
+        let records = annotation::read_records(file_path)?;
+        return Ok(motif::upstream_regions(&records, &["CDS"], upstream).into_iter().unzip());
+    }
+
+    let mut names = Vec::new();
+    let mut seqs = Vec::new();
+    for record in fasta::Reader::from_path(file_path)?.alphabet(Alphabet::IUPAC).uppercase() {
+        let record = record?;
+        names.push(record.id);
+        seqs.push(record.seq.into_bytes());
+    }
+    return Ok((names, seqs));
+}
+
+fn print_motif(method: &str, number: usize, motif: &DiscoveredMotif, names: &[String], seqs: &[Vec<u8>], width: usize) {
+    println!("\n{} motif {}: {}", method, number, motif.pwm.consensus());
+    println!("Log-Likelihood: {:.4}", motif.log_likelihood);
+    println!("Information Content: {:.4} bits", motif.pwm.total_information_content());
+    println!("Sites: {} (expected fraction of sequences {:.4})", motif.sites.len(), motif.site_fraction);
+    for site in &motif.sites {
+        let instance = String::from_utf8_lossy(&seqs[site.sequence][site.start..site.start + width]);
+        println!("{} {} {} {:.4}", names[site.sequence], site.start, instance, site.probability);
+    }
+}
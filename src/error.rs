@@ -0,0 +1,163 @@
+use crate::util::dag::DagError;
+use crate::util::location::LocationError;
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Kinds of input file the pipelines read, named in diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Fasta,
+    GenBank,
+    /// `V name [START|END]` and `E name from to weight` lines, as in `hw4`, or tab-separated edges
+    Dag,
+    /// Blocks of a `# chr:start-end` header and one tab-separated row per species, as in `hw9`
+    Alignment,
+    /// Tab- or space-separated counts, e.g. read starts per position or emissions per state
+    Counts,
+    /// GenBank, GFF3 or BED features to describe regions with
+    Annotation,
+}
+
+impl fmt::Display for FileKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            FileKind::Fasta => write!(f, "FASTA"),
+            FileKind::GenBank => write!(f, "GenBank"),
+            FileKind::Dag => write!(f, "DAG"),
+            FileKind::Alignment => write!(f, "alignment"),
+            FileKind::Counts => write!(f, "count"),
+            FileKind::Annotation => write!(f, "annotation"),
+        };
+    }
+}
+
+/// A line of a file that couldn't be parsed, carried inside an `io::Error` by the readers in `util` so that the line
+/// number survives until the file path is known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl LineError {
+    /// An `io::Error` of kind `InvalidData` wrapping the line and message.
+    pub fn invalid_data(line: usize, message: String) -> io::Error {
+        return io::Error::new(io::ErrorKind::InvalidData, LineError { line, message });
+    }
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "line {}: {}", self.line, self.message);
+    }
+}
+
+impl error::Error for LineError {}
+
+/// Why a pipeline failed, with enough context to find the problem in its input.
+#[derive(Debug)]
+pub enum Error {
+    /// A file couldn't be opened, read or written
+    Io { path: PathBuf, source: io::Error },
+    /// A line of an input file isn't in the expected format
+    Malformed {
+        kind: FileKind,
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    /// An input file parsed but doesn't hold together, e.g. a DAG without a `START` node or with a cycle
+    Invalid { kind: FileKind, path: PathBuf, message: String },
+    /// A feature location couldn't be resolved against its sequence
+    Location(LocationError),
+    /// A graph that should be acyclic isn't
+    Dag(DagError),
+    /// Any other I/O failure, with no particular file to blame
+    Other(io::Error),
+}
+
+impl Error {
+    pub fn io<P: AsRef<Path>>(path: P, source: io::Error) -> Error {
+        return Error::Io {
+            path: path.as_ref().to_path_buf(),
+            source,
+        };
+    }
+
+    pub fn malformed<P: AsRef<Path>>(kind: FileKind, path: P, line: usize, message: String) -> Error {
+        return Error::Malformed {
+            kind,
+            path: path.as_ref().to_path_buf(),
+            line,
+            message,
+        };
+    }
+
+    pub fn invalid<P: AsRef<Path>>(kind: FileKind, path: P, message: String) -> Error {
+        return Error::Invalid {
+            kind,
+            path: path.as_ref().to_path_buf(),
+            message,
+        };
+    }
+
+    /// Blames an error from reading a file on that file. Errors a reader raised over a particular line become
+    /// `Malformed`, and the rest `Io`.
+    pub fn in_file<P: AsRef<Path>>(kind: FileKind, path: P, error: io::Error) -> Error {
+        if let Some(line_error) = error.get_ref().and_then(|e| e.downcast_ref::<LineError>()) {
+            return Error::malformed(kind, path, line_error.line, line_error.message.clone());
+        }
+        return Error::io(path, error);
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Malformed { kind, path, line, message } => {
+                write!(f, "{}, line {}: malformed {} file: {}", path.display(), line, kind, message)
+            }
+            Error::Invalid { kind, path, message } => write!(f, "{}: invalid {} file: {}", path.display(), kind, message),
+            Error::Location(error) => write!(f, "{}", error),
+            Error::Dag(error) => write!(f, "{}", error),
+            Error::Other(error) => write!(f, "{}", error),
+        };
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        return match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Location(error) => Some(error),
+            Error::Dag(error) => Some(error),
+            Error::Other(error) => Some(error),
+            Error::Malformed { .. } | Error::Invalid { .. } => None,
+        };
+    }
+}
+
+impl From<io::Error> for Error {
+    /// Keeps location errors that were passed up as `io::Error`s typed.
+    fn from(error: io::Error) -> Error {
+        if let Some(location_error) = error.get_ref().and_then(|e| e.downcast_ref::<LocationError>()) {
+            return Error::Location(location_error.clone());
+        }
+        return Error::Other(error);
+    }
+}
+
+impl From<DagError> for Error {
+    fn from(error: DagError) -> Error {
+        return Error::Dag(error);
+    }
+}
+
+impl From<LocationError> for Error {
+    fn from(error: LocationError) -> Error {
+        return Error::Location(error);
+    }
+}
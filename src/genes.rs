@@ -0,0 +1,189 @@
+use crate::util::annotation;
+use crate::util::fasta::{self, Alphabet};
+use crate::util::feature_io;
+use crate::util::gene_caller::{self, Gene, GeneModel};
+use crate::util::orf::OrfFinder;
+use crate::util::strand::Strand;
+use crate::util::translate::Translator;
+use gb_io::seq::{Feature, Seq, Topology};
+use gb_io::writer::SeqWriter;
+use gb_io::{feature_kind, qualifier_key};
+use std::fs::File;
+use std::io::{self, BufWriter, Error, Write};
+use std::path::Path;
+
+/// File formats called genes can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeneFormat {
+    Gff3,
+    GenBank,
+}
+
+impl GeneFormat {
+    /// Guesses the format from a file's extension, falling back on GFF3.
+    pub fn from_path<P: AsRef<Path>>(file_path: P) -> GeneFormat {
+        return if annotation::is_genbank(file_path) {
+            GeneFormat::GenBank
+        } else {
+            GeneFormat::Gff3
+        };
+    }
+}
+
+/// Lists every ORF on all six frames of each record of a FASTA or GenBank file, one per line.
+pub fn orfs(file_path: &str, finder: &OrfFinder, circular: bool) -> Result<(), Error> {
+    let seqs = load_records(file_path, circular)?;
+    println!("Genetic Code: {} ({})", finder.code().id, finder.code().name);
+    println!("Sequence Start End Strand Frame Length Starts");
+    for seq in &seqs {
+        for orf in finder.find(&seq.seq, seq.is_circular()) {
+            println!(
+                "{} {} {} {} {:+} {} {}",
+                record_name(seq),
+                orf.start + 1,
+                orf.end,
+                strand_symbol(orf.strand),
+                orf.frame(seq.len()),
+                orf.len(),
+                orf.starts.len()
+            );
+        }
+    }
+    return Ok(());
+}
+
+/// Calls genes in each record of a FASTA or GenBank file by scoring its ORFs.
+///
+/// The models are trained on the CDSs of an annotated GenBank file when one is given, and otherwise on the longest
+/// ORFs of the input itself. Genes go to `output`, as GFF3 or GenBank depending on its extension, or to stdout as GFF3.
+pub fn call(file_path: &str, finder: &OrfFinder, training: Option<&str>, circular: bool, output: Option<&str>) -> Result<(), Error> {
+    let mut seqs = load_records(file_path, circular)?;
+    let orfs: Vec<_> = seqs.iter().map(|seq| finder.find(&seq.seq, seq.is_circular())).collect();
+    println!("Genetic Code: {} ({})", finder.code().id, finder.code().name);
+
+    let model = match training {
+        Some(training_path) => {
+            println!("Training: annotated CDSs of {}", training_path);
+            GeneModel::from_annotations(&annotation::read_records(training_path)?, gene_caller::DEFAULT_ORDER)?
+        }
+        None => {
+            println!("Training: ORFs of at least {} bases", gene_caller::SELF_TRAINING_LENGTH);
+            GeneModel::from_orfs(&seqs, &orfs, gene_caller::DEFAULT_ORDER)?
+        }
+    };
+    println!("Start Consensus: {}", model.start_matrix().consensus());
+
+    let mut genes = Vec::new();
+    for (seq, orfs) in seqs.iter().zip(&orfs) {
+        let called = gene_caller::call_genes(&model, seq, orfs, gene_caller::DEFAULT_MAX_OVERLAP);
+        println!("{}: {} ORFs, {} genes", record_name(seq), orfs.len(), called.len());
+        genes.push(called);
+    }
+
+    match output {
+        Some(output_path) => {
+            let out = BufWriter::new(File::create(output_path)?);
+            match GeneFormat::from_path(output_path) {
+                GeneFormat::Gff3 => write_gff3(out, &seqs, &genes, finder)?,
+                GeneFormat::GenBank => write_genbank(out, &mut seqs, &genes, finder)?,
+            }
+            println!("Genes written to {}", output_path);
+        }
+        None => {
+            println!("\nGenes in GFF3 format:");
+            write_gff3(io::stdout().lock(), &seqs, &genes, finder)?;
+        }
+    }
+    return Ok(());
+}
+
+/// Records of a GenBank file, or of a FASTA file as bare sequences. Every record is circular if `circular` is set, and
+/// otherwise only GenBank records whose LOCUS line says so.
+fn load_records(file_path: &str, circular: bool) -> Result<Vec<Seq>, Error> {
+    if annotation::is_genbank(file_path) {
+        let mut seqs = annotation::read_records(file_path)?;
+        if circular {
+            seqs.iter_mut().for_each(|seq| seq.topology = Topology::Circular);
+        }
+        return Ok(seqs);
+    }
+    let mut seqs = Vec::new();
+    for record in fasta::Reader::from_path(file_path)?.alphabet(Alphabet::IUPAC).skip_invalid().uppercase() {
+        let record = record?;
+        seqs.push(Seq {
+            name: Some(record.id),
+            topology: if circular { Topology::Circular } else { Topology::Linear },
+            molecule_type: Some("DNA".to_string()),
+            len: Some(record.seq.len()),
+            seq: record.seq.into_bytes(),
+            ..Seq::empty()
+        });
+    }
+    return Ok(seqs);
+}
+
+fn write_gff3<W: Write>(mut out: W, seqs: &[Seq], genes: &[Vec<Gene>], finder: &OrfFinder) -> Result<(), Error> {
+    let regions: Vec<(&str, i64)> = seqs.iter().map(|seq| (record_name(seq), seq.len())).collect();
+    let mut features = Vec::new();
+    for (seq, genes) in seqs.iter().zip(genes) {
+        let name = record_name(seq);
+        for (i, gene) in genes.iter().enumerate() {
+            // Genes across the origin of a circular record end past its length, as GFF3 allows
+            let mut feature = feature_io::Feature::new(name, "CDS", gene.orf.start, gene.orf.end, Some(gene.orf.strand));
+            feature.source = Some("bio-rs".to_string());
+            feature.score = Some((gene.score() * 100.0).round() / 100.0);
+            feature.phase = Some(0);
+            feature.attributes = vec![
+                ("ID".to_string(), format!("{}_{}", name, i + 1)),
+                ("coding_score".to_string(), format!("{:.2}", gene.coding_score)),
+                ("start_score".to_string(), format!("{:.2}", gene.start_score)),
+                ("transl_table".to_string(), finder.code().id.to_string()),
+            ];
+            features.push(feature);
+        }
+    }
+    feature_io::write_gff3(&mut out, &features, &regions)?;
+    return out.flush();
+}
+
+/// Writes the records with their called genes as CDS features, in place of whatever features they had.
+fn write_genbank<W: Write>(out: W, seqs: &mut [Seq], genes: &[Vec<Gene>], finder: &OrfFinder) -> Result<(), Error> {
+    let mut writer = SeqWriter::new(out);
+    let translator = Translator::new(finder.code()).start_codon().trim_stop();
+    for (seq, genes) in seqs.iter_mut().zip(genes) {
+        let name = record_name(seq).to_string();
+        seq.features = genes
+            .iter()
+            .enumerate()
+            .map(|(i, gene)| Feature {
+                kind: feature_kind!("CDS"),
+                location: gene.orf.location(seq),
+                qualifiers: vec![
+                    (qualifier_key!("locus_tag"), Some(format!("{}_{}", name, i + 1))),
+                    (
+                        qualifier_key!("note"),
+                        Some(format!("coding_score={:.2}; start_score={:.2}", gene.coding_score, gene.start_score)),
+                    ),
+                    (qualifier_key!("transl_table"), Some(finder.code().id.to_string())),
+                    (
+                        qualifier_key!("translation"),
+                        Some(String::from_utf8(translator.translate(&gene.orf.sequence(&seq.seq))).unwrap()),
+                    ),
+                ],
+            })
+            .collect();
+        writer.write(seq)?;
+    }
+    return Ok(());
+}
+
+fn record_name(seq: &Seq) -> &str {
+    return seq.name.as_deref().or(seq.accession.as_deref()).unwrap_or("unnamed");
+}
+
+fn strand_symbol(strand: Strand) -> char {
+    return match strand {
+        Strand::Forward => '+',
+        Strand::Reverse => '-',
+    };
+}
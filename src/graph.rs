@@ -0,0 +1,62 @@
+use crate::error::{Error, FileKind};
+use crate::util::dag::{End, Objective, Start};
+use crate::util::dag_io::{self, DagFormat};
+
+/// Finds the highest-scoring path through a graph in the `V`/`E` format or an edge-list TSV, or the lowest-scoring one
+/// for `Objective::Shortest`, from its `START` node to its `END` node when both are marked and between any two nodes
+/// otherwise. The graph is written to `output` in the `V`/`E` format if it ends in `.dag` or `.txt`, e.g. to convert an
+/// edge list for `hw4`, and otherwise as DOT with the path highlighted, ready for GraphViz.
+///
+/// Given a `within` score, every path between the same ends that scores at least that well is listed too, best first.
+pub fn run(file_path: &str, objective: Objective, within: Option<f64>, output: Option<&str>) -> Result<(), Error> {
+    let format = DagFormat::from_path(file_path).unwrap_or(DagFormat::Dag);
+    let marked = dag_io::read_dag(file_path, format).map_err(|e| Error::in_file(FileKind::Dag, file_path, e))?;
+    let dag = &marked.dag;
+    dag.topological_order().map_err(|e| Error::invalid(FileKind::Dag, file_path, e.to_string()))?;
+    println!("Graph: {} nodes, {} edges", dag.len(), dag.edges().len());
+
+    let (start, end) = match (marked.start, marked.end) {
+        (Some(start), Some(end)) => (Start::Node(start), End::Node(end)),
+        _ => (Start::Anywhere, End::Anywhere),
+    };
+    let best_paths = dag.best_paths(objective, start)?;
+    let best = match end {
+        End::Node(node) => best_paths.path_to(node),
+        // An empty path at the best end means no edge beats 0, which isn't a path worth reporting
+        End::Anywhere => best_paths.best_end().and_then(|node| best_paths.path_to(node)).filter(|p| !p.edges.is_empty()),
+    };
+    match &best {
+        Some(path) => {
+            let nodes: Vec<&str> = path.nodes.iter().map(|node| dag.name(*node)).collect();
+            println!("Score: {}", path.score);
+            println!("Nodes: {}", nodes.join(" "));
+            println!("Path: {}", dag.path_labels(path, " "));
+            if let Some((_, count)) = dag.count_best_paths(objective, start, end)? {
+                println!("Optimal Paths: {}", count);
+            }
+        }
+        None => match start {
+            Start::Node(_) => println!("No path joins the marked nodes"),
+            Start::Anywhere if objective == Objective::Longest => println!("No path through the graph scores above 0"),
+            Start::Anywhere => println!("No path through the graph scores below 0"),
+        },
+    }
+
+    if let Some(threshold) = within {
+        let paths = dag.paths_within(objective, start, end, threshold)?;
+        println!("Paths Within {}: {}", threshold, paths.len());
+        for path in &paths {
+            println!("{} {}", path.score, dag.path_labels(path, " "));
+        }
+    }
+
+    if let Some(output_path) = output {
+        match DagFormat::from_path(output_path) {
+            Some(DagFormat::Dag) => dag_io::write_dag_to_path(output_path, &marked),
+            _ => dag_io::write_dot_to_path(output_path, &marked, best.as_ref()),
+        }
+        .map_err(|e| Error::io(output_path, e))?;
+        println!("Graph written to {}", output_path);
+    }
+    return Ok(());
+}
@@ -1,86 +1,105 @@
-use crate::util::read;
+use crate::error::{Error, FileKind};
+use crate::util::annotation::{describe_region, Annotations};
+use crate::util::fasta::{self, Alphabet, Record};
+use crate::util::fm_index::FmIndex;
+use crate::util::maximal_match::{maximal_exact_matches, maximal_unique_matches, MaximalMatch, SequenceInfo};
+use crate::util::nucleotide::reverse_complement;
+use crate::util::strand::Strand;
+use crate::util::suffix_array::SuffixArray;
 use itertools::Itertools;
-use std::cmp::min;
+use std::cmp::{min, Reverse};
 use std::collections::{HashMap, HashSet};
-use std::io::Error;
 use std::path::Path;
 
 const ALL_ALPHA_KEY: char = '*';
 
 const ALPHA_CHARS: [char; 5] = ['A', 'C', 'G', 'T', 'N'];
 
-#[derive(Debug, Hash, Eq, PartialEq)]
-struct SuffixPointer<'a> {
-    start_index: usize,
+const MUM_MIN_LEN: usize = 40;
+const TOP_MEMS: usize = 10;
+
+const INDEX_PATH: &str = "./data/hw/hw1/";
+const INDEX_SAMPLE_RATE: usize = 32;
+
+/// One sequence fed into the suffix array: a record from one of the input files, on one strand.
+struct SuffixSource<'a> {
+    file_name: &'a str,
+    id: &'a str,
     string: &'a str,
+    is_first: bool,
+    is_reverse: bool,
+    show_id: bool,
 }
 
-impl<'a> SuffixPointer<'a> {
-    fn new(start_index: usize, string: &'a str) -> SuffixPointer<'a> {
-        return SuffixPointer { start_index, string };
-    }
-
-    fn subsequence(&self, offset: usize) -> &'a str {
-        return &self.string[self.start_index..self.start_index + offset];
+impl<'a> SuffixSource<'a> {
+    fn new(file_name: &'a str, record: &'a Record, string: &'a str, is_first: bool, is_reverse: bool, show_id: bool) -> SuffixSource<'a> {
+        return SuffixSource {
+            file_name,
+            id: &record.id,
+            string,
+            is_first,
+            is_reverse,
+            show_id,
+        };
     }
 
-    fn suffix(&self) -> &'a str {
-        return &self.string[self.start_index..];
+    fn subsequence(&self, start_index: usize, offset: usize) -> &'a str {
+        return &self.string[start_index..start_index + offset];
     }
 }
 
-pub fn run(file_path1: &str, file_path2: &str) -> Result<(), Error> {
+pub fn run(file_path1: &str, file_path2: &str, annotation_path1: Option<&str>, annotation_path2: Option<&str>) -> Result<(), Error> {
     let file_name1 = file_name_from_path(file_path1);
     println!("Fasta 1: {}", file_name1);
-    let seq1 = load_sequence(file_path1)?;
+    let records1 = load_sequence(file_path1)?;
+    let annotations1 =
+        Annotations::load_if_given(annotation_path1).map_err(|e| Error::in_file(FileKind::Annotation, annotation_path1.unwrap_or_default(), e))?;
 
     let file_name2 = file_name_from_path(file_path2);
     println!("\nFasta 2: {}", file_name2);
-    let seq2 = load_sequence(file_path2)?;
-    let seq2_rev = reverse_complement(&seq2);
+    let records2 = load_sequence(file_path2)?;
+    let annotations2 =
+        Annotations::load_if_given(annotation_path2).map_err(|e| Error::in_file(FileKind::Annotation, annotation_path2.unwrap_or_default(), e))?;
+    let records2_rev: Vec<String> = records2
+        .iter()
+        .map(|r| String::from_utf8(reverse_complement(r.seq.as_bytes())).unwrap())
+        .collect();
+
+    let mut sources = Vec::new();
+    for r in records1.iter() {
+        sources.push(SuffixSource::new(file_name1, r, &r.seq, true, false, records1.len() > 1));
+    }
+    for (r, rev) in records2.iter().zip(records2_rev.iter()) {
+        sources.push(SuffixSource::new(file_name2, r, &r.seq, false, false, records2.len() > 1));
+        sources.push(SuffixSource::new(file_name2, r, rev, false, true, records2.len() > 1));
+    }
+
+    let strings: Vec<&[u8]> = sources.iter().map(|s| s.string.as_bytes()).collect();
+    let suffix_array = SuffixArray::new(&strings);
+    let is_first: Vec<bool> = suffix_array.suffixes().iter().map(|p| sources[suffix_array.source(*p).0].is_first).collect();
 
-    let mut suffix_array = build_suffix_array(Vec::from([seq1.as_str(), seq2.as_str(), seq2_rev.as_str()]));
-    suffix_array.sort_unstable_by_key(|s| &s.string[s.start_index..]);
+    let matches = find_closest_matches(&suffix_array, &is_first);
 
     let mut len_histogram: HashMap<usize, i32> = HashMap::new();
     let mut longest_len = 0;
-    let mut longest_matches: HashSet<&SuffixPointer> = HashSet::new();
+    let mut longest_matches: HashSet<usize> = HashSet::new();
     let mut match_string = "";
-    for (i, s1) in suffix_array.iter().enumerate() {
-        if std::ptr::eq(seq1.as_str(), s1.string) {
-            let mut s2_idx = 0;
-            let mut max_len = 0;
-            for j in (0..i).rev() {
-                if !std::ptr::eq(seq1.as_str(), suffix_array[j].string) {
-                    max_len = count_common_prefix(&s1.suffix(), &suffix_array[j].suffix());
-                    s2_idx = j;
-                    break;
-                }
-            }
-
-            for j in i + 1..suffix_array.len() {
-                if !std::ptr::eq(seq1.as_str(), suffix_array[j].string) {
-                    let len = count_common_prefix(&s1.suffix(), &suffix_array[j].suffix());
-                    if len > max_len {
-                        max_len = len;
-                        s2_idx = j;
-                    }
-                    break;
-                }
-            }
-
-            if max_len > longest_len {
-                longest_matches.clear();
-                longest_len = max_len;
-                match_string = s1.subsequence(longest_len);
-                longest_matches.insert(&s1);
-                longest_matches.insert(&suffix_array[s2_idx]);
-            } else if max_len == longest_len {
-                longest_matches.insert(&s1);
-                longest_matches.insert(&suffix_array[s2_idx]);
-            }
-            *len_histogram.entry(max_len).or_default() += 1
+    for (r1, (max_len, r2)) in matches.iter().enumerate() {
+        if !is_first[r1] {
+            continue;
+        }
+        if *max_len > longest_len {
+            longest_matches.clear();
+            longest_len = *max_len;
+            let (seq, start_index) = suffix_array.source(suffix_array.suffix(r1));
+            match_string = sources[seq].subsequence(start_index, longest_len);
+            longest_matches.insert(r1);
+            longest_matches.insert(*r2);
+        } else if *max_len == longest_len {
+            longest_matches.insert(r1);
+            longest_matches.insert(*r2);
         }
+        *len_histogram.entry(*max_len).or_default() += 1
     }
 
     println!("\nMatch Length Histogram:");
@@ -89,104 +108,179 @@ pub fn run(file_path1: &str, file_path2: &str) -> Result<(), Error> {
     }
 
     println!("\nThe longest match length: {}", longest_len);
-    let unique_seqs: HashSet<&str> = HashSet::from_iter(longest_matches.clone().into_iter().map(|s| s.subsequence(longest_len)));
+    let unique_seqs: HashSet<&str> = HashSet::from_iter(longest_matches.iter().map(|r| {
+        let (seq, start_index) = suffix_array.source(suffix_array.suffix(*r));
+        sources[seq].subsequence(start_index, longest_len)
+    }));
     println!("Number of match strings: {}", unique_seqs.len());
 
     println!("\nMatch string: {}", match_string);
-    println!("Description: This sequence comes from [look up entry in .gbff annotation file using the position information below]");
 
-    for s in longest_matches {
-        if std::ptr::eq(seq1.as_str(), s.string) {
-            println!("\nFasta: {}\nPosition: {}\nStrand: forward", file_name1, s.start_index + 1)
-        } else if std::ptr::eq(seq2.as_str(), s.string) {
-            println!("\nFasta: {}\nPosition: {}\nStrand: forward", file_name2, s.start_index + 1)
+    for r in longest_matches {
+        let (seq, start_index) = suffix_array.source(suffix_array.suffix(r));
+        let source = &sources[seq];
+        let strand = if source.is_reverse { "reverse" } else { "forward" };
+        if source.show_id {
+            println!(
+                "\nFasta: {} ({})\nPosition: {}\nStrand: {}",
+                source.file_name,
+                source.id,
+                start_index + 1,
+                strand
+            )
         } else {
-            println!("\nFasta: {}\nPosition: {}\nStrand: reverse", file_name2, s.start_index + 1)
+            println!("\nFasta: {}\nPosition: {}\nStrand: {}", source.file_name, start_index + 1, strand)
+        }
+
+        // Reverse strand positions index into the reverse complement, so map them back onto the annotated strand
+        let start = if source.is_reverse {
+            source.string.len() - start_index - longest_len
+        } else {
+            start_index
+        };
+        let annotations = if source.is_first { annotations1.as_ref() } else { annotations2.as_ref() };
+        for line in describe_region(annotations, source.id, start as i64, (start + longest_len) as i64) {
+            println!("Description: {}", line);
         }
     }
 
-    Ok(())
-}
+    let info: Vec<SequenceInfo> = sources
+        .iter()
+        .map(|s| SequenceInfo {
+            genome: if s.is_first { 0 } else { 1 },
+            strand: if s.is_reverse { Strand::Reverse } else { Strand::Forward },
+        })
+        .collect();
+    print_maximal_matches(&suffix_array, &sources, &info);
 
-fn file_name_from_path(file_path: &str) -> &str {
-    return Path::new(file_path).file_name().unwrap().to_str().unwrap();
+    let indexed: Vec<usize> = (0..sources.len()).filter(|s| !sources[*s].is_reverse).collect();
+    let index_sources: Vec<&SuffixSource> = indexed.iter().map(|s| &sources[*s]).collect();
+    let index = load_index(file_name1, file_name2, &suffix_array, &indexed, &index_sources)?;
+    let (forward_count, reverse_count) = index.count(match_string.as_bytes());
+    println!("\nIndexed Match String Hits: {} forward, {} reverse", forward_count, reverse_count);
+    for hit in index.locate(match_string.as_bytes()) {
+        let source = index_sources[hit.sequence];
+        if source.show_id {
+            println!("{} ({}) {} {}", source.file_name, source.id, hit.start + 1, hit.strand);
+        } else {
+            println!("{} {} {}", source.file_name, hit.start + 1, hit.strand);
+        }
+    }
+
+    Ok(())
 }
 
-fn load_sequence(file_name: &str) -> Result<String, Error> {
-    let mut sequence = String::with_capacity(read::file_size(file_name) as usize);
-    let mut header: String = String::from("NO HEADER");
-    let mut base_counts = HashMap::new();
-    let mut alpha_count: u32 = 0;
-    let mut non_alpha_count: u32 = 0;
-
-    let lines = read::lines(file_name)?;
-    for line in lines {
-        if let Ok(ip) = line {
-            if ip.starts_with('>') {
-                header = ip;
-                continue;
-            }
-            for c in ip.to_uppercase().chars() {
-                if ALPHA_CHARS.contains(&c) {
-                    sequence.push(c);
-                    *base_counts.entry(c).or_default() += 1;
-                    alpha_count += 1;
-                } else if c != ' ' {
-                    // Don't count spaces for some reason?
-                    non_alpha_count += 1;
-                }
-            }
+/// Loads the FM-index over the forward strands of both genomes from disk, building and saving it on first use. A saved
+/// index is rebuilt from the `indexed` sequences of the suffix array if it can't be read or wasn't built from them, e.g.
+/// because either FASTA has changed.
+fn load_index(file_name1: &str, file_name2: &str, suffix_array: &SuffixArray, indexed: &[usize], sources: &[&SuffixSource]) -> Result<FmIndex, Error> {
+    let stem = |f: &str| Path::new(f).file_stem().unwrap().to_str().unwrap().to_string();
+    let index_path = format!("{}{}_{}.fmi", INDEX_PATH, stem(file_name1), stem(file_name2));
+    let strings: Vec<&[u8]> = sources.iter().map(|s| s.string.as_bytes()).collect();
+    if let Ok(index) = FmIndex::load(&index_path) {
+        if index.indexes(&strings) {
+            return Ok(index);
         }
     }
 
-    println!("Non-alphabetic characters: {}", non_alpha_count);
-    println!("{}", header);
-    println!("{}={}", ALL_ALPHA_KEY, alpha_count);
-    for key in ALPHA_CHARS {
-        println!("{}={}", key, base_counts.get(&key).unwrap_or(&0))
-    }
+    let index = FmIndex::new(suffix_array, indexed, INDEX_SAMPLE_RATE);
+    index.save(&index_path)?;
+    return Ok(index);
+}
 
-    Ok(sequence)
+fn print_maximal_matches(suffix_array: &SuffixArray, sources: &[SuffixSource], info: &[SequenceInfo]) {
+    let mums = maximal_unique_matches(suffix_array, info, MUM_MIN_LEN);
+    println!("\nMaximal Unique Matches (>= {} bp): {}", MUM_MIN_LEN, mums.len());
+    print_matches(mums.iter().sorted_by_key(|m| m.hits[0].start), sources);
+
+    // MEMs include every repeated copy that MUMs leave out, so only the longest are listed
+    let mems = maximal_exact_matches(suffix_array, info, MUM_MIN_LEN);
+    println!("\nMaximal Exact Matches (>= {} bp): {}", MUM_MIN_LEN, mems.len());
+    print_matches(mems.iter().sorted_by_key(|m| (Reverse(m.len), m.hits[0].start)).take(TOP_MEMS), sources);
 }
 
-fn build_suffix_array(strings: Vec<&str>) -> Vec<SuffixPointer> {
-    let total_size = strings.iter().fold(0, |sum, s| sum + s.len());
-    let mut suffix_array = Vec::with_capacity(total_size);
-    for string in strings {
-        for (i, _) in string.char_indices() {
-            suffix_array.push(SuffixPointer::new(i, string));
+fn print_matches<'a, I: Iterator<Item = &'a MaximalMatch>>(matches: I, sources: &[SuffixSource]) {
+    for m in matches {
+        print!("{}", m.len);
+        for hit in m.hits.iter() {
+            let source = &sources[hit.sequence];
+            if source.show_id {
+                print!(" {}:{}:{}", source.id, hit.start + 1, hit.strand);
+            } else {
+                print!(" {}:{}", hit.start + 1, hit.strand);
+            }
         }
+        println!();
     }
-    return suffix_array;
 }
 
-fn reverse_complement(sequence: &str) -> String {
-    let mut rev_complement = String::with_capacity(sequence.len());
+/// For every suffix from the first genome, finds the length of its longest common prefix with a suffix from the
+/// other genome along with that suffix's rank. The closest such suffixes above and below it in the array are the
+/// only candidates, and the common prefix with each is the minimum LCP over the ranks in between.
+fn find_closest_matches(suffix_array: &SuffixArray, is_first: &[bool]) -> Vec<(usize, usize)> {
+    let n = suffix_array.len();
+    let mut matches = vec![(0, 0); n];
 
-    for c in sequence.chars().rev() {
-        rev_complement.push(match c {
-            'A' => 'T',
-            'T' => 'A',
-            'C' => 'G',
-            'G' => 'C',
-            _ => 'N',
-        })
+    let mut run: Option<(usize, usize)> = None;
+    for r in 0..n {
+        if !is_first[r] {
+            run = Some((usize::MAX, r));
+            continue;
+        }
+        if let Some((len, partner)) = run {
+            let len = min(len, suffix_array.lcp(r));
+            matches[r] = (len, partner);
+            run = Some((len, partner));
+        }
+    }
+
+    let mut run: Option<(usize, usize)> = None;
+    for r in (0..n).rev() {
+        if !is_first[r] {
+            run = Some((suffix_array.lcp(r), r));
+            continue;
+        }
+        if let Some((len, partner)) = run {
+            if len > matches[r].0 {
+                matches[r] = (len, partner);
+            }
+            run = Some((min(len, suffix_array.lcp(r)), partner));
+        }
     }
 
-    return rev_complement;
+    return matches;
+}
+
+fn file_name_from_path(file_path: &str) -> &str {
+    return Path::new(file_path).file_name().unwrap().to_str().unwrap();
 }
 
-fn count_common_prefix(s1: &str, s2: &str) -> usize {
-    let min_len = min(s1.len(), s2.len());
-    let mut common_count = 0;
+fn load_sequence(file_name: &str) -> Result<Vec<Record>, Error> {
+    let mut base_counts = HashMap::new();
+    let mut alpha_count: u32 = 0;
+    let mut non_alpha_count: usize = 0;
 
-    for i in 0..min_len {
-        if s1.chars().nth(i) == s2.chars().nth(i) {
-            common_count += 1;
-        } else {
-            break;
+    let records: Vec<Record> = fasta::Reader::from_path(file_name)?
+        .alphabet(Alphabet::DNA_N)
+        .skip_invalid()
+        .uppercase()
+        .collect::<Result<_, _>>()?;
+    for record in records.iter() {
+        for c in record.seq.chars() {
+            *base_counts.entry(c).or_default() += 1;
+            alpha_count += 1;
         }
+        non_alpha_count += record.invalid_count;
+    }
+
+    println!("Non-alphabetic characters: {}", non_alpha_count);
+    for record in records.iter() {
+        println!("{}", record.header());
+    }
+    println!("{}={}", ALL_ALPHA_KEY, alpha_count);
+    for key in ALPHA_CHARS {
+        println!("{}={}", key, base_counts.get(&key).unwrap_or(&0))
     }
 
-    return common_count;
+    Ok(records)
 }
@@ -1,114 +1,203 @@
-use gb_io::feature_kind;
-use gb_io::reader::SeqReader;
-use gb_io::seq::{After, Before, Location};
+use crate::error::{Error, FileKind};
+use crate::util::annotation;
+use crate::util::fasta;
+use crate::util::feature_io::{self, FeatureFormat};
+use crate::util::location::{self, Anchor};
+use crate::util::motif_io::{self, Motif, MotifFormat};
+use crate::util::nucleotide::Base;
+use crate::util::pwm::Pwm;
+use crate::util::scan::{PwmScanner, DEFAULT_SCALE};
+use crate::util::simulate::{simulate_records, Iid, PlantedMotif};
+use crate::util::strand::Strand;
+use gb_io::seq::{Feature, Seq};
+use gb_io::{feature_kind, qualifier_key};
 use itertools::Itertools;
+use rand::Rng;
 use std::cmp::{max, min};
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::Error;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 
 const BASE_OFFSET: i64 = 10;
+/// Windows at least this unlikely under the background are reported as candidate start sites
+const P_VALUE_THRESHOLD: f64 = 1e-5;
+/// Records simulated with start sites drawn from the matrix planted in them, to see how many the scan finds, and the
+/// chance of a site starting at each base
+const SIMULATED_LENGTHS: [usize; 4] = [50_000; 4];
+const PLANTED_RATE: f64 = 1e-3;
+
+const STOP_CODONS: [&[u8; 3]; 3] = [b"TAA", b"TAG", b"TGA"];
+
+/// An annotated CDS start
+struct CdsStart {
+    /// 0-based position of the first base of the start codon
+    pos: i64,
+    /// Gene name, or locus tag if the CDS has no gene name
+    name: Option<String>,
+}
 
-#[derive(Eq, Hash, PartialEq, Debug, Clone, Copy)]
-enum Base {
-    A,
-    C,
-    G,
-    T,
-    N,
+/// Annotated CDS starts of one record, sorted by position on each strand, along with the stop codon each CDS ends on
+#[derive(Default)]
+struct StartIndex {
+    forward: Vec<CdsStart>,
+    reverse: Vec<CdsStart>,
+    /// 0-based position of the last base of every annotated stop codon
+    stops: HashSet<(i64, Strand)>,
 }
 
-impl Base {
-    fn from_char(b: char) -> Base {
-        match b {
-            'a' | 'A' => Base::A,
-            'c' | 'C' => Base::C,
-            'g' | 'G' => Base::G,
-            't' | 'T' => Base::T,
-            _ => Base::N,
+impl StartIndex {
+    fn new(seq: &Seq) -> StartIndex {
+        let mut index = StartIndex::default();
+        for f in seq.features.iter().filter(|f| f.kind == feature_kind!("CDS")) {
+            if let Ok((pos, strand)) = location::anchor(&f.location, Anchor::Start) {
+                let start = CdsStart { pos, name: feature_name(f) };
+                match strand {
+                    Strand::Forward => index.forward.push(start),
+                    Strand::Reverse => index.reverse.push(start),
+                }
+            }
+            if let Ok(stop) = location::anchor(&f.location, Anchor::End) {
+                index.stops.insert(stop);
+            }
         }
+        index.forward.sort_by_key(|s| s.pos);
+        index.reverse.sort_by_key(|s| s.pos);
+        return index;
+    }
+
+    fn starts(&self, strand: Strand) -> &[CdsStart] {
+        return match strand {
+            Strand::Forward => &self.forward,
+            Strand::Reverse => &self.reverse,
+        };
+    }
+
+    fn contains(&self, pos: i64, strand: Strand) -> bool {
+        let starts = self.starts(strand);
+        let i = starts.partition_point(|s| s.pos < pos);
+        return starts.get(i).is_some_and(|s| s.pos == pos);
+    }
+
+    /// The annotated start on the same strand closest to `pos`
+    fn nearest(&self, pos: i64, strand: Strand) -> Option<&CdsStart> {
+        let starts = self.starts(strand);
+        let i = starts.partition_point(|s| s.pos < pos);
+        let before = i.checked_sub(1).and_then(|j| starts.get(j));
+        return [before, starts.get(i)].into_iter().flatten().min_by_key(|s| (s.pos - pos).abs());
+    }
+
+    fn is_stop(&self, pos: i64, strand: Strand) -> bool {
+        return self.stops.contains(&(pos, strand));
     }
 }
 
-struct PositionalDistribution {
-    counts: HashMap<i64, HashMap<Base, usize>>,
-    cds_locs: Vec<Location>,
-    freqs: HashMap<i64, HashMap<Base, f64>>,
-    weights: HashMap<i64, HashMap<Base, f64>>,
-    background: BackgroundDistribution,
+/// A high-scoring window whose middle isn't an annotated CDS start, with what's known about its surroundings
+struct Outlier {
+    /// Record the window is on, and its length
+    seq_id: String,
+    seq_len: i64,
+    /// 0-based position of the middle of the window, on its strand
+    pos: i64,
+    strand: Strand,
+    score: f64,
+    p_value: f64,
+    /// Distance to the nearest annotated start on the same strand, read along the strand, so negative when the hit is
+    /// upstream of it
+    offset: Option<i64>,
+    gene: Option<String>,
+    /// Length of the open frame read from the hit up to and including its stop codon, if the frame stops
+    orf_len: Option<i64>,
+    /// Whether that frame ends on the stop codon of an annotated CDS, i.e. the hit is an alternative start for it
+    in_frame: bool,
 }
 
-impl PositionalDistribution {
-    fn new(counts: HashMap<i64, HashMap<Base, usize>>, cds_locs: Vec<Location>, background: BackgroundDistribution) -> PositionalDistribution {
-        let mut freqs: HashMap<i64, HashMap<Base, f64>> = HashMap::new();
-        counts.iter().for_each(|(p, m)| {
-            let pos_count = m.iter().fold(0, |t, (b, c)| if *b != Base::N { t + c } else { t });
-            m.iter().for_each(|(b, count)| {
-                let freq = *count as f64 / pos_count as f64;
-                freqs.entry(*p).or_default().insert(*b, freq);
-            });
-        });
+impl Outlier {
+    fn new(seq: &Seq, index: &StartIndex, pos: i64, strand: Strand, score: f64, p_value: f64) -> Outlier {
+        let step = match strand {
+            Strand::Forward => 1,
+            Strand::Reverse => -1,
+        };
+        let nearest = index.nearest(pos, strand);
+        let stop = find_stop(seq, pos, strand);
+        return Outlier {
+            seq_id: seq.name.clone().or_else(|| seq.accession.clone()).unwrap_or_default(),
+            seq_len: seq.len(),
+            pos,
+            strand,
+            score,
+            p_value,
+            offset: nearest.map(|s| (pos - s.pos) * step),
+            gene: nearest.and_then(|s| s.name.clone()),
+            orf_len: stop.map(|s| (s - pos) * step + 1),
+            in_frame: stop.is_some_and(|s| index.is_stop(s.rem_euclid(seq.len()), strand)),
+        };
+    }
 
-        let mut weights: HashMap<i64, HashMap<Base, f64>> = HashMap::new();
-        freqs.iter().for_each(|(p, m)| {
-            m.iter().for_each(|(b, p_site)| {
-                let weight = p_site.log2() - background.get_base_freq(*b).log2();
-                weights.entry(*p).or_default().insert(*b, weight);
-            });
+    /// The candidate start codon, named after the nearest annotated gene and its score.
+    fn to_feature(&self) -> feature_io::Feature {
+        // A reverse codon that starts before the origin is shifted past the end, as for features across the origin
+        let start = match self.strand {
+            Strand::Forward => self.pos,
+            Strand::Reverse => self.pos - 2,
+        };
+        let start = if start < 0 { start + self.seq_len } else { start };
+        let mut feature = feature_io::Feature::new(&self.seq_id, "start_codon", start, start + 3, Some(self.strand));
+        // BED scores are whole numbers, so the name carries the real one
+        feature.name = Some(match &self.gene {
+            Some(gene) => format!("{}:{:.4}", gene, self.score),
+            None => format!("{:.4}", self.score),
         });
+        feature.score = Some(self.score);
+        return feature;
+    }
+}
+
+struct PositionalDistribution {
+    pwm: Pwm,
+    sites: Vec<Vec<u8>>,
+    /// Annotated starts of each record, in the order they're read from the file
+    cds_starts: Vec<StartIndex>,
+    background: BackgroundDistribution,
+}
 
-        return PositionalDistribution {
-            counts,
-            cds_locs,
-            freqs,
-            weights,
+impl PositionalDistribution {
+    fn new(sites: Vec<Vec<u8>>, cds_starts: Vec<StartIndex>, background: BackgroundDistribution) -> Result<PositionalDistribution, Error> {
+        let base_freqs = Base::ACGT.map(|b| background.get_base_freq(b));
+        let pwm = Pwm::from_sites(&sites, -BASE_OFFSET, 0.0, base_freqs)?;
+        return Ok(PositionalDistribution {
+            pwm,
+            sites,
+            cds_starts,
             background,
-        };
+        });
     }
 
-    fn get_pos_count(&self, pos: i64, base: Base) -> usize {
-        return *self.counts.get(&pos).unwrap_or(&HashMap::new()).get(&base).unwrap_or(&0);
+    fn get_pos_count(&self, pos: i64, base: Base) -> u64 {
+        // Built from whole sites, so the counts are whole
+        return self.pwm.count(pos, base) as u64;
     }
 
     fn get_pos_freq(&self, pos: i64, base: Base) -> f64 {
-        return *self.freqs.get(&pos).unwrap_or(&HashMap::new()).get(&base).unwrap_or(&0.0);
+        return self.pwm.frequency(pos, base);
     }
 
     fn get_pos_weight(&self, pos: i64, base: Base) -> f64 {
-        return if base == Base::N {
-            0.0
-        } else {
-            *self.weights.get(&pos).unwrap_or(&HashMap::new()).get(&base).unwrap_or(&-99.0)
-        };
+        return self.pwm.weight(pos, base);
     }
 
     fn get_max_score(&self) -> f64 {
-        return self
-            .weights
-            .iter()
-            .fold(0.0, |t, (_, m)| t + *m.iter().max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap()).unwrap().1);
+        return self.pwm.max_score();
     }
 
-    fn in_cds_range(&self, pos: i64, is_complement: bool) -> bool {
-        return self
-            .cds_locs
-            .iter()
-            .filter(|l| match l {
-                Location::Complement(_) => is_complement,
-                _ => !is_complement,
-            })
-            .any(|l| {
-                let (start, _) = l.find_bounds().unwrap();
-                return start + BASE_OFFSET + 1 == pos;
-            });
+    fn in_cds_range(&self, record: usize, pos: i64, strand: Strand) -> bool {
+        return self.cds_starts[record].contains(pos, strand);
     }
 
     fn print_pos_count(&self) {
         println!("\nCount Matrix:");
-        for (p, _) in self.counts.iter().sorted_by_key(|w| w.0) {
+        for p in self.pwm.positions() {
             print!("{}", p);
-            for b in [Base::A, Base::C, Base::G, Base::T] {
-                print!(" {}", self.get_pos_count(*p, b));
+            for b in Base::ACGT {
+                print!(" {}", self.get_pos_count(p, b));
             }
             println!();
         }
@@ -116,10 +205,10 @@ impl PositionalDistribution {
 
     fn print_pos_freq(&self) {
         println!("\nFrequency Matrix:");
-        for (p, _) in self.freqs.iter().sorted_by_key(|w| w.0) {
+        for p in self.pwm.positions() {
             print!("{}", p);
-            for b in [Base::A, Base::C, Base::G, Base::T] {
-                print!(" {:.4}", self.get_pos_freq(*p, b));
+            for b in Base::ACGT {
+                print!(" {:.4}", self.get_pos_freq(p, b));
             }
             println!();
         }
@@ -127,10 +216,10 @@ impl PositionalDistribution {
 
     fn print_pos_weight(&self) {
         println!("\nWeight Matrix:");
-        for (p, _) in self.weights.iter().sorted_by_key(|w| w.0) {
+        for p in self.pwm.positions() {
             print!("{}", p);
-            for b in [Base::A, Base::C, Base::G, Base::T] {
-                print!(" {:.4}", self.get_pos_weight(*p, b));
+            for b in Base::ACGT {
+                print!(" {:.4}", self.get_pos_weight(p, b));
             }
             println!();
         }
@@ -174,14 +263,15 @@ impl BackgroundDistribution {
 
     fn print_base_freq(&self) {
         println!("\nBackground Frequency:");
-        for base in [Base::A, Base::C, Base::G, Base::T] {
+        for base in Base::ACGT {
             println!("{:?}={:.4}", base, self.get_base_freq(base));
         }
     }
 }
 
-pub fn run(file_path: &str) -> Result<(), Error> {
-    let pos_dist = count_positions(file_path)?;
+pub fn run<R: Rng>(file_path: &str, line_width: usize, rng: &mut R) -> Result<(), Error> {
+    let seqs = annotation::read_records(file_path).map_err(|e| Error::in_file(FileKind::GenBank, file_path, e))?;
+    let pos_dist = count_positions(&seqs)?;
 
     pos_dist.background.print_base_count();
     pos_dist.background.print_base_freq();
@@ -189,99 +279,72 @@ pub fn run(file_path: &str) -> Result<(), Error> {
     pos_dist.print_pos_freq();
     pos_dist.print_pos_weight();
     println!("\nMaximum Score: {:.10}", pos_dist.get_max_score());
+    println!("Consensus: {}", pos_dist.pwm.consensus());
+    println!("Information Content: {:.4} bits", pos_dist.pwm.total_information_content());
+
+    // Saved in MEME format so other tools (e.g. FIMO) can scan with the same model
+    let motif_path = Path::new(file_path).with_file_name("cds_start.meme");
+    let motif = Motif {
+        id: "cds_start".to_string(),
+        name: "CDS start".to_string(),
+        pwm: pos_dist.pwm.clone(),
+    };
+    motif_io::write_motifs_to_path(&motif_path, &[motif], MotifFormat::Meme)?;
+    println!("Matrix written to {}", motif_path.display());
 
-    score_positions(file_path, &pos_dist)?;
+    score_positions(file_path, &seqs, &pos_dist)?;
+    find_planted_sites(file_path, &pos_dist.pwm, line_width, rng)?;
 
     Ok(())
 }
 
-fn count_positions(file_path: &str) -> Result<PositionalDistribution, Error> {
-    let file = File::open(file_path).unwrap();
+fn count_positions(seqs: &[Seq]) -> Result<PositionalDistribution, Error> {
     let mut base_counts: HashMap<Base, usize> = HashMap::new();
     let mut forward_counts: HashMap<Base, usize> = HashMap::new();
-    let mut pos_counts: HashMap<i64, HashMap<Base, usize>> = HashMap::new();
-    let mut cds_locs: Vec<Location> = Vec::new();
+    let mut sites: Vec<Vec<u8>> = Vec::new();
+    let mut cds_starts: Vec<StartIndex> = Vec::new();
 
-    for seq in SeqReader::new(file) {
-        let seq = seq.unwrap();
+    for seq in seqs {
         seq.seq.iter().for_each(|c| {
-            *base_counts.entry(Base::from_char(*c as char)).or_default() += 1;
-            *forward_counts.entry(Base::from_char(*c as char)).or_default() += 1;
+            *base_counts.entry(Base::from_u8_lossy(*c).to_acgtn()).or_default() += 1;
+            *forward_counts.entry(Base::from_u8_lossy(*c).to_acgtn()).or_default() += 1;
         });
         seq.revcomp()
             .seq
             .iter()
-            .for_each(|c| *base_counts.entry(Base::from_char(*c as char)).or_default() += 1);
-        seq.features
-            .iter()
-            .filter(|f| f.kind == feature_kind!("CDS"))
-            .filter_map(|f| get_start_location(&f.location))
-            .for_each(|l| {
-                let mut p = -BASE_OFFSET;
-                if let Ok(sub_seq) = seq.extract_location(&l) {
-                    for c in sub_seq {
-                        *pos_counts.entry(p).or_default().entry(Base::from_char(c as char)).or_default() += 1;
-                        p += 1;
-                    }
-                    cds_locs.push(l);
-                }
-            });
+            .for_each(|c| *base_counts.entry(Base::from_u8_lossy(*c).to_acgtn()).or_default() += 1);
+        // CDSs with a partial start, or too close to the end of a linear record for a full window, are left out
+        for f in seq.features.iter().filter(|f| f.kind == feature_kind!("CDS")) {
+            let offset = BASE_OFFSET as usize;
+            if let Ok(site) = location::flank(seq, &f.location, Anchor::Start, offset, offset) {
+                sites.push(site);
+            }
+        }
+        cds_starts.push(StartIndex::new(seq));
     }
-    Ok(PositionalDistribution::new(
-        pos_counts,
-        cds_locs,
-        BackgroundDistribution::new(base_counts, forward_counts),
-    ))
+    PositionalDistribution::new(sites, cds_starts, BackgroundDistribution::new(base_counts, forward_counts))
 }
 
-fn score_positions(file_path: &str, pos_dist: &PositionalDistribution) -> Result<(), Error> {
-    let file = File::open(file_path).unwrap();
-    let window_size = (BASE_OFFSET * 2 + 1) as usize;
+fn score_positions(file_path: &str, seqs: &[Seq], pos_dist: &PositionalDistribution) -> Result<(), Error> {
     let mut all_score: HashMap<isize, usize> = HashMap::new();
     let mut cds_score: HashMap<isize, usize> = HashMap::new();
-    let mut outliers: Vec<(Location, f64)> = Vec::new();
-
-    for seq in SeqReader::new(file) {
-        let seq = seq.unwrap();
-        seq.seq.windows(window_size).enumerate().for_each(|(i, w)| {
-            let mut p = -BASE_OFFSET;
-            let mut score = 0.0;
-            for c in w {
-                score += pos_dist.get_pos_weight(p, Base::from_char(*c as char));
-                p += 1;
-            }
-            *all_score.entry(bin_score(score)).or_default() += 1;
-            if score >= 10.0 {
-                let s = i as i64 + BASE_OFFSET + 1;
-                if !pos_dist.in_cds_range(s, false) {
-                    outliers.push((Location::single(s), score));
+    let mut outliers: Vec<Outlier> = Vec::new();
+    let scanner = PwmScanner::new(&pos_dist.pwm, DEFAULT_SCALE);
+
+    for (record, seq) in seqs.iter().enumerate() {
+        scanner.scan_with(&seq.seq, |hit| {
+            *all_score.entry(bin_score(hit.score)).or_default() += 1;
+            if hit.p_value <= P_VALUE_THRESHOLD {
+                // The start codon sits in the middle of the window on either strand
+                let pos = hit.start as i64 + BASE_OFFSET;
+                if !pos_dist.in_cds_range(record, pos, hit.strand) {
+                    outliers.push(Outlier::new(seq, &pos_dist.cds_starts[record], pos, hit.strand, hit.score, hit.p_value));
                 }
             }
         });
-        seq.revcomp().seq.windows(window_size).enumerate().for_each(|(i, w)| {
-            let mut p = -BASE_OFFSET;
-            let mut score = 0.0;
-            for c in w {
-                score += pos_dist.get_pos_weight(p, Base::from_char(*c as char));
-                p += 1;
-            }
-            *all_score.entry(bin_score(score)).or_default() += 1;
-            if score >= 10.0 {
-                let s = seq.seq.len() as i64 - i as i64 - BASE_OFFSET;
-                if !pos_dist.in_cds_range(s, true) {
-                    outliers.push((Location::Complement(Box::from(Location::single(s))), score));
-                }
-            }
-        });
-        pos_dist.cds_locs.iter().for_each(|l| {
-            let mut p = -BASE_OFFSET;
-            let mut score = 0.0;
-            for c in seq.extract_location(&l).unwrap() {
-                score += pos_dist.get_pos_weight(p, Base::from_char(c as char));
-                p += 1;
-            }
-            *cds_score.entry(bin_score(score)).or_default() += 1;
-        });
+    }
+    for site in &pos_dist.sites {
+        *cds_score.entry(bin_score(pos_dist.pwm.score(site))).or_default() += 1;
     }
 
     println!("\nScore Histogram CDS:");
@@ -294,99 +357,116 @@ fn score_positions(file_path: &str, pos_dist: &PositionalDistribution) -> Result
         println!("{} {}", p, c);
     }
 
+    // Sensitivity of the threshold, which the outliers below are the other side of
+    let passing = pos_dist
+        .sites
+        .iter()
+        .filter(|site| scanner.p_value(pos_dist.pwm.score(site)) <= P_VALUE_THRESHOLD)
+        .count();
+    println!("\nAnnotated Starts Passing: {} of {}", passing, pos_dist.sites.len());
+
     println!("\nPosition List:");
-    for (l, s) in outliers.iter().sorted_by_key(|(l, _)| l.find_bounds().unwrap().0) {
-        let strand = match l {
-            Location::Complement(_) => 1,
-            _ => 0,
-        };
-        println!("{} {} {:.4}", l.find_bounds().unwrap().0, strand, s);
+    match scanner.threshold(P_VALUE_THRESHOLD) {
+        Some(t) => println!("Threshold: {:.4} (p <= {:e})", t, P_VALUE_THRESHOLD),
+        None => println!("Threshold: none (no score has p <= {:e})", P_VALUE_THRESHOLD),
+    }
+    println!("Position Strand Score P-Value Offset Gene ORF-Length In-Frame");
+    let dash = |v: Option<String>| v.unwrap_or_else(|| "-".to_string());
+    for o in outliers.iter().sorted_by_key(|o| o.pos) {
+        println!(
+            "{} {} {:.4} {:.3e} {} {} {} {}",
+            o.pos + 1,
+            o.strand as u8,
+            o.score,
+            o.p_value,
+            dash(o.offset.map(|d| format!("{:+}", d))),
+            dash(o.gene.clone()),
+            dash(o.orf_len.map(|l| l.to_string())),
+            if o.in_frame { "yes" } else { "no" }
+        );
     }
 
-    Ok(())
-}
+    // The candidate start codons as BED, to load into a genome browser next to the annotation
+    let bed_path = Path::new(file_path).with_file_name("cds_start_hits.bed");
+    let hits: Vec<feature_io::Feature> = outliers.iter().sorted_by_key(|o| o.pos).map(Outlier::to_feature).collect();
+    feature_io::write_features_to_path(&bed_path, &hits, FeatureFormat::Bed).map_err(|e| Error::io(&bed_path, e))?;
+    println!("Positions written to {}", bed_path.display());
 
-fn bin_score(score: f64) -> isize {
-    return min(max(-51, score.floor() as isize), 51);
+    Ok(())
 }
 
-fn get_start_location(l: &Location) -> Option<Location> {
-    if let Location::Complement(lc) = l {
-        match lc.as_ref() {
-            Location::Range(_, (end, _)) => {
-                return if is_partial(lc) {
-                    None
-                } else {
-                    Some(Location::Complement(Box::from(Location::simple_range(
-                        end - BASE_OFFSET - 1,
-                        end + BASE_OFFSET,
-                    ))))
-                }
-            }
-            Location::Join(ls) => {
-                let mut sls = Vec::new();
-                let mut rem = BASE_OFFSET;
-                for (i, li) in ls.iter().enumerate().rev() {
-                    if is_partial(li) {
-                        return None;
-                    };
-
-                    let (s, e) = li.find_bounds().unwrap();
-
-                    let end = if i == ls.len() - 1 { e + BASE_OFFSET } else { e };
-                    let start = if rem - (e - s) <= 0 { e - rem - 1 } else { s };
-
-                    sls.push(Location::simple_range(start, end));
-                    rem -= e - s;
-
-                    if rem <= 0 {
-                        break;
-                    }
-                }
-                sls.reverse();
-                return Some(Location::Complement(Box::from(Location::Join(sls))));
+/// Simulates records over the matrix's background with sites drawn from its frequencies planted in them, and reports
+/// how many of the sites the scan finds on the forward strand and how many other windows pass the threshold.
+fn find_planted_sites<R: Rng>(file_path: &str, pwm: &Pwm, line_width: usize, rng: &mut R) -> Result<(), Error> {
+    let simulated_path = Path::new(file_path).with_file_name("simulated_sites.fa");
+    let mut planted = PlantedMotif::new(Iid::new(pwm.background()), pwm.frequencies().to_vec(), PLANTED_RATE);
+    let mut writer = fasta::Writer::from_path(&simulated_path)
+        .map_err(|e| Error::io(&simulated_path, e))?
+        .line_width(line_width);
+    simulate_records(&mut writer, "simulated_sites", &SIMULATED_LENGTHS, &mut planted, rng).map_err(|e| Error::io(&simulated_path, e))?;
+    writer.finish().map_err(|e| Error::io(&simulated_path, e))?;
+
+    let scanner = PwmScanner::new(pwm, DEFAULT_SCALE);
+    let (mut found, mut other) = (0, 0);
+    let records = fasta::Reader::from_path(&simulated_path).map_err(|e| Error::io(&simulated_path, e))?;
+    for (record, sites) in records.zip(planted.sites()) {
+        let record = record.map_err(|e| Error::in_file(FileKind::Fasta, &simulated_path, e))?;
+        for hit in scanner.scan(record.seq.as_bytes(), P_VALUE_THRESHOLD) {
+            if hit.strand == Strand::Forward && sites.binary_search(&hit.start).is_ok() {
+                found += 1;
+            } else {
+                other += 1;
             }
-            _ => panic!("Don't know how to convert location '{}'", l),
         }
-    } else {
-        match l {
-            Location::Range((start, _), _) => {
-                return if is_partial(l) {
-                    None
-                } else {
-                    Some(Location::simple_range(start - BASE_OFFSET, start + BASE_OFFSET + 1))
-                }
-            }
-            Location::Join(ls) => {
-                let mut sls = Vec::new();
-                let mut rem = BASE_OFFSET;
-                for (i, li) in ls.iter().enumerate() {
-                    if is_partial(li) {
-                        return None;
-                    };
-
-                    let (s, e) = li.find_bounds().unwrap();
+    }
+    let planted_count: usize = planted.sites().iter().map(|s| s.len()).sum();
+    println!("\nPlanted Sites:");
+    println!("Simulated: {}", simulated_path.display());
+    println!("Found: {} of {}", found, planted_count);
+    println!("Other Hits: {}", other);
 
-                    let start = if i == 0 { s - BASE_OFFSET } else { s };
-                    let end = if rem - (e - s) <= 0 { s + rem + 1 } else { e };
+    Ok(())
+}
 
-                    sls.push(Location::simple_range(start, end));
-                    rem -= e - s;
+/// Gene name of a feature, falling back on its locus tag.
+fn feature_name(feature: &Feature) -> Option<String> {
+    return feature
+        .qualifier_values(qualifier_key!("gene"))
+        .chain(feature.qualifier_values(qualifier_key!("locus_tag")))
+        .next()
+        .map(|v| v.to_string());
+}
 
-                    if rem <= 0 {
-                        break;
-                    }
-                }
-                return Some(Location::Join(sls));
-            }
-            _ => panic!("Don't know how to convert location '{}'", l),
+/// Position of the last base of the first stop codon in frame with `pos`, reading along `strand`. On a circular
+/// sequence the frame may run through the origin, so the position can fall outside the sequence; on a linear one
+/// there's no stop if the frame runs off the end first.
+fn find_stop(seq: &Seq, pos: i64, strand: Strand) -> Option<i64> {
+    let len = seq.len();
+    let step = match strand {
+        Strand::Forward => 1,
+        Strand::Reverse => -1,
+    };
+    let base = |p: i64| -> Option<u8> {
+        if !seq.is_circular() && (p < 0 || p >= len) {
+            return None;
         }
+        let b = seq.seq[p.rem_euclid(len) as usize].to_ascii_uppercase();
+        return Some(match strand {
+            Strand::Forward => b,
+            Strand::Reverse => Base::from_u8_lossy(b).complement().to_u8(),
+        });
     };
+    // Going round a circular sequence more than once would only revisit the same frames
+    for codon in 0..=len / 3 {
+        let first = pos + codon * 3 * step;
+        let triplet = [base(first)?, base(first + step)?, base(first + 2 * step)?];
+        if STOP_CODONS.contains(&&triplet) {
+            return Some(first + 2 * step);
+        }
+    }
+    return None;
 }
 
-fn is_partial(l: &Location) -> bool {
-    return match *l {
-        Location::Range((_, Before(b)), (_, After(a))) => a || b,
-        _ => false,
-    };
+fn bin_score(score: f64) -> isize {
+    return min(max(-51, score.floor() as isize), 51);
 }
@@ -1,301 +1,193 @@
+use crate::error::{Error, FileKind};
+use crate::util::annotation::{describe_region, Annotations};
+use crate::util::dag::{self, Dag, End, Objective, Start};
+use crate::util::dag_io::{self, DagFormat, MarkedDag, END_KEY, START_KEY};
+use crate::util::fasta::{self, Alphabet};
+use crate::util::feature_io::{self, FeatureFormat};
+use crate::util::nucleotide::Base;
 use crate::util::read;
-use std::{
-    cell::RefCell,
-    cmp::max,
-    collections::{HashMap, HashSet, LinkedList},
-    io::Error,
-    rc::Rc,
-};
+use crate::util::segments::{maximal_segments, BaseScores, Segment};
+use crate::util::significance::KarlinAltschul;
+use std::{collections::HashMap, path::Path};
 
-const NODE_KEY: &str = "V";
-const START_KEY: &str = "START";
-const END_KEY: &str = "END";
-const BASE_KEYS: [char; 5] = ['A', 'C', 'G', 'T', 'N'];
+/// Log-odds of each base, in ACGT order, of coming from a GC-rich segment rather than the rest of the genome
+const GC_RICH_SCORES: [f64; 4] = [-1.49, 0.74, 0.74, -1.49];
+/// How many of the highest-scoring GC-rich segments to list and write
+const TOP_SEGMENTS: usize = 10;
+/// How many of the best parses between the marked nodes to list
+const K_BEST: usize = 3;
 
-#[derive(Eq, Hash, PartialEq, Debug, Clone, Copy)]
-enum Base {
-    A,
-    C,
-    G,
-    T,
-    N,
-}
-
-impl Base {
-    fn from_char(b: char) -> Base {
-        match b {
-            'A' => Base::A,
-            'C' => Base::C,
-            'G' => Base::G,
-            'T' => Base::T,
-            _ => Base::N,
-        }
-    }
-}
-
-#[derive(Debug)]
-struct WeightedDirectedAcyclicGraph {
-    nodes: HashMap<String, Rc<RefCell<Node>>>,
-}
+pub fn run(file_path1: &str, file_path2: &str, annotation_path: Option<&str>) -> Result<(), Error> {
+    let marked = load_dag(file_path1)?;
+    let dag = &marked.dag;
 
-impl WeightedDirectedAcyclicGraph {
-    fn new(nodes: HashMap<String, Rc<RefCell<Node>>>) -> Self {
-        return WeightedDirectedAcyclicGraph { nodes };
-    }
+    println!("Part 1");
+    print_best_path(dag)?;
 
-    fn get_path_scores(&self, min_score: isize, constraints: HashSet<String>) -> HashMap<String, TraceScore> {
-        let mut scores: HashMap<String, TraceScore> = HashMap::new();
-        let mut queue = LinkedList::new();
+    println!("\nPart 2");
+    let missing = |key: &str| Error::invalid(FileKind::Dag, file_path1, format!("no node is marked {}", key));
+    let (start, end) = (marked.start.ok_or_else(|| missing(START_KEY))?, marked.end.ok_or_else(|| missing(END_KEY))?);
+    let best = dag.best_paths(Objective::Longest, Start::Node(start))?;
+    let path = best.path_to(end).ok_or_else(|| {
+        let message = format!(
+            "{} node '{}' can't be reached from {} node '{}'",
+            END_KEY,
+            dag.name(end),
+            START_KEY,
+            dag.name(start)
+        );
+        Error::invalid(FileKind::Dag, file_path1, message)
+    })?;
+    print_path(dag, &path);
+    print_alternatives(dag, Start::Node(start), End::Node(end))?;
+
+    let dot_path = Path::new(file_path1).with_file_name("dag.dot");
+    dag_io::write_dot_to_path(&dot_path, &marked, Some(&path)).map_err(|e| Error::io(&dot_path, e))?;
+    println!("Graph written to {}", dot_path.display());
 
-        self.nodes
-            .iter()
-            .filter(|(n, _)| constraints.is_empty() || constraints.contains(*n))
-            .for_each(|(name, node)| {
-                if node.borrow_mut().parents.is_empty() {
-                    scores.insert(
-                        name.to_string(),
-                        TraceScore {
-                            score: 0,
-                            node_name: name.to_string(),
-                            edge_name: "".to_string(),
-                            parent: None,
-                        },
-                    );
-                    queue.push_back(Rc::clone(&node));
-                }
-            });
+    println!("\nPart 3");
+    score_genome(file_path2, annotation_path)?;
 
-        while queue.len() > 0 {
-            let node = queue.pop_front().unwrap();
-            let node_mut = node.borrow_mut();
-            for (child_name, edge) in &node_mut.children {
-                queue.push_back(Rc::clone(&edge.to));
-                let parent = scores.get(&node_mut.name).unwrap();
-                let score = max(min_score, edge.weight + parent.score);
-                if let Some(current_weight) = scores.get(child_name) {
-                    if current_weight.score >= score {
-                        continue;
-                    }
-                }
-                scores.insert(
-                    child_name.to_string(),
-                    TraceScore {
-                        score,
-                        node_name: child_name.to_string(),
-                        edge_name: edge.name.to_string(),
-                        parent: Some(Box::from(parent.clone())),
-                    },
-                );
-            }
-        }
+    Ok(())
+}
 
-        return scores;
+/// Prints the highest-scoring path starting anywhere in the graph.
+fn print_best_path(dag: &Dag) -> Result<(), Error> {
+    let best = dag.best_paths(Objective::Longest, Start::Anywhere)?;
+    if let Some(path) = best.best_end().and_then(|end| best.path_to(end)) {
+        print_path(dag, &path);
     }
+    print_alternatives(dag, Start::Anywhere, End::Anywhere)
+}
 
-    fn print_best_path(&self) {
-        let scores = self.get_path_scores(0, HashSet::new());
-        let max_score = scores.values().max_by_key(|t| t.score).unwrap();
-        let (begin, path) = max_score.trace_back(0, None);
-
-        println!("Score: {}", max_score.score);
-        println!("Begin: {}", begin);
-        println!("End: {}", max_score.node_name);
-        println!("Path: {}", path);
+/// Prints how many paths tie for the best score, and the best few paths whether they tie or not.
+fn print_alternatives(dag: &Dag, start: Start, end: End) -> Result<(), Error> {
+    if let Some((_, count)) = dag.count_best_paths(Objective::Longest, start, end)? {
+        println!("Optimal Paths: {}", count);
     }
-
-    fn print_best_path_nodes(&self, start: String, end: String) {
-        let constraints = HashSet::from([start.clone()]);
-        let scores = self.get_path_scores(isize::MIN, constraints);
-        let end_score = scores.get(&end).unwrap();
-        let (begin, path) = end_score.trace_back(isize::MIN, Some(start));
-
-        println!("Score: {}", end_score.score);
-        println!("Begin: {}", begin);
-        println!("End: {}", end_score.node_name);
-        println!("Path: {}", path);
+    println!("Best {} Paths:", K_BEST);
+    for path in dag.k_best_paths(Objective::Longest, start, end, K_BEST)? {
+        let nodes: Vec<&str> = path.nodes.iter().map(|node| dag.name(*node)).collect();
+        println!("{} {} ({})", path.score, dag.path_labels(&path, ""), nodes.join(" "));
     }
+    Ok(())
 }
 
-#[derive(Debug, Clone)]
-struct TraceScore {
-    score: isize,
-    node_name: String,
-    edge_name: String,
-    parent: Option<Box<TraceScore>>,
+fn print_path(dag: &Dag, path: &dag::Path) {
+    println!("Score: {}", path.score);
+    println!("Begin: {}", dag.name(path.first()));
+    println!("End: {}", dag.name(path.last()));
+    println!("Path: {}", dag.path_labels(path, ""));
 }
 
-impl TraceScore {
-    fn trace_back(&self, min_score: isize, stop_node: Option<String>) -> (String, String) {
-        return self.trace_back_inner(min_score, stop_node, "".to_string());
-    }
+fn load_dag(file_path: &str) -> Result<MarkedDag, Error> {
+    let marked = dag_io::read_dag(file_path, DagFormat::Dag).map_err(|e| Error::in_file(FileKind::Dag, file_path, e))?;
+    // Checked here so that a cycle is blamed on the file rather than on whichever part first orders the graph
+    marked
+        .dag
+        .topological_order()
+        .map_err(|e| Error::invalid(FileKind::Dag, file_path, e.to_string()))?;
+    Ok(marked)
+}
 
-    fn trace_back_inner(&self, min_score: isize, stop_node: Option<String>, current_path: String) -> (String, String) {
-        if let Some(parent) = &self.parent {
-            let should_stop = stop_node.clone().map(|n| n == self.node_name).unwrap_or_default();
-            if parent.score > min_score && !should_stop {
-                return parent.trace_back_inner(min_score, stop_node, format!("{}{}", self.edge_name, current_path));
-            } else {
-                return (parent.node_name.clone(), format!("{}{}", self.edge_name, current_path));
-            }
-        } else {
-            return (self.node_name.clone(), current_path);
+fn score_genome(file_path: &str, annotation_path: Option<&str>) -> Result<(), Error> {
+    let base_scores = BaseScores::new(GC_RICH_SCORES);
+    let mut headers = Vec::new();
+    let mut base_counts: HashMap<Base, usize> = HashMap::new();
+    let mut non_alpha_count: usize = 0;
+    let mut records = Vec::new();
+    // Maximal segments of every record, with the index of the record they're on
+    let mut segments: Vec<(usize, Segment)> = Vec::new();
+
+    let fasta_error = |e| Error::in_file(FileKind::Fasta, file_path, e);
+    let reader = fasta::Reader::from_path(file_path)
+        .map_err(fasta_error)?
+        .alphabet(Alphabet::DNA_N)
+        .skip_invalid()
+        .uppercase();
+    for record in reader {
+        let record = record.map_err(fasta_error)?;
+        for c in record.seq.chars() {
+            *base_counts.entry(Base::from_char_lossy(c)).or_default() += 1;
         }
+        let scores = base_scores.scores(record.seq.as_bytes());
+        segments.extend(maximal_segments(&scores).into_iter().map(|segment| (records.len(), segment)));
+        non_alpha_count += record.invalid_count;
+        headers.push(record.header());
+        records.push((record.id, record.seq));
     }
-}
-
-#[derive(Debug)]
-struct Node {
-    name: String,
-    parents: HashMap<String, Edge>,
-    children: HashMap<String, Edge>,
-}
+    // Stable, so that the earliest of equally good segments comes first
+    segments.sort_by(|(_, a), (_, b)| b.score.total_cmp(&a.score));
 
-impl Node {
-    fn new(name: String) -> Self {
-        return Node {
-            name,
-            parents: HashMap::new(),
-            children: HashMap::new(),
-        };
+    println!("Fasta: {}", read::file_name_from_path(file_path));
+    println!("Non-alphabetic characters: {}", non_alpha_count);
+    for header in headers {
+        println!("{}", header);
     }
-
-    fn add_parent(&mut self, name: String, edge: Edge) {
-        self.parents.insert(name, edge);
+    println!("*={}", base_counts.iter().fold(0, |t, (_, b)| t + b));
+    for base in [Base::A, Base::C, Base::G, Base::T, Base::N] {
+        println!("{}={}", base, base_counts.get(&base).unwrap_or(&0))
     }
 
-    fn add_child(&mut self, name: String, edge: Edge) {
-        self.children.insert(name, edge);
+    let record_id = |i: usize| records.get(i).map_or("", |(id, _)| id.as_str());
+    let (best_record, best) = segments.first().copied().unwrap_or((0, Segment { start: 0, end: 0, score: 0.0 }));
+    println!("\nScore: {:.2}", best.score);
+    if records.len() > 1 {
+        println!("Record: {}", record_id(best_record));
     }
-}
-
-#[derive(Debug, Clone)]
-struct Edge {
-    name: String,
-    weight: isize,
-    to: Rc<RefCell<Node>>,
-}
-
-pub fn run(file_path1: &str, file_path2: &str) -> Result<(), Error> {
-    let (dag, start_node, end_node) = parse_dag(file_path1)?;
-
-    println!("Part 1");
-    dag.print_best_path();
-
-    println!("\nPart 2");
-    dag.print_best_path_nodes(start_node.unwrap(), end_node.unwrap());
-
-    println!("\nPart 3");
-    score_genome(file_path2)?;
-
-    Ok(())
-}
-
-fn parse_dag(file_path: &str) -> Result<(WeightedDirectedAcyclicGraph, Option<String>, Option<String>), Error> {
-    let mut start_node: Option<String> = None;
-    let mut end_node: Option<String> = None;
-    let mut nodes: HashMap<String, Rc<RefCell<Node>>> = HashMap::new();
-
-    let lines = read::lines(file_path)?;
-    for line in lines {
-        if let Ok(ip) = line {
-            let parts: Vec<&str> = ip.split_whitespace().collect();
-            let graph_type = parts.get(0).unwrap();
-            if *graph_type == NODE_KEY {
-                let name = parts.get(1).unwrap().to_string();
-                nodes.insert(name.clone(), Rc::from(RefCell::from(Node::new(name.clone()))));
-                if let Some(k) = parts.get(2) {
-                    match *k {
-                        START_KEY => start_node = Some(name),
-                        END_KEY => end_node = Some(name),
-                        _ => panic!("Uknown key type '{k}' encountered"),
-                    }
-                }
-            } else {
-                // Edge
-                let name = parts.get(1).unwrap().to_string();
-                let from = nodes.get(&parts.get(2).unwrap().to_string()).unwrap();
-                let to = nodes.get(&parts.get(3).unwrap().to_string()).unwrap();
-                let weight: isize = parts.get(4).unwrap().trim().parse().unwrap();
-
-                let edge = Edge {
-                    name,
-                    weight,
-                    to: Rc::clone(to),
-                };
-                let mut to_mut = to.borrow_mut();
-                let mut from_mut = from.borrow_mut();
-                to_mut.add_parent(from_mut.name.clone(), edge.clone());
-                from_mut.add_child(to_mut.name.clone(), edge.clone());
-            }
-        }
+    println!("Begin: {}", best.start);
+    println!("End: {}", best.end);
+    println!("Length: {}", best.len());
+    println!("Path: {}", records.get(best_record).map_or("", |(_, seq)| &seq[best.start..best.end]));
+    let annotations = Annotations::load_if_given(annotation_path).map_err(|e| Error::in_file(FileKind::Annotation, annotation_path.unwrap_or_default(), e))?;
+    for line in describe_region(annotations.as_ref(), record_id(best_record), best.start as i64, best.end as i64) {
+        println!("Description: {}", line);
     }
 
-    Ok((WeightedDirectedAcyclicGraph::new(nodes), start_node, end_node))
-}
-
-fn score_base(base: Base) -> f64 {
-    match base {
-        Base::A | Base::T => -1.49,
-        Base::C | Base::G => 0.74,
-        _ => 0.0,
+    println!("\nMaximal Segments: {}", segments.len());
+    // Chance segments are drawn from the genome's own base composition, and can turn up anywhere in it
+    let frequencies = Base::ACGT.map(|base| *base_counts.get(&base).unwrap_or(&0) as f64);
+    let search_space: f64 = frequencies.iter().sum();
+    let statistics = KarlinAltschul::from_scores(&GC_RICH_SCORES, &frequencies);
+    if let Some(statistics) = &statistics {
+        println!("Lambda: {:.4}", statistics.lambda);
+        println!("K: {:.4}", statistics.k);
     }
-}
-
-fn score_genome(file_path: &str) -> Result<(), Error> {
-    let mut sequence = String::with_capacity(read::file_size(file_path) as usize);
-    let mut header: String = String::from("NO HEADER");
-    let mut base_counts: HashMap<Base, usize> = HashMap::new();
-    let mut non_alpha_count: i32 = 0;
-    let mut i = 0;
-    let mut score: f64 = 0.0;
-    let mut high_score: f64 = 0.0;
-    let mut start = 0;
-    let mut best_start = 0;
-    let mut best_end = 0;
-
-    let lines = read::lines(file_path)?;
-    for line in lines {
-        if let Ok(ip) = line {
-            if ip.starts_with('>') {
-                header = ip;
-                continue;
-            }
-            for c in ip.to_uppercase().chars() {
-                if BASE_KEYS.contains(&c) {
-                    let base = Base::from_char(c);
-                    score += score_base(base);
-                    if score <= 0.0 {
-                        score = 0.0;
-                        start = i + 1
-                    } else if score > high_score {
-                        high_score = score;
-                        best_start = start;
-                        best_end = i + 1;
-                    }
-                    sequence.push(c);
-                    *base_counts.entry(base).or_default() += 1;
-                } else if c != ' ' {
-                    // Don't count spaces for some reason?
-                    non_alpha_count += 1;
-                }
-                i += 1;
-            }
-        }
+    println!("Score E-value P-value Record Begin End");
+    let top = &segments[..segments.len().min(TOP_SEGMENTS)];
+    for (record, segment) in top {
+        let (e_value, p_value) = match &statistics {
+            Some(statistics) => (statistics.e_value(segment.score, search_space), statistics.p_value(segment.score, search_space)),
+            None => (f64::NAN, f64::NAN),
+        };
+        println!(
+            "{:.2} {:.3e} {:.3e} {} {} {}",
+            segment.score,
+            e_value,
+            p_value,
+            record_id(*record),
+            segment.start,
+            segment.end
+        );
     }
 
-    println!("Fasta: {}", read::file_name_from_path(&file_path));
-    println!("Non-alphabetic characters: {}", non_alpha_count);
-    println!("{}", header);
-    println!("*={}", base_counts.iter().fold(0, |t, (_, b)| t + b));
-    for key in BASE_KEYS {
-        println!("{}={}", key, base_counts.get(&Base::from_char(key)).unwrap_or(&0))
+    if !top.is_empty() {
+        let bed_path = Path::new(file_path).with_file_name("gc_segment.bed");
+        let features: Vec<feature_io::Feature> = top
+            .iter()
+            .enumerate()
+            .map(|(rank, (record, segment))| {
+                let (start, end) = (segment.start as i64, segment.end as i64);
+                let mut feature = feature_io::Feature::new(record_id(*record), feature_io::BED_KIND, start, end, None);
+                // BED scores are whole numbers, so the name carries the real one
+                feature.name = Some(format!("high_gc_{}:{:.2}", rank + 1, segment.score));
+                feature.score = Some((segment.score * 100.0).round() / 100.0);
+                feature
+            })
+            .collect();
+        feature_io::write_features_to_path(&bed_path, &features, FeatureFormat::Bed).map_err(|e| Error::io(&bed_path, e))?;
+        println!("Segments written to {}", bed_path.display());
     }
 
-    println!("\nScore: {:.2}", high_score);
-    println!("Begin: {best_start}");
-    println!("End: {best_end}");
-    println!("Path: {}", sequence[best_start..best_end].to_string());
-    println!("Description: TODO");
-
     Ok(())
 }
@@ -1,34 +1,47 @@
-use itertools::{Itertools, Position};
+use itertools::Itertools;
 
-use crate::util::read;
-use std::{collections::HashMap, io::Error};
+use crate::error::{Error, FileKind};
+use crate::util::annotation::{describe_region, Annotations};
+use crate::util::feature_io::{self, Feature, FeatureFormat};
+use crate::util::read_starts::{self, ReadStartSegments, COUNT_CLASSES};
+use crate::util::significance::KarlinAltschul;
+use std::{collections::HashMap, path::Path};
 
 const D_SCORE: f64 = -20.0;
 const S_SCORE: f64 = -D_SCORE;
 
-pub fn run(file_path1: &str) -> Result<(), Error> {
+pub fn run(file_path1: &str, annotation_path: Option<&str>) -> Result<(), Error> {
     let rh = parse_sequence(file_path1)?;
+    let annotations = Annotations::load_if_given(annotation_path).map_err(|e| Error::in_file(FileKind::Annotation, annotation_path.unwrap_or_default(), e))?;
 
     rh.print_seg_list();
-    rh.print_annotations();
+    rh.print_annotations(annotations.as_ref());
     rh.print_non_elevated();
     rh.print_elevated();
 
+    let bed_path = Path::new(file_path1).with_file_name("elevated_segments.bed");
+    rh.write_elevated(&bed_path)?;
+    println!("\nSegments written to {}", bed_path.display());
+
     Ok(())
 }
 
 struct ReadHistogram {
+    chr: String,
     segs: Vec<(isize, isize, f64)>,
     non_elevated_copies: HashMap<isize, isize>,
     elevated_copies: HashMap<isize, isize>,
 }
 
 impl ReadHistogram {
-    fn new() -> Self {
+    fn from_segments(found: ReadStartSegments) -> Self {
+        let copies = |counts: [usize; COUNT_CLASSES]| (0..).zip(counts).map(|(class, count)| (class, count as isize)).collect();
         return ReadHistogram {
-            segs: Vec::new(),
-            non_elevated_copies: HashMap::new(),
-            elevated_copies: HashMap::new(),
+            chr: found.chr,
+            // Segments are kept 1-based and inclusive, as the positions are numbered
+            segs: found.segments.iter().map(|s| (s.start as isize, s.end as isize - 1, s.score)).collect(),
+            non_elevated_copies: copies(found.counts.outside_segments()),
+            elevated_copies: copies(found.counts.in_segments),
         };
     }
 
@@ -41,14 +54,47 @@ impl ReadHistogram {
         println!("Elevated CN Segments={elevated}");
 
         println!("\nSegment List:");
+        let statistics = self.significance();
+        let search_space = self.position_count() as f64;
+        if let Some(statistics) = &statistics {
+            println!("Lambda={:.4}", statistics.lambda);
+            println!("K={:.4}", statistics.k);
+        }
         for (p_start, p_end, s) in self.segs.iter() {
             if *s >= S_SCORE {
-                println!("{p_start} {p_end} {s:.2}");
+                match &statistics {
+                    Some(statistics) => {
+                        let (e_value, p_value) = (statistics.e_value(*s, search_space), statistics.p_value(*s, search_space));
+                        println!("{p_start} {p_end} {s:.2} E={e_value:.3e} P={p_value:.3e}");
+                    }
+                    None => println!("{p_start} {p_end} {s:.2}"),
+                }
             }
         }
     }
 
-    fn print_annotations(&self) {
+    /// Number of positions with each read start count across the whole chromosome.
+    fn count_totals(&self) -> [isize; 4] {
+        let mut totals = [0; 4];
+        for (bucket, total) in totals.iter_mut().enumerate() {
+            let bucket = bucket as isize;
+            *total = self.elevated_copies.get(&bucket).unwrap_or(&0) + self.non_elevated_copies.get(&bucket).unwrap_or(&0);
+        }
+        return totals;
+    }
+
+    fn position_count(&self) -> isize {
+        return self.count_totals().iter().sum();
+    }
+
+    /// How likely segments are to score as well as they do by chance, taking read start counts at the chromosome's
+    /// overall rates as the background.
+    fn significance(&self) -> Option<KarlinAltschul> {
+        let scores = [0, 1, 2, 3].map(get_read_score);
+        return KarlinAltschul::from_scores(&scores, &self.count_totals().map(|t| t as f64));
+    }
+
+    fn print_annotations(&self, annotations: Option<&Annotations>) {
         println!("\nAnnotations:");
         self.segs
             .iter()
@@ -57,8 +103,28 @@ impl ReadHistogram {
             .for_each(|(start, end, _)| {
                 println!("\nStart: {start}");
                 println!("End: {end}");
-                println!("Description: TODO");
+                // Segment ends are 1-based and inclusive, which is the same as a 0-based exclusive end
+                for line in describe_region(annotations, &self.chr, *start as i64 - 1, *end as i64) {
+                    println!("Description: {}", line);
+                }
+            })
+    }
+
+    /// Writes the elevated segments as BED, scored like the segment list.
+    fn write_elevated(&self, file_path: &Path) -> Result<(), Error> {
+        let segments: Vec<Feature> = self
+            .segs
+            .iter()
+            .filter(|(_, _, s)| *s >= S_SCORE)
+            .map(|(start, end, s)| {
+                let mut segment = Feature::new(&self.chr, feature_io::BED_KIND, *start as i64 - 1, *end as i64, None);
+                // BED scores are whole numbers, so the name carries the real one
+                segment.name = Some(format!("{:.2}", s));
+                segment.score = Some(*s);
+                segment
             })
+            .collect();
+        return feature_io::write_features_to_path(file_path, &segments, FeatureFormat::Bed).map_err(|e| Error::io(file_path, e));
     }
 
     fn print_non_elevated(&self) {
@@ -95,78 +161,7 @@ fn get_read_score(reads: isize) -> f64 {
 }
 
 fn parse_sequence(file_path: &str) -> Result<ReadHistogram, Error> {
-    let mut rh = ReadHistogram::new();
-    let mut cum: f64 = 0.0;
-    let mut max: f64 = 0.0;
-    let mut start: isize = 1;
-    let mut end: isize = 1;
-
-    let (mut c_0, mut c_1, mut c_2, mut c_3) = (0, 0, 0, 0);
-    let (mut t_0, mut t_1, mut t_2, mut t_3) = (0, 0, 0, 0);
-    let (mut m_0, mut m_1, mut m_2, mut m_3) = (0, 0, 0, 0);
-
-    let lines = read::lines(file_path)?;
-    for line in lines.enumerate().with_position() {
-        let (is_last, line) = match line {
-            Position::Middle((_, res)) => (false, res),
-            Position::Last((_, res)) => (true, res),
-            Position::First((_, res)) => (false, res),
-            Position::Only((_, res)) => (true, res),
-        };
-        if let Ok(ip) = line {
-            let mut iter = ip.split_whitespace();
-            let _chr = iter.next().unwrap();
-            let pos: isize = iter.next().unwrap().parse().unwrap();
-            let cnt: isize = iter.next().unwrap().parse().unwrap();
-
-            match cnt {
-                0 => {
-                    c_0 += 1;
-                    t_0 += 1;
-                }
-                1 => {
-                    c_1 += 1;
-                    t_1 += 1;
-                }
-                2 => {
-                    c_2 += 1;
-                    t_2 += 1;
-                }
-                _ => {
-                    // >=3
-                    c_3 += 1;
-                    t_3 += 1;
-                }
-            }
-
-            cum += get_read_score(cnt);
-            if cum >= max {
-                max = cum;
-                end = pos;
-                (m_0, m_1, m_2, m_3) = (c_0, c_1, c_2, c_3)
-            }
-
-            if cum <= 0.0 || cum <= max + D_SCORE || is_last {
-                if max >= S_SCORE {
-                    rh.segs.push((start, end, max));
-                    *rh.elevated_copies.entry(0).or_default() += m_0;
-                    *rh.elevated_copies.entry(1).or_default() += m_1;
-                    *rh.elevated_copies.entry(2).or_default() += m_2;
-                    *rh.elevated_copies.entry(3).or_default() += m_3;
-                }
-                (c_0, c_1, c_2, c_3) = (0, 0, 0, 0);
-                max = 0.0;
-                cum = 0.0;
-                start = pos + 1;
-                end = pos + 1;
-            }
-        }
-    }
-
-    *rh.non_elevated_copies.entry(0).or_default() += t_0 - rh.elevated_copies[&0];
-    *rh.non_elevated_copies.entry(1).or_default() += t_1 - rh.elevated_copies[&1];
-    *rh.non_elevated_copies.entry(2).or_default() += t_2 - rh.elevated_copies[&2];
-    *rh.non_elevated_copies.entry(3).or_default() += t_3 - rh.elevated_copies[&3];
-
-    Ok(rh)
+    let scores = [0, 1, 2, 3].map(get_read_score);
+    let found = read_starts::segment_read_starts(file_path, &scores, -D_SCORE, S_SCORE).map_err(|e| Error::in_file(FileKind::Counts, file_path, e))?;
+    Ok(ReadHistogram::from_segments(found))
 }
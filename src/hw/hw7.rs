@@ -1,15 +1,28 @@
-use crate::util::read;
-use itertools::{Itertools, Position};
+use crate::error::{Error, FileKind};
+use crate::util::read_starts::{self, ReadStartSegments, COUNT_CLASSES};
+use crate::util::segments::{DropOffSegmenter, Segment, Step};
+use crate::util::significance::KarlinAltschul;
+use itertools::Itertools;
 use rand::Rng;
-use std::{collections::HashMap, io::Error};
+use std::collections::HashMap;
 
 const D_SCORE_1: f64 = -20.0;
 const D_SCORE_2: f64 = -5.0;
 const BACKGROUND_N: f64 = 8_422_401.0;
+/// Real segments expected fewer than this many times by chance are listed as significant
+const SIGNIFICANT_E_VALUE: f64 = 0.01;
 
-pub fn run(file_path: &str) -> Result<(), Error> {
+pub fn run<R: Rng>(file_path: &str, rng: &mut R) -> Result<(), Error> {
     let default_scores = HashMap::from([(0, -0.1077), (1, 0.47720), (2, 1.0622), (3, 1.6748)]);
     let rh = parse_sequence(file_path, D_SCORE_1, &default_scores)?;
+    if rh.segs.is_empty() {
+        // The scoring scheme is fitted to the read starts in elevated segments, so there must be some
+        return Err(Error::invalid(
+            FileKind::Counts,
+            file_path,
+            "no elevated segments to fit a scoring scheme to".to_string(),
+        ));
+    }
 
     rh.print_background_freqs();
     rh.print_elevated_freqs();
@@ -19,12 +32,15 @@ pub fn run(file_path: &str) -> Result<(), Error> {
     println!("\nReal data:");
     rh_custom.print_score_histogram();
 
-    let rh_simulated = rh.simulate_new(D_SCORE_2);
+    let rh_simulated = rh.simulate_new(D_SCORE_2, rng);
     println!("\nSimulated data:");
     rh_simulated.print_score_histogram();
     println!("\nRatios of simulated data:");
     rh_simulated.print_score_ratios();
 
+    println!("\nKarlin-Altschul statistics:");
+    rh.print_significance(&rh_custom, &rh_simulated);
+
     Ok(())
 }
 
@@ -43,16 +59,25 @@ impl ReadHistogram {
         };
     }
 
-    fn simulate_new(&self, d_score: f64) -> Self {
+    fn from_segments(found: ReadStartSegments) -> Self {
+        let copies = |counts: [usize; COUNT_CLASSES]| (0..).zip(counts).map(|(class, count)| (class, count as isize)).collect();
+        let mut rh = ReadHistogram {
+            segs: Vec::new(),
+            non_elevated_copies: copies(found.counts.outside_segments()),
+            elevated_copies: copies(found.counts.in_segments),
+        };
+        for segment in found.segments {
+            rh.push_segment(segment);
+        }
+        return rh;
+    }
+
+    fn simulate_new<R: Rng>(&self, d_score: f64, rng: &mut R) -> Self {
         let background_freqs = self.get_background_freq();
         let scoring_scheme = self.get_scoring_scheme();
         let total = self.get_total_background() as isize;
         let mut rh = ReadHistogram::new();
-        let mut cum: f64 = 0.0;
-        let mut max: f64 = 0.0;
-        let mut start: isize = 1;
-        let mut end: isize = 1;
-        let mut rng = rand::thread_rng();
+        let mut segmenter = DropOffSegmenter::new(-d_score, -d_score);
 
         for i in 0..total {
             let rnd = rng.gen::<f64>();
@@ -66,26 +91,23 @@ impl ReadHistogram {
                 3
             };
 
-            cum += get_read_score(cnt, &scoring_scheme);
-            if cum >= max {
-                max = cum;
-                end = i;
-            }
-
-            if cum <= 0.0 || cum <= max + d_score || i == total - 1 {
-                if max >= -d_score {
-                    rh.segs.push((start, end, max));
-                }
-                max = 0.0;
-                cum = 0.0;
-                start = i + 1;
-                end = i + 1;
+            // Positions are numbered from 1, as in the real data
+            if let Step::Closed(Some(segment)) = segmenter.push(i as usize + 1, get_read_score(cnt, &scoring_scheme)) {
+                rh.push_segment(segment);
             }
         }
+        if let Some(segment) = segmenter.finish() {
+            rh.push_segment(segment);
+        }
 
         return rh;
     }
 
+    fn push_segment(&mut self, segment: Segment) {
+        // Segments are kept 1-based and inclusive, as the positions are numbered
+        self.segs.push((segment.start as isize, segment.end as isize - 1, segment.score));
+    }
+
     fn get_total_background(&self) -> f64 {
         let total_elevated = self.elevated_copies.values().fold(0.0, |t, v| t + *v as f64);
         let total_non_elevated = self.non_elevated_copies.values().fold(0.0, |t, v| t + *v as f64);
@@ -165,6 +187,48 @@ impl ReadHistogram {
         }
     }
 
+    /// Compares how many segments of each score turned up in the real and simulated data with how many Karlin-Altschul
+    /// statistics expect by chance, for the scoring scheme fitted to this data. The simulation draws from the same
+    /// background, so it should agree with the expected counts.
+    fn print_significance(&self, real: &ReadHistogram, simulated: &ReadHistogram) {
+        let background_freqs = self.get_background_freq();
+        let distribution: Vec<(f64, f64)> = self.get_scoring_scheme().iter().map(|(cnt, score)| (*score, background_freqs[cnt])).collect();
+        let Some(statistics) = KarlinAltschul::from_distribution(&distribution) else {
+            println!("The scoring scheme doesn't score the background negatively, so segments can't be assessed");
+            return;
+        };
+        let search_space = self.get_total_background();
+        println!("Lambda={:.4}", statistics.lambda);
+        println!("K={:.4}", statistics.k);
+        println!("H={:.4}", statistics.h);
+        println!("Expected N_seg(i-1)/N_seg(i) {:.2}", statistics.lambda.exp());
+
+        println!("\nScore Real Simulated Expected P-value");
+        for i in 5..31 {
+            let score = i as f64;
+            println!(
+                "{i} {} {} {:.2} {:.3e}",
+                real.count_at_least(score),
+                simulated.count_at_least(score),
+                statistics.e_value(score, search_space),
+                statistics.p_value(score, search_space)
+            );
+        }
+
+        println!("\nReal segments with E-value < {}:", SIGNIFICANT_E_VALUE);
+        for (start, end, score) in real.segs.iter() {
+            let e_value = statistics.e_value(*score, search_space);
+            if e_value < SIGNIFICANT_E_VALUE {
+                let p_value = statistics.p_value(*score, search_space);
+                println!("{start} {end} {score:.2} E={e_value:.3e} P={p_value:.3e}");
+            }
+        }
+    }
+
+    fn count_at_least(&self, score: f64) -> usize {
+        return self.segs.iter().filter(|(_, _, s)| *s >= score).count();
+    }
+
     fn print_score_ratios(&self) {
         let mut prev_count = -1.0;
         for i in 5..31 {
@@ -184,78 +248,7 @@ fn get_read_score(reads: isize, scoring_scheme: &HashMap<isize, f64>) -> f64 {
 }
 
 fn parse_sequence(file_path: &str, d_score: f64, scoring_scheme: &HashMap<isize, f64>) -> Result<ReadHistogram, Error> {
-    let mut rh = ReadHistogram::new();
-    let mut cum: f64 = 0.0;
-    let mut max: f64 = 0.0;
-    let mut start: isize = 1;
-    let mut end: isize = 1;
-
-    let (mut c_0, mut c_1, mut c_2, mut c_3) = (0, 0, 0, 0);
-    let (mut t_0, mut t_1, mut t_2, mut t_3) = (0, 0, 0, 0);
-    let (mut m_0, mut m_1, mut m_2, mut m_3) = (0, 0, 0, 0);
-
-    let lines = read::lines(file_path)?;
-    for line in lines.enumerate().with_position() {
-        let (is_last, line) = match line {
-            Position::Middle((_, res)) => (false, res),
-            Position::Last((_, res)) => (true, res),
-            Position::First((_, res)) => (false, res),
-            Position::Only((_, res)) => (true, res),
-        };
-        if let Ok(ip) = line {
-            let mut iter = ip.split_whitespace();
-            let _chr = iter.next().unwrap();
-            let pos: isize = iter.next().unwrap().parse().unwrap();
-            let cnt: isize = iter.next().unwrap().parse().unwrap();
-
-            match cnt {
-                0 => {
-                    c_0 += 1;
-                    t_0 += 1;
-                }
-                1 => {
-                    c_1 += 1;
-                    t_1 += 1;
-                }
-                2 => {
-                    c_2 += 1;
-                    t_2 += 1;
-                }
-                _ => {
-                    // >=3
-                    c_3 += 1;
-                    t_3 += 1;
-                }
-            }
-
-            cum += get_read_score(cnt, scoring_scheme);
-            if cum >= max {
-                max = cum;
-                end = pos;
-                (m_0, m_1, m_2, m_3) = (c_0, c_1, c_2, c_3)
-            }
-
-            if cum <= 0.0 || cum <= max + d_score || is_last {
-                if max >= -d_score {
-                    rh.segs.push((start, end, max));
-                    *rh.elevated_copies.entry(0).or_default() += m_0;
-                    *rh.elevated_copies.entry(1).or_default() += m_1;
-                    *rh.elevated_copies.entry(2).or_default() += m_2;
-                    *rh.elevated_copies.entry(3).or_default() += m_3;
-                }
-                (c_0, c_1, c_2, c_3) = (0, 0, 0, 0);
-                max = 0.0;
-                cum = 0.0;
-                start = pos + 1;
-                end = pos + 1;
-            }
-        }
-    }
-
-    *rh.non_elevated_copies.entry(0).or_default() += t_0 - rh.elevated_copies[&0];
-    *rh.non_elevated_copies.entry(1).or_default() += t_1 - rh.elevated_copies[&1];
-    *rh.non_elevated_copies.entry(2).or_default() += t_2 - rh.elevated_copies[&2];
-    *rh.non_elevated_copies.entry(3).or_default() += t_3 - rh.elevated_copies[&3];
-
-    Ok(rh)
+    let scores = [0, 1, 2, 3].map(|reads| get_read_score(reads, scoring_scheme));
+    let found = read_starts::segment_read_starts(file_path, &scores, -d_score, -d_score).map_err(|e| Error::in_file(FileKind::Counts, file_path, e))?;
+    Ok(ReadHistogram::from_segments(found))
 }
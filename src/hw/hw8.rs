@@ -1,9 +1,79 @@
 use itertools::Itertools;
 
+use crate::util::fasta::{self, Alphabet};
+use crate::util::nucleotide::Base;
 use crate::util::read;
+use crate::util::simulate::{simulate_record, Hmm};
+use rand::Rng;
+use std::path::Path;
 use std::{collections::HashMap, io::Error};
 
-pub fn run(file_path: &str) -> Result<(), Error> {
+/// Bases simulated from the trained model, which is then trained on them in turn to see how well it's recovered
+const SIMULATED_LENGTH: usize = 100_000;
+
+/// Parameters of the two-state model, as natural logs, and how training on a sequence went.
+struct TrainedModel {
+    iterations: usize,
+    log_likelihood: f64,
+    start_probs: [f64; 2],
+    transition_probs: [[f64; 2]; 2],
+    emission_probs: [HashMap<char, f64>; 2],
+}
+
+impl TrainedModel {
+    /// The model as a simulator, with plain probabilities and emissions in ACGT order.
+    fn to_hmm(&self) -> Hmm {
+        return Hmm::new(
+            self.start_probs.iter().map(|p| p.exp()).collect(),
+            self.transition_probs.iter().map(|t| t.iter().map(|p| p.exp()).collect()).collect(),
+            self.emission_probs.iter().map(|e| Base::ACGT.map(|b| e[&b.to_char()].exp())).collect(),
+        );
+    }
+
+    fn print(&self) {
+        let states = self.start_probs.len();
+        println!("\nIterations for Convergence:\n{}", self.iterations);
+
+        println!("\nLog Likelihood:\n{:.3}", self.log_likelihood);
+
+        println!("\nInitial State Probabilities:");
+        (0..states).for_each(|i| println!("{}={:.3e}", i + 1, self.start_probs[i].exp()));
+
+        println!("\nTransition Probabilities:");
+        (0..states).for_each(|i| (0..states).for_each(|j| println!("{},{}={:.3e}", i + 1, j + 1, self.transition_probs[i][j].exp())));
+
+        println!("\nEmission Probabilities:");
+        (0..states).for_each(|i| {
+            self.emission_probs[i]
+                .iter()
+                .sorted_by_key(|(c, _)| *c)
+                .for_each(|(c, p)| println!("{},{c}={:.3e}", i + 1, p.exp()))
+        });
+    }
+}
+
+pub fn run<R: Rng>(file_path: &str, line_width: usize, rng: &mut R) -> Result<(), Error> {
+    let sequence = load_fasta(file_path)?;
+    let model = train(&sequence);
+    model.print();
+
+    println!();
+    let simulated_path = Path::new(file_path).with_file_name("simulated_hmm.fa");
+    let mut hmm = model.to_hmm();
+    let mut writer = fasta::Writer::from_path(&simulated_path)?.line_width(line_width);
+    simulate_record(&mut writer, "simulated_hmm", "", SIMULATED_LENGTH, &mut hmm, rng)?;
+    writer.finish()?;
+    let in_state_2 = hmm.states().iter().filter(|s| **s == 1).count();
+    println!("Simulated State 2 Fraction: {:.4}", in_state_2 as f64 / SIMULATED_LENGTH as f64);
+
+    let simulated = load_fasta(simulated_path.to_str().unwrap())?;
+    train(&simulated).print();
+
+    Ok(())
+}
+
+/// Fits the two-state model to a sequence by Baum-Welch, starting from an AT-rich and a GC-rich state.
+fn train(sequence: &str) -> TrainedModel {
     let states = 2;
     let mut start_probs = [0.996_f64.ln(), 0.004_f64.ln()];
     let mut transition_probs = [[0.999_f64.ln(), 0.001_f64.ln()], [0.01_f64.ln(), 0.99_f64.ln()]];
@@ -11,7 +81,6 @@ pub fn run(file_path: &str) -> Result<(), Error> {
     let emissions_2 = HashMap::from([('A', 0.15_f64.ln()), ('T', 0.15_f64.ln()), ('G', 0.35_f64.ln()), ('C', 0.35_f64.ln())]);
     let mut emission_probs = [emissions_1, emissions_2];
 
-    let sequence = load_fasta(file_path)?;
     let n = sequence.len();
 
     let mut iterations = 0;
@@ -22,10 +91,10 @@ pub fn run(file_path: &str) -> Result<(), Error> {
         prev_ll = current_ll;
 
         // Compute forward and backward probabilities
-        let forward = compute_forward_scores(&sequence, &emission_probs, &start_probs, &transition_probs);
+        let forward = compute_forward_scores(sequence, &emission_probs, &start_probs, &transition_probs);
         let last = forward.last().unwrap();
         current_ll = sum_log_prob(last[0], last[1]);
-        let backward = compute_backward_scores(&sequence, &emission_probs, &transition_probs);
+        let backward = compute_backward_scores(sequence, &emission_probs, &transition_probs);
 
         // New start probs
         for s_i in 0..states {
@@ -87,41 +156,23 @@ pub fn run(file_path: &str) -> Result<(), Error> {
         }
     }
 
-    println!("\nIterations for Convergence:\n{iterations}");
-
-    println!("\nLog Likelihood:\n{:.3}", current_ll);
-
-    println!("\nInitial State Probabilities:");
-    (0..states).for_each(|i| println!("{}={:.3e}", i + 1, start_probs[i].exp()));
-
-    println!("\nTransition Probabilities:");
-    (0..states).for_each(|i| (0..states).for_each(|j| println!("{},{}={:.3e}", i + 1, j + 1, transition_probs[i][j].exp())));
-
-    println!("\nEmission Probabilities:");
-    (0..states).for_each(|i| {
-        emission_probs[i]
-            .iter()
-            .sorted_by_key(|(c, _)| *c)
-            .for_each(|(c, p)| println!("{},{c}={:.3e}", i + 1, p.exp()))
-    });
-
-    Ok(())
+    return TrainedModel {
+        iterations,
+        log_likelihood: current_ll,
+        start_probs,
+        transition_probs,
+        emission_probs,
+    };
 }
 
 fn load_fasta(file_path: &str) -> Result<String, Error> {
     let mut sequence = String::with_capacity(read::file_size(file_path) as usize);
-    let lines = read::lines(file_path)?;
-    for line in lines {
-        if let Ok(ip) = line {
-            if ip.starts_with('>') {
-                println!("Fasta: {}", read::file_name_from_path(&file_path));
-                println!("{}", ip);
-                continue;
-            }
-            for c in ip.to_uppercase().chars() {
-                sequence.push(c);
-            }
-        }
+    println!("Fasta: {}", read::file_name_from_path(file_path));
+    // Contigs are concatenated, so the model treats a multi-record file as one long sequence.
+    for record in fasta::Reader::from_path(file_path)?.alphabet(Alphabet::DNA).uppercase() {
+        let record = record?;
+        println!("{}", record.header());
+        sequence.push_str(&record.seq);
     }
 
     Ok(sequence)
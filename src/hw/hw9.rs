@@ -1,19 +1,19 @@
 use itertools::Itertools;
 
+use crate::error::{Error, FileKind};
+use crate::util::annotation::{describe_region, Annotations};
+use crate::util::feature_io::{self, Feature, FeatureFormat};
 use crate::util::read;
-use std::{collections::HashMap, io::Error};
+use std::{collections::HashMap, path::Path};
 
-const NEUTRAL_FREQ_FILE: &str = "/Users/tneely/dev/bio-rs/data/hw/hw9/STATE1_anc_rep_counts.txt";
-const CONSERVED_FREQ_FILE: &str = "/Users/tneely/dev/bio-rs/data/hw/hw9/STATE2_codon1_2_counts.txt";
-
-pub fn run(file_path: &str) -> Result<(), Error> {
-    let neutral_state = load_freqs(NEUTRAL_FREQ_FILE)?;
-    let conserved_state = load_freqs(CONSERVED_FREQ_FILE)?;
+pub fn run(file_path: &str, neutral_freq_path: &str, conserved_freq_path: &str, annotation_path: Option<&str>) -> Result<(), Error> {
+    let neutral_state = load_freqs(neutral_freq_path)?;
+    let conserved_state = load_freqs(conserved_freq_path)?;
     let states = [&neutral_state, &conserved_state];
     let start_probs = [0.95_f64.ln(), 0.05_f64.ln()];
     let transition_probs = [[0.95_f64.ln(), 0.05_f64.ln()], [0.10_f64.ln(), 0.90_f64.ln()]];
 
-    let (aln, start, end) = load_alignment(file_path)?;
+    let (aln, chr, start, end) = load_alignment(file_path)?;
 
     // Compute scores
     let mut score_tracker = Vec::from([vec![0.0; aln.len()], vec![0.0; aln.len()]]);
@@ -21,12 +21,13 @@ pub fn run(file_path: &str) -> Result<(), Error> {
     for (i, em) in aln.iter().enumerate() {
         if i == 0 {
             for s in 0..states.len() {
-                score_tracker[s][i] = start_probs[s] + states[s].get_prob(em)
+                score_tracker[s][i] = start_probs[s] + states[s].get_prob(em)?
             }
         } else {
             for s in 0..states.len() {
-                let p0 = score_tracker[0][i - 1] + transition_probs[0][s] + states[s].get_prob(em);
-                let p1 = score_tracker[1][i - 1] + transition_probs[1][s] + states[s].get_prob(em);
+                let emission_prob = states[s].get_prob(em)?;
+                let p0 = score_tracker[0][i - 1] + transition_probs[0][s] + emission_prob;
+                let p1 = score_tracker[1][i - 1] + transition_probs[1][s] + emission_prob;
                 if p0 > p1 {
                     score_tracker[s][i] = p0;
                     state_tracker[s][i] = 0;
@@ -83,27 +84,52 @@ pub fn run(file_path: &str) -> Result<(), Error> {
     top_conserved.iter().for_each(|(s, e)| println!("{s} {e}"));
 
     println!("\nAnnotations:");
-    println!("TODO");
+    let annotations = Annotations::load_if_given(annotation_path).map_err(|e| Error::in_file(FileKind::Annotation, annotation_path.unwrap_or_default(), e))?;
+    top_conserved.iter().for_each(|(s, e)| {
+        println!("\n{s} {e}");
+        for line in describe_region(annotations.as_ref(), &chr, *s as i64 - 1, *e as i64) {
+            println!("Description: {}", line);
+        }
+    });
+
+    // Every conserved segment, not just the longest, for a genome browser track
+    let bed_path = Path::new(file_path).with_file_name("conserved_segments.bed");
+    let conserved: Vec<Feature> = state_segments[1]
+        .iter()
+        .sorted()
+        .map(|(s, e)| Feature::new(&chr, feature_io::BED_KIND, *s as i64 - 1, *e as i64, None))
+        .collect();
+    feature_io::write_features_to_path(&bed_path, &conserved, FeatureFormat::Bed).map_err(|e| Error::io(&bed_path, e))?;
+    println!("\nSegments written to {}", bed_path.display());
 
     Ok(())
 }
 
 struct StateProbability {
+    /// Frequency file the probabilities came from, to blame for missing emissions
+    file_path: String,
     emission_probabilities: HashMap<String, f64>,
 }
 
 impl StateProbability {
-    fn from_counts(emission_counts: HashMap<String, usize>) -> Self {
+    fn from_counts(file_path: &str, emission_counts: HashMap<String, usize>) -> Self {
         let mut emission_probabilities = HashMap::new();
         let total_count = emission_counts.iter().fold(0.0, |prev, (_, next)| prev + *next as f64);
         for (em, c) in emission_counts {
             emission_probabilities.insert(em, (c as f64 / total_count).ln());
         }
-        return StateProbability { emission_probabilities };
+        return StateProbability {
+            file_path: file_path.to_string(),
+            emission_probabilities,
+        };
     }
 
-    fn get_prob(&self, em: &String) -> f64 {
-        return self.emission_probabilities[em];
+    fn get_prob(&self, em: &String) -> Result<f64, Error> {
+        return self
+            .emission_probabilities
+            .get(em)
+            .copied()
+            .ok_or_else(|| Error::invalid(FileKind::Counts, &self.file_path, format!("no count for alignment column '{}'", em)));
     }
 
     fn print_probs(&self, id: &str) {
@@ -113,67 +139,81 @@ impl StateProbability {
     }
 }
 
-fn load_alignment(file_path: &str) -> Result<(Vec<String>, isize, isize), Error> {
-    let mut lines = read::lines(file_path)?;
+fn load_alignment(file_path: &str) -> Result<(Vec<String>, String, isize, isize), Error> {
+    let mut lines = read::lines(file_path)
+        .map_err(|e| Error::in_file(FileKind::Alignment, file_path, e))?
+        .enumerate();
     let mut aln = Vec::new();
+    let mut chr = String::new();
     let mut start: Option<isize> = None;
     let mut end: Option<isize> = None;
-    while let Some(line) = lines.next() {
+    while let Some((line_no, line)) = lines.next() {
         if let Ok(ip) = line {
             if ip.starts_with("#") {
-                let (s, e) = parse_range(&ip);
-                if start == None {
+                let (c, s, e) = parse_range(&ip).map_err(|message| Error::malformed(FileKind::Alignment, file_path, line_no + 1, message))?;
+                if start.is_none() {
+                    chr = c;
                     start = Some(s);
-                } else {
-                    end = Some(e);
                 }
+                end = Some(e);
 
                 // Expected format: hg18\tATAAAA
-                let l1 = lines.next().unwrap()?;
-                let seq1 = l1.split("\t").nth(1).unwrap();
-                let l2 = lines.next().unwrap()?;
-                let seq2 = l2.split("\t").nth(1).unwrap();
-                let l3 = lines.next().unwrap()?;
-                let seq3 = l3.split("\t").nth(1).unwrap();
+                let mut rows: Vec<String> = Vec::new();
+                for _ in 0..3 {
+                    let (line_no, line) = lines
+                        .next()
+                        .ok_or_else(|| Error::malformed(FileKind::Alignment, file_path, line_no + 1, "block has fewer than 3 rows".to_string()))?;
+                    let row = line.map_err(|e| Error::in_file(FileKind::Alignment, file_path, e))?;
+                    let seq = row
+                        .split('\t')
+                        .nth(1)
+                        .map(|s| s.to_string())
+                        .ok_or_else(|| Error::malformed(FileKind::Alignment, file_path, line_no + 1, "expected 'species\tsequence'".to_string()))?;
+                    if !rows.is_empty() && seq.len() != rows[0].len() {
+                        let message = format!("row is {} columns long, but the block's first row is {}", seq.len(), rows[0].len());
+                        return Err(Error::malformed(FileKind::Alignment, file_path, line_no + 1, message));
+                    }
+                    rows.push(seq);
+                }
+                let (seq1, seq2, seq3) = (rows[0].as_bytes(), rows[1].as_bytes(), rows[2].as_bytes());
 
                 for i in 0..seq1.len() {
-                    aln.push(format!(
-                        "{}{}{}",
-                        seq1.as_bytes()[i] as char,
-                        seq2.as_bytes()[i] as char,
-                        seq3.as_bytes()[i] as char
-                    ))
+                    aln.push(format!("{}{}{}", seq1[i] as char, seq2[i] as char, seq3[i] as char))
                 }
             }
         }
     }
 
-    return Ok((aln, start.unwrap(), end.unwrap()));
+    return match (start, end) {
+        (Some(start), Some(end)) => Ok((aln, chr, start, end)),
+        _ => Err(Error::invalid(FileKind::Alignment, file_path, "no '# chr:start-end' block header".to_string())),
+    };
 }
 
 fn load_freqs(file_path: &str) -> Result<StateProbability, Error> {
     let mut emission_counts = HashMap::new();
-    let lines = read::lines(file_path)?;
-    for line in lines {
+    let lines = read::lines(file_path).map_err(|e| Error::in_file(FileKind::Counts, file_path, e))?;
+    for (line_no, line) in lines.enumerate() {
         if let Ok(ip) = line {
-            let mut line_parts = ip.split("\t");
-            let emission = line_parts.next().unwrap();
-            let count = line_parts.next().unwrap().parse::<usize>().unwrap();
+            let malformed = |message: String| Error::malformed(FileKind::Counts, file_path, line_no + 1, message);
+            let (emission, count) = ip.split_once('\t').ok_or_else(|| malformed("expected 'emission\tcount'".to_string()))?;
+            let count = count.trim().parse::<usize>().map_err(|_| malformed(format!("'{}' is not a count", count)))?;
             emission_counts.insert(emission.to_string(), count);
         }
     }
 
-    let state_prob = StateProbability::from_counts(emission_counts);
+    let state_prob = StateProbability::from_counts(file_path, emission_counts);
 
     Ok(state_prob)
 }
 
 // Expected format: # chrX:152767491-152767698
-fn parse_range(string: &str) -> (isize, isize) {
-    let range_str = string.split(":").nth(1).unwrap();
-    let mut range_parts = range_str.split("-");
-    let start: isize = range_parts.next().unwrap().parse().unwrap();
-    let end: isize = range_parts.next().unwrap().parse().unwrap();
-
-    return (start, end);
+fn parse_range(string: &str) -> Result<(String, isize, isize), String> {
+    let expected = || format!("expected '# chr:start-end', found '{}'", string);
+    let (chr, range_str) = string.trim_start_matches('#').trim().split_once(':').ok_or_else(expected)?;
+    let (start, end) = range_str.split_once('-').ok_or_else(expected)?;
+    let start: isize = start.trim().parse().map_err(|_| expected())?;
+    let end: isize = end.trim().parse().map_err(|_| expected())?;
+
+    return Ok((chr.to_string(), start, end));
 }
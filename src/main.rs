@@ -1,11 +1,21 @@
 extern crate core;
 
+use std::process;
 use std::time::Instant;
 
 use clap::{ArgGroup, Parser};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 
 mod aoc;
+mod discover;
+mod error;
+mod genes;
+mod graph;
 mod hw;
+mod matrix;
+mod profile;
+mod proteome;
 mod util;
 
 // Simple program to run assignments
@@ -14,16 +24,144 @@ mod util;
 #[command(group(
 ArgGroup::new("vers")
 .required(true)
-.args(["hw", "aoc"]),
+.args(["hw", "aoc", "profile", "discover", "orfs", "genes", "translate", "dag", "pwm"]),
 ))]
 struct Args {
     /// Genome 540 homework assignment to run
     #[arg(long)]
     hw: Option<u8>,
 
+    /// GenBank, GFF3 or BED file describing what homework hits overlap. Homework 1 takes one per genome, in order,
+    /// and homeworks 4, 6 and 9 take one each
+    #[arg(long)]
+    annotation: Vec<String>,
+
     /// Advent of code day to run
     #[arg(long)]
     aoc: Option<u8>,
+
+    /// FASTA file to report k-mer statistics and GC tracks for
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Longest k-mer to report with --profile
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u64).range(1..=util::kmer::MAX_K as u64))]
+    k: u64,
+
+    /// GC window size for --profile
+    #[arg(long, default_value_t = 10_000, value_parser = clap::value_parser!(u64).range(1..))]
+    window: u64,
+
+    /// Output format for --profile
+    #[arg(long, value_enum, default_value_t = profile::Format::Tsv)]
+    format: profile::Format,
+
+    /// FASTA file, or GenBank file to take the regions upstream of every CDS from, to look for motifs in
+    #[arg(long)]
+    discover: Option<String>,
+
+    /// Motif width for --discover
+    #[arg(long, default_value_t = 12, value_parser = clap::value_parser!(u64).range(1..))]
+    width: u64,
+
+    /// Number of motifs to find with --discover
+    #[arg(long, default_value_t = 1)]
+    motifs: usize,
+
+    /// Number of motif sites per sequence assumed by --discover
+    #[arg(long, value_enum, default_value_t = util::motif::SiteModel::Zoops)]
+    site_model: util::motif::SiteModel,
+
+    /// Length of the region upstream of each CDS that --discover searches in GenBank files
+    #[arg(long, default_value_t = 50)]
+    upstream: usize,
+
+    /// FASTA or GenBank file to list the open reading frames of, on all six frames
+    #[arg(long)]
+    orfs: Option<String>,
+
+    /// FASTA or GenBank file to call genes in, by scoring its open reading frames
+    #[arg(long)]
+    genes: Option<String>,
+
+    /// GenBank file to translate every CDS of into a protein FASTA file, checking each against its /translation
+    #[arg(long)]
+    translate: Option<String>,
+
+    /// Graph of `V`/`E` lines, or tab-separated edges if it ends in .tsv, to find the highest-scoring path through
+    #[arg(long)]
+    dag: Option<String>,
+
+    /// Find the lowest-scoring path through the --dag graph instead of the highest
+    #[arg(long)]
+    shortest: bool,
+
+    /// List every path through the --dag graph that scores at least this well, or at most this with --shortest
+    #[arg(long)]
+    within: Option<f64>,
+
+    /// GenBank file to build a position weight matrix around the 5' end of every --kinds feature of, FASTA file of
+    /// aligned sites or of the records that --sites lie on, or JASPAR (.pfm, .jaspar), MEME or TRANSFAC file of matrices
+    #[arg(long)]
+    pwm: Option<String>,
+
+    /// BED file of sites on the records of the --pwm FASTA file
+    #[arg(long)]
+    sites: Option<String>,
+
+    /// Comma-separated feature kinds whose 5' ends --pwm models in GenBank files
+    #[arg(long, value_delimiter = ',', default_value = "CDS")]
+    kinds: Vec<String>,
+
+    /// Bases either side of each --pwm site's 5' end, or added to both sides of each --sites interval
+    #[arg(long, default_value_t = util::pwm::DEFAULT_FLANK)]
+    flank: usize,
+
+    /// Pseudocount for --pwm, spread over the bases of each column
+    #[arg(long, default_value_t = util::pwm::DEFAULT_PSEUDOCOUNT)]
+    pseudocount: f64,
+
+    /// FASTA file to scan on both strands with every --pwm matrix
+    #[arg(long)]
+    scan: Option<String>,
+
+    /// Highest p-value of the --scan hits to list
+    #[arg(long, default_value_t = util::scan::DEFAULT_P_VALUE)]
+    p_value: f64,
+
+    /// NCBI genetic code for --orfs and --genes, and for --translate CDSs without a /transl_table
+    #[arg(long, default_value_t = util::genetic_code::BACTERIAL, value_parser = parse_genetic_code)]
+    genetic_code: u8,
+
+    /// Comma-separated start codons for --orfs and --genes, in place of the genetic code's
+    #[arg(long, value_delimiter = ',', value_parser = parse_codon)]
+    start_codons: Option<Vec<[u8; 3]>>,
+
+    /// Shortest open reading frame for --orfs and --genes, in bases, stop codon included
+    #[arg(long, default_value_t = util::orf::DEFAULT_MIN_LENGTH)]
+    min_length: usize,
+
+    /// Treat every record as circular for --orfs and --genes, even GenBank records whose LOCUS line says linear
+    #[arg(long)]
+    circular: bool,
+
+    /// Annotated GenBank file to train --genes on, rather than the longest open reading frames of the input
+    #[arg(long)]
+    training: Option<String>,
+
+    /// File to write the genes called by --genes to, as GenBank or GFF3 depending on its extension, the proteins from
+    /// --translate to, the --dag graph to, as DOT or in the `V`/`E` format if it ends in .dag or .txt, or the --pwm
+    /// matrices to, in the motif format its extension names (.pfm, .jaspar, .meme or .transfac)
+    #[arg(long)]
+    output: Option<String>,
+
+    /// Seed for simulations, so their output can be reproduced. A random seed is used if not given
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Bases per line of the FASTA files simulated by homeworks 2, 3 and 8, or 0 for a single line per record
+    #[arg(long, default_value_t = util::fasta::DEFAULT_LINE_WIDTH)]
+    line_width: usize,
 }
 
 fn main() {
@@ -31,17 +169,29 @@ fn main() {
 
     if let Some(hw) = args.hw {
         println!("Running homework assignment '{}':", hw);
+        let mut rng = seeded_rng(args.seed);
+        let annotation = |i: usize| args.annotation.get(i).map(String::as_str);
         let now = Instant::now();
         match hw {
-            1 => hw::hw1::run("./data/hw/hw1/CP001872.fna", "./data/hw/hw1/CP003913.fna").expect("Homework 1 should pass!"),
-            2 => hw::hw2::run("./data/hw/hw1/CP003913.fna").expect("Homework 2 should pass!"),
-            3 => hw::hw3::run("./data/hw/hw3/s_pyogenes.gbff").expect("Homework 3 should pass!"),
-            4 => hw::hw4::run("./data/hw/hw4/dag.txt", "data/hw/hw4/s_pyogenes.fa").expect("Homework 4 should pass!"),
-            5 => hw::hw5::run("./data/hw/hw5/seq1.fa", "./data/hw/hw5/seq2.fa", "./data/hw/hw5/seq3.fa").expect("Homework 5 should pass!"),
-            6 => hw::hw6::run("./data/hw/hw6/chm13.chr16.txt").expect("Homework 6 should pass!"),
-            7 => hw::hw7::run("./data/hw/hw6/chm13.chr16.txt").expect("Homework 7 should pass!"),
-            8 => hw::hw8::run("./data/hw/hw8/Pyrococcus_horikoshii.fasta").expect("Homework 8 should pass!"),
-            9 => hw::hw9::run("./data/hw/hw9/ENm010.aln").expect("Homework 9 should pass!"),
+            1 => exit_on_error(hw::hw1::run(
+                "./data/hw/hw1/CP001872.fna",
+                "./data/hw/hw1/CP003913.fna",
+                annotation(0),
+                annotation(1),
+            )),
+            2 => exit_on_error(hw::hw2::run("./data/hw/hw1/CP003913.fna", args.line_width, &mut rng)),
+            3 => exit_on_error(hw::hw3::run("./data/hw/hw3/s_pyogenes.gbff", args.line_width, &mut rng)),
+            4 => exit_on_error(hw::hw4::run("./data/hw/hw4/dag.txt", "data/hw/hw4/s_pyogenes.fa", annotation(0))),
+            5 => exit_on_error(hw::hw5::run("./data/hw/hw5/seq1.fa", "./data/hw/hw5/seq2.fa", "./data/hw/hw5/seq3.fa")),
+            6 => exit_on_error(hw::hw6::run("./data/hw/hw6/chm13.chr16.txt", annotation(0))),
+            7 => exit_on_error(hw::hw7::run("./data/hw/hw6/chm13.chr16.txt", &mut rng)),
+            8 => exit_on_error(hw::hw8::run("./data/hw/hw8/Pyrococcus_horikoshii.fasta", args.line_width, &mut rng)),
+            9 => exit_on_error(hw::hw9::run(
+                "./data/hw/hw9/ENm010.aln",
+                "./data/hw/hw9/STATE1_anc_rep_counts.txt",
+                "./data/hw/hw9/STATE2_codon1_2_counts.txt",
+                annotation(0),
+            )),
             _ => panic!("This assignment hasn't been completed!"),
         }
         println!("Homework '{}' completed in '{}' seconds", hw, now.elapsed().as_secs());
@@ -60,7 +210,82 @@ fn main() {
             _ => panic!("This day hasn't been completed!"),
         }
         println!("Day '{}' completed in '{:#?}'", aoc, now.elapsed());
+    } else if let Some(file_path) = args.profile {
+        exit_on_error(profile::run(&file_path, args.k as usize, args.window as usize, args.format));
+    } else if let Some(file_path) = args.discover {
+        let mut rng = seeded_rng(args.seed);
+        exit_on_error(discover::run(
+            &file_path,
+            args.width as usize,
+            args.motifs,
+            args.site_model,
+            args.upstream,
+            &mut rng,
+        ));
+    } else if let Some(file_path) = args.orfs.as_deref() {
+        exit_on_error(genes::orfs(file_path, &orf_finder(&args), args.circular));
+    } else if let Some(file_path) = args.genes.as_deref() {
+        let finder = orf_finder(&args);
+        exit_on_error(genes::call(file_path, &finder, args.training.as_deref(), args.circular, args.output.as_deref()));
+    } else if let Some(file_path) = args.translate.as_deref() {
+        let code = util::genetic_code::GeneticCode::from_id(args.genetic_code).unwrap();
+        exit_on_error(proteome::run(file_path, code, args.output.as_deref()));
+    } else if let Some(file_path) = args.dag.as_deref() {
+        let objective = if args.shortest {
+            util::dag::Objective::Shortest
+        } else {
+            util::dag::Objective::Longest
+        };
+        exit_on_error(graph::run(file_path, objective, args.within, args.output.as_deref()));
+    } else if let Some(file_path) = args.pwm.as_deref() {
+        let options = matrix::Options {
+            sites: args.sites.as_deref(),
+            kinds: &args.kinds,
+            flank: args.flank,
+            pseudocount: args.pseudocount,
+            scan: args.scan.as_deref(),
+            p_value: args.p_value,
+            output: args.output.as_deref(),
+        };
+        exit_on_error(matrix::run(file_path, &options));
     } else {
         panic!("How'd you get here?!")
     }
 }
+
+/// Prints a failed run's error and exits, so that a bad input gives a diagnostic rather than a panic and backtrace.
+fn exit_on_error<E: Into<error::Error>>(result: Result<(), E>) {
+    if let Err(e) = result {
+        eprintln!("Error: {}", e.into());
+        process::exit(1);
+    }
+}
+
+/// Seeds the RNG from `--seed`, or randomly, and prints the seed so the run can be reproduced.
+fn seeded_rng(seed: Option<u64>) -> StdRng {
+    let seed = seed.unwrap_or_else(rand::random);
+    println!("Seed: {}", seed);
+    return StdRng::seed_from_u64(seed);
+}
+
+fn orf_finder(args: &Args) -> util::orf::OrfFinder {
+    let finder = util::orf::OrfFinder::new()
+        .genetic_code(util::genetic_code::GeneticCode::from_id(args.genetic_code).unwrap())
+        .min_length(args.min_length);
+    return match &args.start_codons {
+        Some(start_codons) => finder.start_codons(start_codons.clone()),
+        None => finder,
+    };
+}
+
+fn parse_genetic_code(s: &str) -> Result<u8, String> {
+    let id: u8 = s.parse().map_err(|_| format!("'{}' isn't a number", s))?;
+    return match util::genetic_code::GeneticCode::from_id(id) {
+        Some(_) => Ok(id),
+        None => Err(format!("there's no NCBI genetic code {}", id)),
+    };
+}
+
+fn parse_codon(s: &str) -> Result<[u8; 3], String> {
+    return util::orf::parse_codon(s).ok_or_else(|| format!("'{}' isn't a codon of A, C, G and T", s));
+}
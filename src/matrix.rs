@@ -0,0 +1,136 @@
+use crate::util::annotation;
+use crate::util::fasta::{self, Alphabet};
+use crate::util::motif_io::{self, Motif, MotifFormat};
+use crate::util::nucleotide::{reverse_complement, Base};
+use crate::util::pwm::Pwm;
+use crate::util::scan::{PwmScanner, DEFAULT_SCALE};
+use crate::util::strand::Strand;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+/// Where `run` takes its sites from and what it does with the matrices.
+pub struct Options<'a> {
+    /// BED file of sites on the records of a FASTA input
+    pub sites: Option<&'a str>,
+    /// Feature kinds whose 5' ends are modelled in a GenBank input
+    pub kinds: &'a [String],
+    pub flank: usize,
+    pub pseudocount: f64,
+    /// FASTA file to scan with every matrix
+    pub scan: Option<&'a str>,
+    pub p_value: f64,
+    pub output: Option<&'a str>,
+}
+
+/// Builds position weight matrices, relative to an even background, from one of:
+///
+/// - a JASPAR, MEME or TRANSFAC file, named by its extension, taking every motif in it,
+/// - a GenBank file, taking `flank` bases either side of the 5' end of every feature of the given kinds,
+/// - a FASTA file and a BED file of `sites` on its records, each widened by `flank` bases on both sides,
+/// - a FASTA file of aligned sites, one per record.
+///
+/// Prints each matrix's summary and weights, lists the windows of the `scan` file's records that score with at most
+/// the given p-value, and writes the matrices to `output` in the motif format its extension names, so a motif file
+/// can also be converted from one format to another.
+pub fn run(file_path: &str, options: &Options) -> Result<(), Error> {
+    let background = [0.25; 4];
+    let motifs = match MotifFormat::from_path(file_path) {
+        Some(format) => motif_io::read_motifs(file_path, format, options.pseudocount, background)?,
+        None => {
+            let pwm = build_pwm(file_path, options, background)?;
+            let id = Path::new(file_path).file_stem().and_then(|s| s.to_str()).unwrap_or("pwm").to_string();
+            vec![Motif {
+                id,
+                name: pwm.consensus(),
+                pwm,
+            }]
+        }
+    };
+    if motifs.iter().all(|m| m.pwm.is_empty()) {
+        return Err(Error::new(ErrorKind::InvalidData, format!("no sites found in {}", file_path)));
+    }
+
+    for motif in &motifs {
+        print_motif(motif);
+    }
+    if let Some(scan_path) = options.scan {
+        scan(scan_path, &motifs, options.p_value)?;
+    }
+
+    if let Some(output_path) = options.output {
+        let format = MotifFormat::from_path(output_path).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("can't tell the motif format of {} from its extension", output_path),
+            )
+        })?;
+        motif_io::write_motifs_to_path(output_path, &motifs, format)?;
+        println!("\nMatrices written to {}", output_path);
+    }
+    return Ok(());
+}
+
+fn build_pwm(file_path: &str, options: &Options, background: [f64; 4]) -> Result<Pwm, Error> {
+    let (flank, pseudocount) = (options.flank, options.pseudocount);
+    return if annotation::is_genbank(file_path) {
+        let records = annotation::read_records(file_path)?;
+        let kinds: Vec<&str> = options.kinds.iter().map(String::as_str).collect();
+        Pwm::from_features(&records, &kinds, flank, flank, pseudocount, background)
+    } else if let Some(bed_path) = options.sites {
+        Pwm::from_bed(file_path, bed_path, flank, pseudocount, background)
+    } else {
+        Pwm::from_fasta(file_path, 0, pseudocount, background)
+    };
+}
+
+fn print_motif(motif: &Motif) {
+    let pwm = &motif.pwm;
+    println!("\nMotif {} {}", motif.id, motif.name);
+    println!("Sites: {}", pwm.site_count());
+    println!("Positions: {}..{}", pwm.offset(), pwm.offset() + pwm.len() as i64 - 1);
+    println!("Pseudocount: {}", pwm.pseudocount());
+    println!("Consensus: {}", pwm.consensus());
+    println!("Information Content: {:.4} bits", pwm.total_information_content());
+    println!("Score Range: {:.4} to {:.4}", pwm.min_score(), pwm.max_score());
+    println!("Weight Matrix:");
+    println!("Position A C G T");
+    for pos in pwm.positions() {
+        let weights: Vec<String> = Base::ACGT.iter().map(|b| format!("{:.4}", pwm.weight(pos, *b))).collect();
+        println!("{} {}", pos, weights.join(" "));
+    }
+}
+
+/// Prints every window of every record that one of the motifs scores with at most `p_value`, on either strand.
+fn scan(file_path: &str, motifs: &[Motif], p_value: f64) -> Result<(), Error> {
+    let scanners: Vec<PwmScanner> = motifs.iter().map(|m| PwmScanner::new(&m.pwm, DEFAULT_SCALE)).collect();
+    println!("\nHits in {} (p <= {:e}):", file_path, p_value);
+    println!("Motif Record Position Strand Score P-Value Site");
+    let mut count = 0;
+    for record in fasta::Reader::from_path(file_path)?.alphabet(Alphabet::IUPAC).uppercase() {
+        let record = record?;
+        let seq = record.seq.as_bytes();
+        for (motif, scanner) in motifs.iter().zip(&scanners) {
+            for hit in scanner.scan(seq, p_value) {
+                // The window as the motif reads it, so reverse-strand sites line up with the consensus
+                let window = &seq[hit.start..hit.start + scanner.len()];
+                let site = match hit.strand {
+                    Strand::Forward => window.to_vec(),
+                    Strand::Reverse => reverse_complement(window),
+                };
+                println!(
+                    "{} {} {} {} {:.4} {:.3e} {}",
+                    motif.id,
+                    record.id,
+                    hit.start + 1,
+                    hit.strand,
+                    hit.score,
+                    hit.p_value,
+                    String::from_utf8_lossy(&site)
+                );
+                count += 1;
+            }
+        }
+    }
+    println!("Hits: {}", count);
+    return Ok(());
+}
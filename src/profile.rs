@@ -0,0 +1,55 @@
+use crate::util::fasta::{self, Alphabet};
+use crate::util::kmer::{self, KmerSpectrum};
+use clap::ValueEnum;
+use std::io::{self, BufWriter, Error, Write};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Tsv,
+    Json,
+}
+
+/// Writes a composition profile of an assembly to stdout: the spectrum of every k-mer length up to `k`, and GC
+/// content and skew over non-overlapping windows of every record.
+///
+/// TSV output is split into sections that each start with a `# name` line, so they can be pulled apart with `awk`.
+pub fn run(file_path: &str, k: usize, window: usize, format: Format) -> Result<(), Error> {
+    let mut spectrum = KmerSpectrum::new(k);
+    let mut windows = Vec::new();
+    for record in fasta::Reader::from_path(file_path)?.alphabet(Alphabet::IUPAC).skip_invalid().uppercase() {
+        let record = record?;
+        spectrum.add(record.seq.as_bytes());
+        windows.extend(kmer::gc_windows(&record.id, record.seq.as_bytes(), window, window));
+    }
+
+    let mut out = BufWriter::new(io::stdout().lock());
+    match format {
+        Format::Tsv => {
+            writeln!(out, "# strand_asymmetry")?;
+            writeln!(out, "k\tasymmetry")?;
+            for len in 1..=k {
+                writeln!(out, "{}\t{:.6}", len, spectrum.strand_asymmetry(len))?;
+            }
+            for len in 1..=k {
+                writeln!(out, "\n# kmers k={}", len)?;
+                spectrum.write_tsv(&mut out, len)?;
+            }
+            writeln!(out, "\n# gc window={}", window)?;
+            kmer::write_gc_tsv(&mut out, &windows)?;
+        }
+        Format::Json => {
+            write!(out, "{{\"window\":{},\"spectra\":[", window)?;
+            for len in 1..=k {
+                if len > 1 {
+                    write!(out, ",")?;
+                }
+                spectrum.write_json(&mut out, len)?;
+            }
+            write!(out, "],\"gc\":")?;
+            kmer::write_gc_json(&mut out, &windows)?;
+            writeln!(out, "}}")?;
+        }
+    }
+
+    return out.flush();
+}
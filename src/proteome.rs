@@ -0,0 +1,72 @@
+use crate::util::annotation;
+use crate::util::fasta;
+use crate::util::genetic_code::GeneticCode;
+use crate::util::translate;
+use gb_io::seq::Feature;
+use gb_io::{feature_kind, qualifier_key};
+use std::io::Error;
+use std::path::Path;
+
+/// Translates every CDS of a GenBank file into a protein FASTA file, `output` or the input with a `.faa` extension,
+/// ready for the alignment homework. Each translation is checked against the feature's `/translation` and any that
+/// differ are listed. Proteins are written up to any internal stop, with `J` as `X`, so `hw5` can read them.
+pub fn run(file_path: &str, default_code: &'static GeneticCode, output: Option<&str>) -> Result<(), Error> {
+    let output_path = match output {
+        Some(output) => Path::new(output).to_path_buf(),
+        None => Path::new(file_path).with_extension("faa"),
+    };
+    let mut writer = fasta::Writer::from_path(&output_path)?;
+    let (mut total, mut matched, mut unchecked, mut skipped, mut truncated) = (0, 0, 0, 0, 0);
+    let mut mismatches = Vec::new();
+
+    for seq in annotation::read_records(file_path)? {
+        for feature in seq.features.iter().filter(|f| f.kind == feature_kind!("CDS")) {
+            total += 1;
+            let id = protein_id(feature, &seq.name);
+            // Pseudogenes and CDSs pointing into other entries have nothing to translate
+            let translation = match translate::translate_feature(&seq, feature, default_code) {
+                Ok(translation) => translation,
+                Err(e) => {
+                    println!("Skipped {}: {}", id, e);
+                    skipped += 1;
+                    continue;
+                }
+            };
+            match translation.matches() {
+                Some(true) => matched += 1,
+                Some(false) => mismatches.push(id.clone()),
+                None => unchecked += 1,
+            }
+            let product = feature.qualifier_values(qualifier_key!("product")).next().unwrap_or("");
+            let (protein, stopped) = translate::for_alignment(&translation.protein);
+            if stopped {
+                truncated += 1;
+            }
+            writer.write_header(&id, product)?;
+            writer.write_seq(&protein)?;
+        }
+    }
+    writer.finish()?;
+
+    println!("CDSs: {}", total);
+    println!("Matching /translation: {}", matched);
+    println!("Without /translation: {}", unchecked);
+    println!("Skipped: {}", skipped);
+    println!("Cut at an internal stop: {}", truncated);
+    println!("Mismatched /translation: {}", mismatches.len());
+    for id in &mismatches {
+        println!("{}", id);
+    }
+    println!("Proteins written to {}", output_path.display());
+    return Ok(());
+}
+
+/// Protein id, locus tag or gene name of a CDS, or failing those its record and location.
+fn protein_id(feature: &Feature, record: &Option<String>) -> String {
+    for key in [qualifier_key!("protein_id"), qualifier_key!("locus_tag"), qualifier_key!("gene")] {
+        if let Some(id) = feature.qualifier_values(key).next() {
+            return id.to_string();
+        }
+    }
+    return format!("{}:{}", record.as_deref().unwrap_or("unnamed"), feature.location.to_gb_format());
+}
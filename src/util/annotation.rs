@@ -0,0 +1,331 @@
+use crate::util::feature_io::{self, Feature, FeatureFormat};
+use crate::util::strand::Strand;
+use gb_io::reader::SeqReader;
+use gb_io::seq::Seq;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+
+/// Extensions of GenBank flat files, for telling them apart from FASTA.
+pub const GENBANK_EXTENSIONS: [&str; 3] = ["gb", "gbk", "gbff"];
+
+/// A feature flattened down to what's needed to describe a position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub kind: String,
+    /// 0-based, exclusive bounds of the whole feature. `end` comes before `start` when it spans the origin of a
+    /// circular sequence.
+    pub start: i64,
+    pub end: i64,
+    pub strand: Strand,
+    pub gene: Option<String>,
+    pub locus_tag: Option<String>,
+    pub product: Option<String>,
+}
+
+impl Annotation {
+    /// Takes the gene, locus tag and product from the GenBank qualifiers or GFF3 attributes of the same names, and
+    /// the gene from the name of a feature that has neither, such as a BED interval.
+    fn from_feature(feature: &Feature) -> Annotation {
+        let attribute = |key| feature.attribute(key).map(|v| v.to_string());
+        let locus_tag = attribute("locus_tag");
+        return Annotation {
+            kind: feature.kind.clone(),
+            start: feature.start,
+            end: feature.end,
+            strand: feature.strand.unwrap_or(Strand::Forward),
+            gene: attribute("gene").or_else(|| feature.name.clone().filter(|_| locus_tag.is_none())),
+            locus_tag,
+            product: attribute("product"),
+        };
+    }
+
+    /// One-line summary, e.g. `CDS dnaA (SPY_0001) 1..1362 forward: chromosomal replication initiator protein DnaA`
+    pub fn describe(&self) -> String {
+        let mut description = self.kind.clone();
+        if let Some(gene) = &self.gene {
+            description.push_str(&format!(" {}", gene));
+        }
+        if let Some(locus_tag) = &self.locus_tag {
+            description.push_str(&format!(" ({})", locus_tag));
+        }
+        description.push_str(&format!(" {}..{} {}", self.start + 1, self.end, self.strand));
+        if let Some(product) = &self.product {
+            description.push_str(&format!(": {}", product));
+        }
+        return description;
+    }
+}
+
+/// Interval index over the features of one sequence.
+///
+/// Every contiguous piece of a feature is stored as its own interval, sorted by start and paired with the running
+/// maximum of the ends, so a lookup is a binary search followed by a scan over the intervals that can still reach the
+/// query.
+pub struct AnnotationIndex {
+    pub name: String,
+    accession: Option<String>,
+    version: Option<String>,
+    annotations: Vec<Annotation>,
+    intervals: Vec<(i64, i64, usize)>,
+    max_ends: Vec<i64>,
+}
+
+impl AnnotationIndex {
+    pub fn new(seq: &Seq) -> AnnotationIndex {
+        let features = feature_io::from_seq(seq);
+        let mut index = AnnotationIndex::from_features(&seq.name.clone().unwrap_or_default(), &features, Some(seq.len()));
+        index.accession = seq.accession.clone();
+        index.version = seq.version.clone();
+        return index;
+    }
+
+    /// Indexes features of the sequence called `name`. Pieces that run past `len`, the length of a circular sequence,
+    /// wrap round to its start.
+    pub fn from_features(name: &str, features: &[Feature], len: Option<i64>) -> AnnotationIndex {
+        let mut annotations = Vec::new();
+        let mut intervals = Vec::new();
+        for feature in features.iter().filter(|f| f.kind != "source") {
+            for (s, e) in feature.pieces() {
+                // A site between two bases covers both of them
+                let (s, e) = if s == e { (s - 1, e + 1) } else { (s, e) };
+                match len {
+                    Some(len) if e > len => {
+                        if s < len {
+                            intervals.push((s, len, annotations.len()));
+                        }
+                        intervals.push(((s - len).max(0), e - len, annotations.len()));
+                    }
+                    _ => intervals.push((s, e, annotations.len())),
+                }
+            }
+            let mut annotation = Annotation::from_feature(feature);
+            if let Some(len) = len.filter(|len| annotation.end > *len) {
+                annotation.end -= len;
+            }
+            annotations.push(annotation);
+        }
+
+        intervals.sort_unstable();
+        let mut max_ends = Vec::with_capacity(intervals.len());
+        let mut max_end = i64::MIN;
+        for (_, e, _) in intervals.iter() {
+            max_end = max_end.max(*e);
+            max_ends.push(max_end);
+        }
+
+        return AnnotationIndex {
+            name: name.to_string(),
+            accession: None,
+            version: None,
+            annotations,
+            intervals,
+            max_ends,
+        };
+    }
+
+    /// Annotations overlapping the 0-based, exclusive range, optionally restricted to one strand.
+    pub fn overlapping_range(&self, start: i64, end: i64, strand: Option<Strand>) -> Vec<&Annotation> {
+        let mut found: Vec<usize> = Vec::new();
+        let mut i = self.intervals.partition_point(|(s, _, _)| *s < end);
+        while i > 0 && self.max_ends[i - 1] > start {
+            i -= 1;
+            let (_, e, a) = self.intervals[i];
+            if e > start && strand.map(|s| s == self.annotations[a].strand).unwrap_or(true) {
+                found.push(a);
+            }
+        }
+        found.sort_unstable();
+        found.dedup();
+        return found.iter().map(|a| &self.annotations[*a]).collect();
+    }
+
+    /// Whether a sequence identifier (a FASTA id such as `gi|284930242|gb|CP001872.1|`) refers to this record.
+    pub fn matches_id(&self, id: &str) -> bool {
+        return id.split('|').filter(|f| !f.is_empty()).any(|f| {
+            let unversioned = f.split('.').next().unwrap_or(f);
+            f == self.name || Some(f) == self.version.as_deref() || Some(unversioned) == self.accession.as_deref()
+        });
+    }
+}
+
+/// All records of a GenBank, GFF3 or BED file, indexed for position lookups.
+pub struct Annotations {
+    records: Vec<AnnotationIndex>,
+}
+
+impl Annotations {
+    /// Loads a GFF3 or BED file, going by its extension, or otherwise a GenBank flat file.
+    pub fn load<P: AsRef<Path>>(file_path: P) -> io::Result<Annotations> {
+        let Some(format) = FeatureFormat::from_path(&file_path) else {
+            let records = read_records(file_path)?.iter().map(AnnotationIndex::new).collect();
+            return Ok(Annotations { records });
+        };
+        return Ok(Annotations::from_features(feature_io::read_features(file_path, format)?));
+    }
+
+    /// Indexes features from any number of linear sequences, keeping the sequences in the order they first appear.
+    pub fn from_features(features: Vec<Feature>) -> Annotations {
+        let mut by_seq: Vec<(String, Vec<Feature>)> = Vec::new();
+        let mut seq_index: HashMap<String, usize> = HashMap::new();
+        for feature in features {
+            let i = *seq_index.entry(feature.seq_id.clone()).or_insert_with(|| {
+                by_seq.push((feature.seq_id.clone(), Vec::new()));
+                by_seq.len() - 1
+            });
+            by_seq[i].1.push(feature);
+        }
+        let records = by_seq
+            .iter()
+            .map(|(name, features)| AnnotationIndex::from_features(name, features, None))
+            .collect();
+        return Annotations { records };
+    }
+
+    /// Loads the annotation file if one was given, so callers can fall back gracefully when there isn't one.
+    pub fn load_if_given(file_path: Option<&str>) -> io::Result<Option<Annotations>> {
+        return file_path.map(Annotations::load).transpose();
+    }
+
+    /// The record matching the sequence id, or the only record when the file has just one.
+    pub fn find(&self, id: &str) -> Option<&AnnotationIndex> {
+        return self
+            .records
+            .iter()
+            .find(|r| r.matches_id(id))
+            .or(if self.records.len() == 1 { self.records.first() } else { None });
+    }
+
+    /// Summary lines for every annotation overlapping the 0-based, exclusive range of the given sequence.
+    pub fn describe(&self, id: &str, start: i64, end: i64, strand: Option<Strand>) -> Vec<String> {
+        return match self.find(id) {
+            Some(index) => index.overlapping_range(start, end, strand).iter().map(|a| a.describe()).collect(),
+            None => Vec::new(),
+        };
+    }
+}
+
+/// Whether a file looks like GenBank from its extension.
+pub fn is_genbank<P: AsRef<Path>>(file_path: P) -> bool {
+    let extension = file_path.as_ref().extension().and_then(|e| e.to_str()).unwrap_or("");
+    return GENBANK_EXTENSIONS.contains(&extension);
+}
+
+/// Every record of a GenBank flat file.
+pub fn read_records<P: AsRef<Path>>(file_path: P) -> io::Result<Vec<Seq>> {
+    let mut records = Vec::new();
+    for seq in SeqReader::new(File::open(file_path)?) {
+        records.push(seq.map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?);
+    }
+    return Ok(records);
+}
+
+/// Description lines for a region, falling back to a note when there's no annotation file or nothing overlaps.
+pub fn describe_region(annotations: Option<&Annotations>, id: &str, start: i64, end: i64) -> Vec<String> {
+    let Some(annotations) = annotations else {
+        return vec!["no annotation file given".to_string()];
+    };
+    let lines = annotations.describe(id, start, end, None);
+    return if lines.is_empty() {
+        vec!["no overlapping annotation".to_string()]
+    } else {
+        lines
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::feature_io::parse_features;
+    use gb_io::seq::{Feature as GbFeature, Location, Topology};
+    use gb_io::{feature_kind, qualifier_key};
+
+    fn gb_feature(kind: gb_io::FeatureKind, location: &str, qualifiers: &[(gb_io::QualifierKey, &str)]) -> GbFeature {
+        return GbFeature {
+            kind,
+            location: Location::from_gb_format(location).unwrap(),
+            qualifiers: qualifiers.iter().map(|(k, v)| (k.clone(), Some(v.to_string()))).collect(),
+        };
+    }
+
+    fn circular_record() -> Seq {
+        return Seq {
+            name: Some("CP001872".to_string()),
+            accession: Some("CP001872".to_string()),
+            version: Some("CP001872.1".to_string()),
+            topology: Topology::Circular,
+            len: Some(100),
+            seq: vec![b'A'; 100],
+            features: vec![
+                gb_feature(feature_kind!("source"), "1..100", &[]),
+                gb_feature(feature_kind!("gene"), "join(91..100,1..10)", &[(qualifier_key!("gene"), "dnaA")]),
+                gb_feature(
+                    feature_kind!("CDS"),
+                    "complement(30..40)",
+                    &[(qualifier_key!("locus_tag"), "SPY_0001"), (qualifier_key!("product"), "replication protein")],
+                ),
+            ],
+            ..Seq::empty()
+        };
+    }
+
+    fn descriptions(annotations: Vec<&Annotation>) -> Vec<String> {
+        return annotations.iter().map(|a| a.describe()).collect();
+    }
+
+    #[test]
+    fn features_spanning_the_origin_are_found_on_both_sides_of_it() {
+        let index = AnnotationIndex::new(&circular_record());
+        let gene = vec!["gene dnaA 91..10 forward".to_string()];
+        assert_eq!(descriptions(index.overlapping_range(0, 1, None)), gene);
+        assert_eq!(descriptions(index.overlapping_range(95, 96, None)), gene);
+        assert_eq!(descriptions(index.overlapping_range(85, 100, None)), gene);
+        assert!(index.overlapping_range(10, 29, None).is_empty());
+        assert!(index.overlapping_range(50, 90, None).is_empty());
+    }
+
+    #[test]
+    fn overlaps_can_be_restricted_to_a_strand() {
+        let index = AnnotationIndex::new(&circular_record());
+        let cds = vec!["CDS (SPY_0001) 30..40 reverse: replication protein".to_string()];
+        assert_eq!(descriptions(index.overlapping_range(5, 35, Some(Strand::Reverse))), cds);
+        assert_eq!(descriptions(index.overlapping_range(39, 40, None)), cds);
+        assert_eq!(index.overlapping_range(5, 35, Some(Strand::Forward)).len(), 1);
+        assert!(index.overlapping_range(40, 41, None).is_empty());
+    }
+
+    #[test]
+    fn records_are_matched_by_name_accession_or_version() {
+        let index = AnnotationIndex::new(&circular_record());
+        assert!(index.matches_id("CP001872"));
+        assert!(index.matches_id("gi|284930242|gb|CP001872.1|"));
+        assert!(index.matches_id("CP001872.2"));
+        assert!(!index.matches_id("CP003913.1"));
+    }
+
+    #[test]
+    fn gff3_features_are_indexed_per_sequence_without_their_introns() {
+        let gff3 = "##gff-version 3\n\
+                    chr1\t.\tCDS\t1\t50\t.\t+\t0\tID=cds1;gene=abcA\n\
+                    chr1\t.\tCDS\t201\t300\t.\t+\t1\tID=cds1;gene=abcA\n\
+                    chr2\t.\tgene\t11\t20\t.\t-\t.\tID=gene1;locus_tag=XYZ_1\n";
+        let annotations = Annotations::from_features(parse_features(gff3, FeatureFormat::Gff3).unwrap());
+        assert_eq!(annotations.describe("chr1", 0, 1, None), vec!["CDS abcA 1..300 forward"]);
+        assert_eq!(annotations.describe("chr1", 249, 260, None), vec!["CDS abcA 1..300 forward"]);
+        assert!(annotations.describe("chr1", 50, 200, None).is_empty());
+        assert_eq!(annotations.describe("chr2", 0, 11, None), vec!["gene (XYZ_1) 11..20 reverse"]);
+        assert!(annotations.describe("chr1", 10, 11, Some(Strand::Reverse)).is_empty());
+        // With more than one record, an unknown id matches none of them
+        assert!(annotations.find("chr3").is_none());
+
+        assert_eq!(describe_region(Some(&annotations), "chr2", 30, 40), vec!["no overlapping annotation"]);
+        assert_eq!(describe_region(None, "chr2", 10, 20), vec!["no annotation file given"]);
+    }
+
+    #[test]
+    fn a_single_record_answers_for_any_id() {
+        let annotations = Annotations::from_features(parse_features("chr1\t5\t15\tpeak1\t0\t+\n", FeatureFormat::Bed).unwrap());
+        assert_eq!(annotations.describe("gi|1|gb|other.1|", 10, 11, None), vec!["region peak1 6..15 forward"]);
+    }
+}
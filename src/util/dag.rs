@@ -0,0 +1,607 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::error;
+use std::fmt;
+use std::io;
+
+pub type NodeId = usize;
+pub type EdgeId = usize;
+
+/// Scores within this fraction of each other count as tied, so that the same weights summed in a different order still
+/// match.
+const TIE_TOLERANCE: f64 = 1e-9;
+
+/// Why a graph couldn't be treated as a DAG.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DagError {
+    /// The graph has a cycle, which goes through the named node
+    Cycle(String),
+}
+
+impl fmt::Display for DagError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            DagError::Cycle(node) => write!(f, "graph has a cycle through node '{}'", node),
+        };
+    }
+}
+
+impl error::Error for DagError {}
+
+impl From<DagError> for io::Error {
+    fn from(error: DagError) -> io::Error {
+        return io::Error::new(io::ErrorKind::InvalidData, error);
+    }
+}
+
+/// A labelled, weighted edge.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edge {
+    pub from: NodeId,
+    pub to: NodeId,
+    pub label: String,
+    pub weight: f64,
+}
+
+/// A weighted directed graph with named nodes, meant to be acyclic.
+///
+/// Nodes and edges live in vectors and refer to each other by index, so building and walking even graphs of millions
+/// of nodes (e.g. the alignment graph of three proteins) stays cheap. Nothing stops a cycle being added; it's caught
+/// when the graph is first ordered.
+#[derive(Debug, Clone, Default)]
+pub struct Dag {
+    names: Vec<String>,
+    index: HashMap<String, NodeId>,
+    edges: Vec<Edge>,
+    outgoing: Vec<Vec<EdgeId>>,
+    incoming: Vec<Vec<EdgeId>>,
+}
+
+/// Whether the best path is the heaviest or the lightest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Objective {
+    Longest,
+    Shortest,
+}
+
+impl Objective {
+    /// Whether score `a` beats score `b`.
+    pub fn better(self, a: f64, b: f64) -> bool {
+        return match self {
+            Objective::Longest => a > b,
+            Objective::Shortest => a < b,
+        };
+    }
+
+    /// 1 or -1, so that the best path under either objective is the heaviest once its weights are multiplied by it.
+    fn sign(self) -> f64 {
+        return match self {
+            Objective::Longest => 1.0,
+            Objective::Shortest => -1.0,
+        };
+    }
+}
+
+/// Where best paths may begin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Start {
+    /// Only at the given node, so every path is scored in full
+    Node(NodeId),
+    /// At any node. A path is only carried on while it beats the empty path, which scores 0, as in local alignment.
+    Anywhere,
+}
+
+/// Where paths may end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum End {
+    /// Only at the given node
+    Node(NodeId),
+    /// At any node, as long as the path has at least one edge
+    Anywhere,
+}
+
+/// A path through the graph, as its nodes and the edges between them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Path {
+    pub nodes: Vec<NodeId>,
+    pub edges: Vec<EdgeId>,
+    pub score: f64,
+}
+
+impl Path {
+    pub fn first(&self) -> NodeId {
+        return self.nodes[0];
+    }
+
+    pub fn last(&self) -> NodeId {
+        return *self.nodes.last().unwrap();
+    }
+}
+
+/// The best score of a path into every node, with a back-pointer to the edge it arrived by.
+#[derive(Debug, Clone)]
+pub struct BestPaths<'a> {
+    dag: &'a Dag,
+    objective: Objective,
+    /// `None` for nodes no path reaches
+    scores: Vec<Option<f64>>,
+    back: Vec<Option<EdgeId>>,
+}
+
+/// Paths from best to worst, found by best-first search over partial paths.
+///
+/// Every partial path is ranked by its score plus the best score of any way to finish it, which is known exactly from
+/// one backward pass over the graph, so complete paths come off the queue in order and each costs only a walk along
+/// its own edges. Partial paths share their beginnings as links back to the path they extend.
+#[derive(Debug, Clone)]
+pub struct RankedPaths<'a> {
+    dag: &'a Dag,
+    sign: f64,
+    end: End,
+    /// Best signed score of a way to finish a path from every node, or `None` if no path can finish from it
+    completion: Vec<Option<f64>>,
+    /// The node each partial path has reached, the edge it took there and the path it extends
+    links: Vec<(NodeId, Option<EdgeId>, Option<usize>)>,
+    queue: BinaryHeap<Candidate>,
+    pushed: usize,
+}
+
+/// A partial or complete path waiting on the queue, ranked by the best score it could end with.
+#[derive(Debug, Clone)]
+struct Candidate {
+    bound: f64,
+    /// Order it was queued in, so that ties go to the newest and tied paths are followed to the end one at a time
+    /// rather than all spread out a step at a time
+    order: usize,
+    link: usize,
+    /// Signed score so far
+    score: f64,
+    complete: bool,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        return self.cmp(other) == Ordering::Equal;
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        return self.bound.total_cmp(&other.bound).then_with(|| self.order.cmp(&other.order));
+    }
+}
+
+impl Dag {
+    pub fn new() -> Dag {
+        return Dag::default();
+    }
+
+    /// Adds a node, or returns the existing one with the same name.
+    pub fn add_node(&mut self, name: &str) -> NodeId {
+        if let Some(id) = self.index.get(name) {
+            return *id;
+        }
+        let id = self.names.len();
+        self.names.push(name.to_string());
+        self.index.insert(name.to_string(), id);
+        self.outgoing.push(Vec::new());
+        self.incoming.push(Vec::new());
+        return id;
+    }
+
+    pub fn add_edge(&mut self, from: NodeId, to: NodeId, label: &str, weight: f64) -> EdgeId {
+        let id = self.edges.len();
+        self.edges.push(Edge {
+            from,
+            to,
+            label: label.to_string(),
+            weight,
+        });
+        self.outgoing[from].push(id);
+        self.incoming[to].push(id);
+        return id;
+    }
+
+    pub fn node_id(&self, name: &str) -> Option<NodeId> {
+        return self.index.get(name).copied();
+    }
+
+    pub fn name(&self, node: NodeId) -> &str {
+        return &self.names[node];
+    }
+
+    /// Number of nodes.
+    pub fn len(&self) -> usize {
+        return self.names.len();
+    }
+
+    pub fn edges(&self) -> &[Edge] {
+        return &self.edges;
+    }
+
+    /// Nodes in an order where every edge points forwards, by Kahn's algorithm. Ties go to the node added first, so
+    /// the order is the same every time.
+    pub fn topological_order(&self) -> Result<Vec<NodeId>, DagError> {
+        let mut in_degree: Vec<usize> = self.incoming.iter().map(|edges| edges.len()).collect();
+        let mut ready: VecDeque<NodeId> = (0..self.len()).filter(|n| in_degree[*n] == 0).collect();
+        let mut order = Vec::with_capacity(self.len());
+        while let Some(node) = ready.pop_front() {
+            order.push(node);
+            for edge in &self.outgoing[node] {
+                let to = self.edges[*edge].to;
+                in_degree[to] -= 1;
+                if in_degree[to] == 0 {
+                    ready.push_back(to);
+                }
+            }
+        }
+        if order.len() < self.len() {
+            return Err(DagError::Cycle(self.names[self.cycle_node(&in_degree)].clone()));
+        }
+        return Ok(order);
+    }
+
+    /// Scores the best path into every node in one pass over the edges in topological order.
+    pub fn best_paths(&self, objective: Objective, start: Start) -> Result<BestPaths<'_>, DagError> {
+        let order = self.topological_order()?;
+        let mut scores: Vec<Option<f64>> = match start {
+            Start::Node(_) => vec![None; self.len()],
+            Start::Anywhere => vec![Some(0.0); self.len()],
+        };
+        if let Start::Node(node) = start {
+            scores[node] = Some(0.0);
+        }
+        let mut back: Vec<Option<EdgeId>> = vec![None; self.len()];
+        for node in order {
+            let Some(score) = scores[node] else {
+                continue;
+            };
+            for id in &self.outgoing[node] {
+                let edge = &self.edges[*id];
+                let candidate = score + edge.weight;
+                // Ties keep the path found first
+                if scores[edge.to].is_none_or(|current| objective.better(candidate, current)) {
+                    scores[edge.to] = Some(candidate);
+                    back[edge.to] = Some(*id);
+                }
+            }
+        }
+        return Ok(BestPaths {
+            dag: self,
+            objective,
+            scores,
+            back,
+        });
+    }
+
+    /// Every path between the given ends, best first. Paths are only built as they're asked for, so taking the first few
+    /// of a huge graph is cheap.
+    pub fn ranked_paths(&self, objective: Objective, start: Start, end: End) -> Result<RankedPaths<'_>, DagError> {
+        let sign = objective.sign();
+        let order = self.topological_order()?;
+        let mut completion: Vec<Option<f64>> = vec![None; self.len()];
+        for node in order.into_iter().rev() {
+            let mut best = match end {
+                End::Node(target) if target == node => Some(0.0),
+                End::Node(_) => None,
+                End::Anywhere => Some(0.0),
+            };
+            for id in &self.outgoing[node] {
+                let edge = &self.edges[*id];
+                if let Some(rest) = completion[edge.to] {
+                    let candidate = sign * edge.weight + rest;
+                    if best.is_none_or(|b| candidate > b) {
+                        best = Some(candidate);
+                    }
+                }
+            }
+            completion[node] = best;
+        }
+
+        let mut ranked = RankedPaths {
+            dag: self,
+            sign,
+            end,
+            completion,
+            links: Vec::new(),
+            queue: BinaryHeap::new(),
+            pushed: 0,
+        };
+        let starts: Vec<NodeId> = match start {
+            Start::Node(node) => vec![node],
+            Start::Anywhere => (0..self.len()).collect(),
+        };
+        // Pushed last node first, so that ties go to the node added first
+        for node in starts.into_iter().rev() {
+            if let Some(bound) = ranked.completion[node] {
+                ranked.links.push((node, None, None));
+                ranked.push(bound, ranked.links.len() - 1, 0.0, false);
+            }
+        }
+        return Ok(ranked);
+    }
+
+    /// The `k` best paths between the given ends, best first.
+    pub fn k_best_paths(&self, objective: Objective, start: Start, end: End, k: usize) -> Result<Vec<Path>, DagError> {
+        return Ok(self.ranked_paths(objective, start, end)?.take(k).collect());
+    }
+
+    /// Every path between the given ends that scores `threshold` or better, best first.
+    pub fn paths_within(&self, objective: Objective, start: Start, end: End, threshold: f64) -> Result<Vec<Path>, DagError> {
+        let ranked = self.ranked_paths(objective, start, end)?;
+        return Ok(ranked.take_while(|p| !objective.better(threshold, p.score)).collect());
+    }
+
+    /// The best score of a path between the given ends and how many paths share it, or `None` if there are no such
+    /// paths. Counting takes one pass over the graph however many paths there are, and stops at `u128::MAX`.
+    pub fn count_best_paths(&self, objective: Objective, start: Start, end: End) -> Result<Option<(f64, u128)>, DagError> {
+        let sign = objective.sign();
+        let order = self.topological_order()?;
+        // Best signed score into every node, by any path and by paths of at least one edge, with how many paths get it
+        let mut best: Vec<Option<(f64, u128)>> = vec![None; self.len()];
+        let mut nonempty: Vec<Option<(f64, u128)>> = vec![None; self.len()];
+        let merge = |into: &mut Option<(f64, u128)>, score: f64, count: u128| match into {
+            Some((b, c)) if tied(score, *b) => *c = c.saturating_add(count),
+            Some((b, _)) if score < *b => {}
+            _ => *into = Some((score, count)),
+        };
+        for node in order {
+            if let Some((score, count)) = nonempty[node] {
+                merge(&mut best[node], score, count);
+            }
+            if start == Start::Anywhere || start == Start::Node(node) {
+                merge(&mut best[node], 0.0, 1);
+            }
+            let Some((score, count)) = best[node] else {
+                continue;
+            };
+            for id in &self.outgoing[node] {
+                let edge = &self.edges[*id];
+                merge(&mut nonempty[edge.to], score + sign * edge.weight, count);
+            }
+        }
+
+        let result = match end {
+            End::Node(node) => best[node],
+            End::Anywhere => {
+                let mut result = None;
+                for (score, count) in nonempty.into_iter().flatten() {
+                    merge(&mut result, score, count);
+                }
+                result
+            }
+        };
+        return Ok(result.map(|(score, count)| (sign * score, count)));
+    }
+
+    /// Edge labels of a path joined by `separator`.
+    pub fn path_labels(&self, path: &Path, separator: &str) -> String {
+        return path.edges.iter().map(|e| self.edges[*e].label.as_str()).collect::<Vec<&str>>().join(separator);
+    }
+
+    /// A node on a cycle, given the in-degrees left once Kahn's algorithm stalls. Walking back along edges between the
+    /// stalled nodes must come round to a node already seen, which is on a cycle.
+    fn cycle_node(&self, in_degree: &[usize]) -> NodeId {
+        let mut seen = vec![false; self.len()];
+        let mut node = (0..self.len()).find(|n| in_degree[*n] > 0).unwrap();
+        while !seen[node] {
+            seen[node] = true;
+            node = self.incoming[node]
+                .iter()
+                .map(|e| self.edges[*e].from)
+                .find(|from| in_degree[*from] > 0)
+                .unwrap();
+        }
+        return node;
+    }
+}
+
+impl BestPaths<'_> {
+    /// The node with the best score, the first added on ties.
+    pub fn best_end(&self) -> Option<NodeId> {
+        let mut best: Option<(NodeId, f64)> = None;
+        for (node, score) in self.scores.iter().enumerate() {
+            if let Some(score) = score {
+                if best.is_none_or(|(_, b)| self.objective.better(*score, b)) {
+                    best = Some((node, *score));
+                }
+            }
+        }
+        return best.map(|(node, _)| node);
+    }
+
+    /// The best path into the node, by following back-pointers to where it began.
+    pub fn path_to(&self, node: NodeId) -> Option<Path> {
+        let score = self.scores[node]?;
+        let mut nodes = vec![node];
+        let mut edges = Vec::new();
+        let mut current = node;
+        while let Some(edge) = self.back[current] {
+            edges.push(edge);
+            current = self.dag.edges[edge].from;
+            nodes.push(current);
+        }
+        nodes.reverse();
+        edges.reverse();
+        return Some(Path { nodes, edges, score });
+    }
+}
+
+impl RankedPaths<'_> {
+    fn push(&mut self, bound: f64, link: usize, score: f64, complete: bool) {
+        self.queue.push(Candidate {
+            bound,
+            order: self.pushed,
+            link,
+            score,
+            complete,
+        });
+        self.pushed += 1;
+    }
+
+    fn path(&self, link: usize, score: f64) -> Path {
+        let (mut nodes, mut edges) = (Vec::new(), Vec::new());
+        let mut current = Some(link);
+        while let Some(link) = current {
+            let (node, edge, parent) = self.links[link];
+            nodes.push(node);
+            edges.extend(edge);
+            current = parent;
+        }
+        nodes.reverse();
+        edges.reverse();
+        return Path {
+            nodes,
+            edges,
+            score: self.sign * score,
+        };
+    }
+}
+
+impl Iterator for RankedPaths<'_> {
+    type Item = Path;
+
+    fn next(&mut self) -> Option<Path> {
+        while let Some(candidate) = self.queue.pop() {
+            if candidate.complete {
+                return Some(self.path(candidate.link, candidate.score));
+            }
+            let (node, edge, _) = self.links[candidate.link];
+            let finished = match self.end {
+                End::Node(target) => node == target,
+                End::Anywhere => edge.is_some(),
+            };
+            // Pushed last edge first and the finished path after them all, so that among ties the finished path comes
+            // out first and the edges are followed in the order they were added
+            for id in self.dag.outgoing[node].iter().rev() {
+                let edge = &self.dag.edges[*id];
+                if let Some(rest) = self.completion[edge.to] {
+                    let score = candidate.score + self.sign * edge.weight;
+                    self.links.push((edge.to, Some(*id), Some(candidate.link)));
+                    self.push(score + rest, self.links.len() - 1, score, false);
+                }
+            }
+            if finished {
+                self.push(candidate.score, candidate.link, candidate.score, true);
+            }
+        }
+        return None;
+    }
+}
+
+fn tied(a: f64, b: f64) -> bool {
+    return (a - b).abs() <= TIE_TOLERANCE * a.abs().max(b.abs()).max(1.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Nodes 0 to `steps` in a line, with two parallel edges of weight 1 between each pair, so every one of the
+    /// 2^`steps` paths from end to end is optimal.
+    fn ladder(steps: usize) -> Dag {
+        let mut dag = Dag::new();
+        let mut prev = dag.add_node("0");
+        for i in 1..=steps {
+            let node = dag.add_node(&i.to_string());
+            dag.add_edge(prev, node, "a", 1.0);
+            dag.add_edge(prev, node, "b", 1.0);
+            prev = node;
+        }
+        return dag;
+    }
+
+    #[test]
+    fn best_paths_follow_the_heaviest_and_lightest_edges() {
+        let mut dag = Dag::new();
+        let (s, a, b, t) = (dag.add_node("s"), dag.add_node("a"), dag.add_node("b"), dag.add_node("t"));
+        dag.add_edge(s, a, "sa", 2.0);
+        dag.add_edge(s, b, "sb", 1.0);
+        dag.add_edge(a, t, "at", -3.0);
+        dag.add_edge(b, t, "bt", 4.0);
+
+        let longest = dag.best_paths(Objective::Longest, Start::Node(s)).unwrap();
+        assert_eq!(longest.path_to(t).map(|p| p.score), Some(5.0));
+        assert_eq!(longest.path_to(t).unwrap().nodes, vec![s, b, t]);
+        let shortest = dag.best_paths(Objective::Shortest, Start::Node(s)).unwrap();
+        assert_eq!(shortest.path_to(t).unwrap().nodes, vec![s, a, t]);
+        assert_eq!(dag.best_paths(Objective::Longest, Start::Node(a)).unwrap().path_to(b), None);
+
+        // Starting anywhere drops the path into `a` before the loss on `at`
+        let local = dag.best_paths(Objective::Longest, Start::Anywhere).unwrap();
+        let end = local.best_end().unwrap();
+        assert_eq!(end, t);
+        assert_eq!(local.path_to(end).unwrap().nodes, vec![s, b, t]);
+    }
+
+    #[test]
+    fn cycles_are_reported() {
+        let mut dag = Dag::new();
+        let (a, b, c) = (dag.add_node("a"), dag.add_node("b"), dag.add_node("c"));
+        dag.add_edge(a, b, "ab", 1.0);
+        dag.add_edge(b, c, "bc", 1.0);
+        dag.add_edge(c, b, "cb", 1.0);
+        assert!(matches!(dag.topological_order(), Err(DagError::Cycle(node)) if node == "b" || node == "c"));
+        assert!(dag.best_paths(Objective::Longest, Start::Node(a)).is_err());
+    }
+
+    #[test]
+    fn k_best_paths_on_a_ladder_of_ties_are_found_depth_first() {
+        let steps = 64;
+        let dag = ladder(steps);
+        let paths = dag.k_best_paths(Objective::Longest, Start::Node(0), End::Node(steps), 3).unwrap();
+        assert_eq!(paths.len(), 3);
+        for path in &paths {
+            assert_eq!(path.score, steps as f64);
+            assert_eq!(path.edges.len(), steps);
+        }
+        assert_eq!(dag.path_labels(&paths[0], ""), "a".repeat(steps));
+    }
+
+    #[test]
+    fn ranked_paths_come_out_best_first() {
+        let mut dag = Dag::new();
+        let (s, a, b, t) = (dag.add_node("s"), dag.add_node("a"), dag.add_node("b"), dag.add_node("t"));
+        dag.add_edge(s, a, "sa", 3.0);
+        dag.add_edge(s, b, "sb", 1.0);
+        dag.add_edge(a, t, "at", 1.0);
+        dag.add_edge(b, t, "bt", 2.0);
+        dag.add_edge(s, t, "st", 5.0);
+        let paths = dag.k_best_paths(Objective::Longest, Start::Node(s), End::Node(t), 10).unwrap();
+        let scores: Vec<f64> = paths.iter().map(|p| p.score).collect();
+        assert_eq!(scores, vec![5.0, 4.0, 3.0]);
+        assert_eq!(dag.path_labels(&paths[0], ","), "st");
+        assert_eq!(dag.path_labels(&paths[1], ","), "sa,at");
+
+        let shortest = dag.k_best_paths(Objective::Shortest, Start::Node(s), End::Node(t), 1).unwrap();
+        assert_eq!(shortest[0].score, 3.0);
+        let within = dag.paths_within(Objective::Longest, Start::Node(s), End::Node(t), 4.0).unwrap();
+        assert_eq!(within.len(), 2);
+    }
+
+    #[test]
+    fn count_best_paths_counts_ties() {
+        let mut dag = Dag::new();
+        let (s, a, b, t) = (dag.add_node("s"), dag.add_node("a"), dag.add_node("b"), dag.add_node("t"));
+        dag.add_edge(s, a, "sa", 1.0);
+        dag.add_edge(s, b, "sb", 2.0);
+        dag.add_edge(a, t, "at", 2.0);
+        dag.add_edge(b, t, "bt", 1.0);
+        dag.add_edge(s, t, "st", 1.0);
+        assert_eq!(dag.count_best_paths(Objective::Longest, Start::Node(s), End::Node(t)).unwrap(), Some((3.0, 2)));
+        assert_eq!(dag.count_best_paths(Objective::Shortest, Start::Node(s), End::Node(t)).unwrap(), Some((1.0, 1)));
+        assert_eq!(dag.count_best_paths(Objective::Longest, Start::Node(t), End::Node(s)).unwrap(), None);
+
+        let ladder = ladder(100);
+        let (score, count) = ladder.count_best_paths(Objective::Longest, Start::Node(0), End::Node(100)).unwrap().unwrap();
+        assert_eq!((score, count), (100.0, 1 << 100));
+    }
+}
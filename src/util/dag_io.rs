@@ -0,0 +1,366 @@
+use crate::error::LineError;
+use crate::util::dag::{Dag, NodeId, Path as DagPath};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+
+pub const NODE_KEY: &str = "V";
+pub const EDGE_KEY: &str = "E";
+pub const START_KEY: &str = "START";
+pub const END_KEY: &str = "END";
+
+/// Colour the highlighted path is drawn in by `write_dot`.
+const HIGHLIGHT_COLOR: &str = "red";
+
+/// Text formats graphs can be read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DagFormat {
+    /// `V name [START|END]` lines declaring nodes and `E name from to weight` lines joining them, as in `hw4`
+    Dag,
+    /// Tab-separated `from to weight [label]` rows, with nodes declared by the edges that use them
+    EdgeList,
+}
+
+impl DagFormat {
+    /// Guesses the format from a file extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<DagFormat> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        return match extension.as_str() {
+            "dag" | "txt" => Some(DagFormat::Dag),
+            "tsv" | "tab" => Some(DagFormat::EdgeList),
+            _ => None,
+        };
+    }
+}
+
+/// A graph read from a file, with the nodes marked as where paths through it start and end, if any.
+#[derive(Debug, Clone, Default)]
+pub struct MarkedDag {
+    pub dag: Dag,
+    pub start: Option<NodeId>,
+    pub end: Option<NodeId>,
+}
+
+/// Reads a graph. Blank lines and anything after a `#` are ignored, and weights may be any finite number.
+///
+/// Nodes and edges are named uniquely, so a repeated name is an error, as is an edge between nodes that haven't been
+/// declared. The graph isn't checked for cycles, which is left to whatever orders it.
+pub fn read_dag<P: AsRef<Path>>(file_path: P, format: DagFormat) -> io::Result<MarkedDag> {
+    return parse_dag(&fs::read_to_string(file_path)?, format);
+}
+
+pub fn parse_dag(text: &str, format: DagFormat) -> io::Result<MarkedDag> {
+    let lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, l)| (i + 1, l.split('#').next().unwrap().trim()))
+        .filter(|(_, l)| !l.is_empty());
+    return match format {
+        DagFormat::Dag => parse_node_edge(lines),
+        DagFormat::EdgeList => parse_edge_list(lines),
+    };
+}
+
+/// Writes a graph in the `V`/`E` format, marking its start and end nodes.
+pub fn write_dag_to_path<P: AsRef<Path>>(file_path: P, marked: &MarkedDag) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(file_path)?);
+    write_dag(&mut writer, marked)?;
+    return writer.flush();
+}
+
+pub fn write_dag<W: Write>(writer: &mut W, marked: &MarkedDag) -> io::Result<()> {
+    let dag = &marked.dag;
+    for node in 0..dag.len() {
+        let name = writable(dag.name(node))?;
+        if marked.start == Some(node) {
+            writeln!(writer, "{} {} {}", NODE_KEY, name, START_KEY)?;
+        } else if marked.end == Some(node) {
+            writeln!(writer, "{} {} {}", NODE_KEY, name, END_KEY)?;
+        } else {
+            writeln!(writer, "{} {}", NODE_KEY, name)?;
+        }
+    }
+    for edge in dag.edges() {
+        let label = writable(&edge.label)?;
+        writeln!(writer, "{} {} {} {} {}", EDGE_KEY, label, dag.name(edge.from), dag.name(edge.to), edge.weight)?;
+    }
+    return Ok(());
+}
+
+/// Writes a graph as GraphViz DOT, laid out left to right with every edge labelled by its name and weight. The nodes
+/// and edges of `highlight`, e.g. the best path, are drawn bold in red, and marked start and end nodes are doubled.
+pub fn write_dot_to_path<P: AsRef<Path>>(file_path: P, marked: &MarkedDag, highlight: Option<&DagPath>) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(file_path)?);
+    write_dot(&mut writer, marked, highlight)?;
+    return writer.flush();
+}
+
+pub fn write_dot<W: Write>(writer: &mut W, marked: &MarkedDag, highlight: Option<&DagPath>) -> io::Result<()> {
+    let dag = &marked.dag;
+    let nodes: HashSet<NodeId> = highlight.map(|p| p.nodes.iter().copied().collect()).unwrap_or_default();
+    let edges: HashSet<usize> = highlight.map(|p| p.edges.iter().copied().collect()).unwrap_or_default();
+
+    writeln!(writer, "digraph dag {{")?;
+    writeln!(writer, "  rankdir=LR;")?;
+    for node in 0..dag.len() {
+        let mut attributes = Vec::new();
+        if marked.start == Some(node) || marked.end == Some(node) {
+            attributes.push("shape=doublecircle".to_string());
+        }
+        if nodes.contains(&node) {
+            attributes.push(format!("color={}, penwidth=2", HIGHLIGHT_COLOR));
+        }
+        writeln!(writer, "  {}{};", quote(dag.name(node)), dot_attributes(&attributes))?;
+    }
+    for (id, edge) in dag.edges().iter().enumerate() {
+        let mut attributes = vec![format!("label={}", quote(&format!("{} ({})", edge.label, edge.weight)))];
+        if edges.contains(&id) {
+            attributes.push(format!("color={}, fontcolor={}, penwidth=2", HIGHLIGHT_COLOR, HIGHLIGHT_COLOR));
+        }
+        writeln!(
+            writer,
+            "  {} -> {}{};",
+            quote(dag.name(edge.from)),
+            quote(dag.name(edge.to)),
+            dot_attributes(&attributes)
+        )?;
+    }
+    writeln!(writer, "}}")?;
+    return Ok(());
+}
+
+fn parse_node_edge<'a>(lines: impl Iterator<Item = (usize, &'a str)>) -> io::Result<MarkedDag> {
+    let mut marked = MarkedDag::default();
+    // Line every node and edge was named on, to point at the first when a name comes up again
+    let mut node_lines: HashMap<String, usize> = HashMap::new();
+    let mut edge_lines: HashMap<String, usize> = HashMap::new();
+    let mut start_line = 0;
+    let mut end_line = 0;
+
+    for (line_no, line) in lines {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            [NODE_KEY, name, key @ ..] => {
+                if let Some(first) = node_lines.insert(name.to_string(), line_no) {
+                    return Err(invalid(line_no, format!("node '{}' was already declared on line {}", name, first)));
+                }
+                let node = marked.dag.add_node(name);
+                let (mark, mark_line) = match key {
+                    [] => continue,
+                    [START_KEY] => (&mut marked.start, &mut start_line),
+                    [END_KEY] => (&mut marked.end, &mut end_line),
+                    _ => return Err(invalid(line_no, format!("expected '{} name [{}|{}]'", NODE_KEY, START_KEY, END_KEY))),
+                };
+                if mark.is_some() {
+                    return Err(invalid(line_no, format!("a node was already marked {} on line {}", key[0], mark_line)));
+                }
+                *mark = Some(node);
+                *mark_line = line_no;
+            }
+            [EDGE_KEY, name, from, to, weight] => {
+                if let Some(first) = edge_lines.insert(name.to_string(), line_no) {
+                    return Err(invalid(line_no, format!("edge '{}' was already declared on line {}", name, first)));
+                }
+                let from = marked
+                    .dag
+                    .node_id(from)
+                    .ok_or_else(|| invalid(line_no, format!("edge '{}' leaves undeclared node '{}'", name, from)))?;
+                let to = marked
+                    .dag
+                    .node_id(to)
+                    .ok_or_else(|| invalid(line_no, format!("edge '{}' enters undeclared node '{}'", name, to)))?;
+                let weight = parse_weight(line_no, weight)?;
+                marked.dag.add_edge(from, to, name, weight);
+            }
+            _ => {
+                return Err(invalid(
+                    line_no,
+                    format!("expected '{} name [{}|{}]' or '{} name from to weight'", NODE_KEY, START_KEY, END_KEY, EDGE_KEY),
+                ))
+            }
+        }
+    }
+    return Ok(marked);
+}
+
+/// Tab-separated edges, with an optional `from to weight` header. Edges without a label are named after their ends,
+/// so that two unlabelled edges between the same nodes are caught as duplicates.
+fn parse_edge_list<'a>(lines: impl Iterator<Item = (usize, &'a str)>) -> io::Result<MarkedDag> {
+    let mut marked = MarkedDag::default();
+    let mut edge_lines: HashMap<String, usize> = HashMap::new();
+
+    for (i, (line_no, line)) in lines.enumerate() {
+        let fields: Vec<&str> = line.split('\t').map(str::trim).collect();
+        if i == 0 && fields.len() >= 3 && fields[..3] == ["from", "to", "weight"] {
+            continue;
+        }
+        let (from, to, weight, label) = match fields.as_slice() {
+            [from, to, weight] => (*from, *to, *weight, format!("{}->{}", from, to)),
+            [from, to, weight, label] => (*from, *to, *weight, label.to_string()),
+            _ => return Err(invalid(line_no, "expected tab-separated 'from to weight [label]'".to_string())),
+        };
+        if from.is_empty() || to.is_empty() {
+            return Err(invalid(line_no, "edges need a node at both ends".to_string()));
+        }
+        let weight = parse_weight(line_no, weight)?;
+        if let Some(first) = edge_lines.insert(label.clone(), line_no) {
+            return Err(invalid(line_no, format!("edge '{}' was already declared on line {}", label, first)));
+        }
+        let from = marked.dag.add_node(from);
+        let to = marked.dag.add_node(to);
+        marked.dag.add_edge(from, to, &label, weight);
+    }
+    return Ok(marked);
+}
+
+fn parse_weight(line_no: usize, weight: &str) -> io::Result<f64> {
+    return match weight.parse::<f64>() {
+        Ok(w) if w.is_finite() => Ok(w),
+        _ => Err(invalid(line_no, format!("weight '{}' isn't a finite number", weight))),
+    };
+}
+
+/// Names are whitespace-separated in the `V`/`E` format, so names with whitespace or a comment in them can't be
+/// written back.
+fn writable(name: &str) -> io::Result<&str> {
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '#') {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("'{}' can't be written as a node or edge name", name),
+        ));
+    }
+    return Ok(name);
+}
+
+fn quote(s: &str) -> String {
+    return format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+}
+
+fn dot_attributes(attributes: &[String]) -> String {
+    return if attributes.is_empty() {
+        String::new()
+    } else {
+        format!(" [{}]", attributes.join(", "))
+    };
+}
+
+fn invalid(line_no: usize, message: String) -> Error {
+    return LineError::invalid_data(line_no, message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::dag::{DagError, Objective, Start};
+
+    /// Line number and message of a parse error.
+    fn line_error(text: &str, format: DagFormat) -> (usize, String) {
+        let error = parse_dag(text, format).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let line_error = error.get_ref().and_then(|e| e.downcast_ref::<LineError>()).unwrap();
+        return (line_error.line, line_error.message.clone());
+    }
+
+    #[test]
+    fn node_edge_graphs_survive_a_round_trip() {
+        let text = "# a comment\n\
+                    V a START\n\
+                    V b\n\n\
+                    V c END # trailing comment\n\
+                    E ab a b 1.5\n\
+                    E bc b c -2\n\
+                    E ac a c 0\n";
+        let marked = parse_dag(text, DagFormat::Dag).unwrap();
+        assert_eq!(marked.dag.len(), 3);
+        assert_eq!(marked.start, marked.dag.node_id("a"));
+        assert_eq!(marked.end, marked.dag.node_id("c"));
+
+        let mut written = Vec::new();
+        write_dag(&mut written, &marked).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert_eq!(written, "V a START\nV b\nV c END\nE ab a b 1.5\nE bc b c -2\nE ac a c 0\n");
+        let reread = parse_dag(&written, DagFormat::Dag).unwrap();
+        assert_eq!(reread.dag.edges(), marked.dag.edges());
+        assert_eq!((reread.start, reread.end), (marked.start, marked.end));
+    }
+
+    #[test]
+    fn malformed_lines_are_reported_with_their_line_number() {
+        assert_eq!(line_error("V a\nV b\nE ab a b\n", DagFormat::Dag).0, 3);
+        assert_eq!(line_error("V a\nX b\n", DagFormat::Dag).0, 2);
+        assert_eq!(line_error("V a MIDDLE\n", DagFormat::Dag).0, 1);
+        assert_eq!(
+            line_error("V a\nV b\nE ab a b heavy\n", DagFormat::Dag),
+            (3, "weight 'heavy' isn't a finite number".to_string())
+        );
+        assert_eq!(line_error("V a\nV b\nE ab a b inf\n", DagFormat::Dag).0, 3);
+        assert_eq!(
+            line_error("V a START\nV b START\n", DagFormat::Dag),
+            (2, "a node was already marked START on line 1".to_string())
+        );
+        assert_eq!(
+            line_error("V a\n\nV a\n", DagFormat::Dag),
+            (3, "node 'a' was already declared on line 1".to_string())
+        );
+        assert_eq!(
+            line_error("V a\nV b\nE x a b 1\nE x b a 1\n", DagFormat::Dag),
+            (4, "edge 'x' was already declared on line 3".to_string())
+        );
+
+        assert_eq!(line_error("from\tto\tweight\na\tb\n", DagFormat::EdgeList).0, 2);
+        assert_eq!(line_error("a\t\t1\n", DagFormat::EdgeList).0, 1);
+        assert_eq!(
+            line_error("a\tb\t1\nb\tc\t2\na\tb\t3\n", DagFormat::EdgeList),
+            (3, "edge 'a->b' was already declared on line 1".to_string())
+        );
+    }
+
+    #[test]
+    fn edges_between_undeclared_nodes_are_rejected() {
+        assert_eq!(
+            line_error("V a\nE ab a b 1\n", DagFormat::Dag),
+            (2, "edge 'ab' enters undeclared node 'b'".to_string())
+        );
+        // Nodes have to be declared before the edges that use them
+        assert_eq!(
+            line_error("E ab a b 1\nV a\nV b\n", DagFormat::Dag),
+            (1, "edge 'ab' leaves undeclared node 'a'".to_string())
+        );
+    }
+
+    #[test]
+    fn cycles_are_read_and_left_for_the_graph_to_reject() {
+        let marked = parse_dag("V a\nV b\nV c\nE ab a b 1\nE bc b c 1\nE ca c a 1\n", DagFormat::Dag).unwrap();
+        assert!(matches!(marked.dag.topological_order(), Err(DagError::Cycle(_))));
+        let marked = parse_dag("a\tb\t1\nb\ta\t1\tback\n", DagFormat::EdgeList).unwrap();
+        assert_eq!(marked.dag.len(), 2);
+        assert!(marked.dag.topological_order().is_err());
+    }
+
+    #[test]
+    fn dot_output_highlights_the_path_and_doubles_marked_nodes() {
+        let marked = parse_dag("V a START\nV b\nV c END\nE ab a b 1\nE bc b c 2\nE ac a c 1\n", DagFormat::Dag).unwrap();
+        let best = marked.dag.best_paths(Objective::Longest, Start::Node(0)).unwrap().path_to(2).unwrap();
+        let mut written = Vec::new();
+        write_dot(&mut written, &marked, Some(&best)).unwrap();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "digraph dag {\n  \
+               rankdir=LR;\n  \
+               \"a\" [shape=doublecircle, color=red, penwidth=2];\n  \
+               \"b\" [color=red, penwidth=2];\n  \
+               \"c\" [shape=doublecircle, color=red, penwidth=2];\n  \
+               \"a\" -> \"b\" [label=\"ab (1)\", color=red, fontcolor=red, penwidth=2];\n  \
+               \"b\" -> \"c\" [label=\"bc (2)\", color=red, fontcolor=red, penwidth=2];\n  \
+               \"a\" -> \"c\" [label=\"ac (1)\"];\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn names_that_would_not_read_back_are_not_written() {
+        let marked = parse_dag("x y\tz\t1\n", DagFormat::EdgeList).unwrap();
+        assert_eq!(write_dag(&mut Vec::new(), &marked).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,361 @@
+use crate::error::LineError;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Error, Lines, Write};
+use std::path::Path;
+
+/// Sequence line width used by most tools (and by NCBI's downloads).
+pub const DEFAULT_LINE_WIDTH: usize = 60;
+
+/// The set of symbols a record's sequence is allowed to contain.
+/// Membership is case-insensitive and whitespace is always ignored by the readers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Alphabet {
+    allowed: [bool; 256],
+}
+
+impl Alphabet {
+    pub const DNA: Alphabet = Alphabet::new(b"ACGT");
+    pub const DNA_N: Alphabet = Alphabet::new(b"ACGTN");
+    pub const IUPAC: Alphabet = Alphabet::new(b"ACGTURYSWKMBDHVN");
+    pub const PROTEIN: Alphabet = Alphabet::new(b"ARNDCQEGHILKMFPSTWYVBZX");
+
+    pub const fn new(symbols: &[u8]) -> Alphabet {
+        let mut allowed = [false; 256];
+        let mut i = 0;
+        while i < symbols.len() {
+            allowed[symbols[i].to_ascii_uppercase() as usize] = true;
+            allowed[symbols[i].to_ascii_lowercase() as usize] = true;
+            i += 1;
+        }
+        return Alphabet { allowed };
+    }
+
+    pub fn contains(&self, c: u8) -> bool {
+        return self.allowed[c as usize];
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub id: String,
+    pub description: String,
+    pub seq: String,
+    /// Number of symbols outside the alphabet that were dropped from `seq`.
+    /// Always zero unless the reader was created with `skip_invalid`.
+    pub invalid_count: usize,
+}
+
+impl Record {
+    pub fn header(&self) -> String {
+        return if self.description.is_empty() {
+            format!(">{}", self.id)
+        } else {
+            format!(">{} {}", self.id, self.description)
+        };
+    }
+}
+
+/// Streams `Record`s out of a (possibly multi-record) FASTA file.
+pub struct Reader<B: BufRead> {
+    lines: Lines<B>,
+    line_no: usize,
+    pending_header: Option<(usize, String)>,
+    alphabet: Alphabet,
+    skip_invalid: bool,
+    uppercase: bool,
+}
+
+impl Reader<BufReader<File>> {
+    pub fn from_path<P: AsRef<Path>>(file_path: P) -> io::Result<Self> {
+        return Ok(Reader::new(BufReader::new(File::open(file_path)?)));
+    }
+}
+
+impl<B: BufRead> Reader<B> {
+    pub fn new(reader: B) -> Self {
+        return Reader {
+            lines: reader.lines(),
+            line_no: 0,
+            pending_header: None,
+            alphabet: Alphabet::IUPAC,
+            skip_invalid: false,
+            uppercase: false,
+        };
+    }
+
+    pub fn alphabet(mut self, alphabet: Alphabet) -> Self {
+        self.alphabet = alphabet;
+        return self;
+    }
+
+    /// Drop symbols outside the alphabet instead of failing, tallying them in `Record::invalid_count`.
+    pub fn skip_invalid(mut self) -> Self {
+        self.skip_invalid = true;
+        return self;
+    }
+
+    pub fn uppercase(mut self) -> Self {
+        self.uppercase = true;
+        return self;
+    }
+
+    fn next_line(&mut self) -> Option<io::Result<String>> {
+        let line = self.lines.next()?;
+        self.line_no += 1;
+        return Some(line);
+    }
+
+    fn read_record(&mut self, header_line_no: usize, header: String) -> io::Result<Record> {
+        let (id, description) = split_header(&header[1..]);
+        if id.is_empty() {
+            return Err(invalid_data(header_line_no, "record header has no identifier".to_string()));
+        }
+        let mut record = Record {
+            id,
+            description,
+            seq: String::new(),
+            invalid_count: 0,
+        };
+
+        while let Some(line) = self.next_line() {
+            let line = line?;
+            if line.starts_with('>') {
+                self.pending_header = Some((self.line_no, line));
+                break;
+            }
+            if line.starts_with(';') {
+                continue;
+            }
+            for (col, c) in line.bytes().enumerate() {
+                if c.is_ascii_whitespace() {
+                    continue;
+                }
+                if !self.alphabet.contains(c) {
+                    if self.skip_invalid {
+                        record.invalid_count += 1;
+                        continue;
+                    }
+                    return Err(invalid_data(
+                        self.line_no,
+                        format!("unexpected symbol '{}' at column {} in record '{}'", c as char, col + 1, record.id),
+                    ));
+                }
+                record.seq.push(if self.uppercase { c.to_ascii_uppercase() } else { c } as char);
+            }
+        }
+
+        return Ok(record);
+    }
+}
+
+impl<B: BufRead> Iterator for Reader<B> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((line_no, header)) = self.pending_header.take() {
+            return Some(self.read_record(line_no, header));
+        }
+
+        while let Some(line) = self.next_line() {
+            let line = match line {
+                Ok(l) => l,
+                Err(e) => return Some(Err(e)),
+            };
+            if line.starts_with('>') {
+                let line_no = self.line_no;
+                return Some(self.read_record(line_no, line));
+            }
+            if !line.trim().is_empty() && !line.starts_with(';') {
+                return Some(Err(invalid_data(self.line_no, "sequence data before the first '>' header".to_string())));
+            }
+        }
+
+        return None;
+    }
+}
+
+/// Writes FASTA records, wrapping sequence lines at a fixed width.
+///
+/// A record's sequence can be written in pieces with `write_seq`, so long simulated sequences never need to be held in
+/// memory. Call `finish` once done to end the last line and flush.
+pub struct Writer<W: Write> {
+    writer: W,
+    line_width: usize,
+    column: usize,
+}
+
+impl Writer<BufWriter<File>> {
+    pub fn from_path<P: AsRef<Path>>(file_path: P) -> io::Result<Self> {
+        return Ok(Writer::new(BufWriter::new(File::create(file_path)?)));
+    }
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(writer: W) -> Self {
+        return Writer {
+            writer,
+            line_width: DEFAULT_LINE_WIDTH,
+            column: 0,
+        };
+    }
+
+    /// Bases per sequence line. Zero writes each sequence on a single line.
+    pub fn line_width(mut self, line_width: usize) -> Self {
+        self.line_width = line_width;
+        return self;
+    }
+
+    /// Starts a new record, ending the previous record's last line.
+    pub fn write_header(&mut self, id: &str, description: &str) -> io::Result<()> {
+        self.end_line()?;
+        return if description.is_empty() {
+            writeln!(self.writer, ">{}", id)
+        } else {
+            writeln!(self.writer, ">{} {}", id, description)
+        };
+    }
+
+    /// Appends sequence to the current record, carrying the line wrapping on from the previous call.
+    pub fn write_seq(&mut self, seq: &[u8]) -> io::Result<()> {
+        if self.line_width == 0 {
+            self.column += seq.len();
+            return self.writer.write_all(seq);
+        }
+
+        let mut rest = seq;
+        while !rest.is_empty() {
+            if self.column == self.line_width {
+                self.end_line()?;
+            }
+            let take = rest.len().min(self.line_width - self.column);
+            self.writer.write_all(&rest[..take])?;
+            self.column += take;
+            rest = &rest[take..];
+        }
+        return Ok(());
+    }
+
+    /// Ends the last line and flushes, handing back the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.end_line()?;
+        self.writer.flush()?;
+        return Ok(self.writer);
+    }
+
+    fn end_line(&mut self) -> io::Result<()> {
+        if self.column > 0 {
+            self.column = 0;
+            return writeln!(self.writer);
+        }
+        return Ok(());
+    }
+}
+
+fn split_header(header: &str) -> (String, String) {
+    let header = header.trim();
+    return match header.split_once(char::is_whitespace) {
+        Some((id, desc)) => (id.to_string(), desc.trim().to_string()),
+        None => (header.to_string(), String::new()),
+    };
+}
+
+fn invalid_data(line_no: usize, message: String) -> Error {
+    return LineError::invalid_data(line_no, message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    fn read(text: &str, alphabet: Alphabet) -> Vec<Record> {
+        return Reader::new(text.as_bytes()).alphabet(alphabet).collect::<io::Result<Vec<Record>>>().unwrap();
+    }
+
+    /// Line number and message of a parse error.
+    fn line_error<B: BufRead>(reader: Reader<B>) -> (usize, String) {
+        let error = reader.collect::<io::Result<Vec<Record>>>().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let line_error = error.get_ref().and_then(|e| e.downcast_ref::<LineError>()).unwrap();
+        return (line_error.line, line_error.message.clone());
+    }
+
+    #[test]
+    fn every_record_of_a_multi_record_file_is_read_across_blank_lines() {
+        let records = read(
+            "\n; a comment\n>chr1 first contig\nACGT\n\nac gt\n>chr2\n\n>chr3  third\tcontig \nNNRY\n",
+            Alphabet::IUPAC,
+        );
+        let summary: Vec<(&str, &str, &str)> = records.iter().map(|r| (r.id.as_str(), r.description.as_str(), r.seq.as_str())).collect();
+        assert_eq!(
+            summary,
+            vec![("chr1", "first contig", "ACGTacgt"), ("chr2", "", ""), ("chr3", "third\tcontig", "NNRY")]
+        );
+        assert_eq!(records[0].header(), ">chr1 first contig");
+        assert_eq!(records[1].header(), ">chr2");
+    }
+
+    #[test]
+    fn lowercase_is_kept_unless_asked_for_uppercase() {
+        assert_eq!(read(">a\nacGT\n", Alphabet::DNA)[0].seq, "acGT");
+        let record = Reader::new(">a\nacGT\n".as_bytes())
+            .alphabet(Alphabet::DNA)
+            .uppercase()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.seq, "ACGT");
+    }
+
+    #[test]
+    fn symbols_outside_the_alphabet_are_errors_unless_skipped() {
+        assert_eq!(
+            line_error(Reader::new(">a\nACGT\nACNT\n".as_bytes()).alphabet(Alphabet::DNA)),
+            (3, "unexpected symbol 'N' at column 3 in record 'a'".to_string())
+        );
+        assert_eq!(
+            line_error(Reader::new(">p\nMKV*\n".as_bytes()).alphabet(Alphabet::PROTEIN)),
+            (2, "unexpected symbol '*' at column 4 in record 'p'".to_string())
+        );
+
+        let records: Vec<Record> = Reader::new(">p\nMKV*\nm-k\n>q\nAC\n".as_bytes())
+            .alphabet(Alphabet::PROTEIN)
+            .skip_invalid()
+            .uppercase()
+            .collect::<io::Result<Vec<Record>>>()
+            .unwrap();
+        assert_eq!((records[0].seq.as_str(), records[0].invalid_count), ("MKVMK", 2));
+        assert_eq!((records[1].seq.as_str(), records[1].invalid_count), ("AC", 0));
+    }
+
+    #[test]
+    fn files_that_are_not_fasta_are_rejected_with_their_line_number() {
+        assert_eq!(
+            line_error(Reader::new("\nACGT\n>a\nACGT\n".as_bytes())),
+            (2, "sequence data before the first '>' header".to_string())
+        );
+        assert_eq!(
+            line_error(Reader::new(">a\nACGT\n> \nACGT\n".as_bytes())),
+            (3, "record header has no identifier".to_string())
+        );
+    }
+
+    #[test]
+    fn sequences_are_wrapped_across_pieces_and_read_back() {
+        let mut writer = Writer::new(Vec::new()).line_width(4);
+        writer.write_header("a", "two pieces").unwrap();
+        writer.write_seq(b"ACG").unwrap();
+        writer.write_seq(b"TACGTA").unwrap();
+        writer.write_header("b", "").unwrap();
+        writer.write_seq(b"ACGT").unwrap();
+        let written = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(written, ">a two pieces\nACGT\nACGT\nA\n>b\nACGT\n");
+        assert_eq!(read(&written, Alphabet::DNA)[0].seq, "ACGTACGTA");
+
+        let mut writer = Writer::new(Vec::new()).line_width(0);
+        writer.write_header("a", "").unwrap();
+        writer.write_seq(b"ACGT").unwrap();
+        writer.write_seq(b"ACGT").unwrap();
+        assert_eq!(writer.finish().unwrap(), b">a\nACGTACGT\n");
+    }
+}
@@ -0,0 +1,540 @@
+use crate::error::LineError;
+use crate::util::location::{self, LocationError};
+use crate::util::strand::Strand;
+use gb_io::seq::{Feature as GbFeature, Seq};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Error, Write};
+use std::path::Path;
+
+/// Feature type given to BED intervals, which don't carry one.
+pub const BED_KIND: &str = "region";
+
+/// Characters GFF3 reserves in column 9, which must be percent-encoded inside tags and values.
+const GFF3_RESERVED: &[char] = &[';', '=', '&', ',', '%', '\t', '\n', '\r'];
+
+/// Tab-separated formats for exchanging features with genome browsers and interval tools.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureFormat {
+    /// BED6, or BED12 for features with blocks or a thick part
+    Bed,
+    /// GFF3, with discontinuous features written as several lines sharing an `ID`
+    Gff3,
+}
+
+impl FeatureFormat {
+    /// Guesses the format from a file extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<FeatureFormat> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        return match extension.as_str() {
+            "bed" => Some(FeatureFormat::Bed),
+            "gff" | "gff3" => Some(FeatureFormat::Gff3),
+            _ => None,
+        };
+    }
+}
+
+/// A feature on a named sequence, the common ground between GenBank, GFF3 and BED.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Feature {
+    pub seq_id: String,
+    /// Program or database the feature came from, GFF3's second column
+    pub source: Option<String>,
+    /// Feature type, e.g. `CDS`, or `region` for BED intervals
+    pub kind: String,
+    /// 0-based, exclusive bounds covering every block
+    pub start: i64,
+    pub end: i64,
+    pub strand: Option<Strand>,
+    /// Written to BED rounded and clamped to its 0 to 1000 range, so a score outside that belongs in the name too
+    pub score: Option<f64>,
+    /// Bases to skip to reach the first whole codon of a CDS, in its 5'-most piece when it has several
+    pub phase: Option<u8>,
+    pub name: Option<String>,
+    /// 0-based, exclusive bounds of every piece of a discontinuous feature, such as the exons of a spliced CDS, in
+    /// order along the forward strand. Empty for a feature in one piece.
+    pub blocks: Vec<(i64, i64)>,
+    /// The part drawn thick by genome browsers, usually the coding part of a transcript
+    pub thick: Option<(i64, i64)>,
+    /// GFF3 attributes in file order, or GenBank qualifiers
+    pub attributes: Vec<(String, String)>,
+}
+
+impl Feature {
+    /// A feature in one piece with nothing but its position.
+    pub fn new(seq_id: &str, kind: &str, start: i64, end: i64, strand: Option<Strand>) -> Feature {
+        return Feature {
+            seq_id: seq_id.to_string(),
+            source: None,
+            kind: kind.to_string(),
+            start,
+            end,
+            strand,
+            score: None,
+            phase: None,
+            name: None,
+            blocks: Vec::new(),
+            thick: None,
+            attributes: Vec::new(),
+        };
+    }
+
+    /// Converts a GenBank feature of `seq`, keeping its qualifiers as attributes and naming it after its gene or locus
+    /// tag. Features spanning the origin of a circular sequence end past its length.
+    pub fn from_genbank(seq: &Seq, feature: &GbFeature) -> Result<Feature, LocationError> {
+        let (start, end) = location::bounds(&feature.location, seq)?;
+        let spans = location::spans(&feature.location)?;
+        // Pieces from before the origin come after the end of the sequence
+        let mut blocks: Vec<(i64, i64)> = spans
+            .iter()
+            .map(|s| {
+                if s.start < start {
+                    (s.start + seq.len(), s.end + seq.len())
+                } else {
+                    (s.start, s.end)
+                }
+            })
+            .collect();
+        blocks.sort();
+        // A join across the origin is one piece once its second half is shifted past the end
+        blocks.dedup_by(|next, last| {
+            if next.0 == last.1 {
+                last.1 = next.1;
+            }
+            return next.0 <= last.1 && next.1 <= last.1;
+        });
+        if blocks.len() == 1 {
+            blocks.clear();
+        }
+        let qualifier = |key: &str| feature.qualifiers.iter().find(|(k, _)| &**k == key).and_then(|(_, v)| v.clone());
+        return Ok(Feature {
+            seq_id: seq.name.clone().or_else(|| seq.accession.clone()).unwrap_or_default(),
+            source: None,
+            kind: feature.kind.to_string(),
+            start,
+            end,
+            strand: Some(location::strand(&feature.location)?),
+            score: None,
+            phase: match &*feature.kind {
+                "CDS" => Some(
+                    qualifier("codon_start")
+                        .and_then(|c| c.parse::<u8>().ok())
+                        .map_or(0, |c| c.saturating_sub(1) % 3),
+                ),
+                _ => None,
+            },
+            name: qualifier("gene").or_else(|| qualifier("locus_tag")),
+            blocks,
+            thick: None,
+            attributes: feature.qualifiers.iter().filter_map(|(k, v)| Some((k.to_string(), v.clone()?))).collect(),
+        });
+    }
+
+    /// Every piece of the feature, which is just its bounds when it has no blocks.
+    pub fn pieces(&self) -> Vec<(i64, i64)> {
+        return if self.blocks.is_empty() {
+            vec![(self.start, self.end)]
+        } else {
+            self.blocks.clone()
+        };
+    }
+
+    /// First value of an attribute.
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        return self.attributes.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+    }
+}
+
+/// Every feature of a GenBank record that can be placed on it, skipping those on other entries or gaps.
+pub fn from_seq(seq: &Seq) -> Vec<Feature> {
+    return seq.features.iter().filter_map(|f| Feature::from_genbank(seq, f).ok()).collect();
+}
+
+pub fn read_features<P: AsRef<Path>>(file_path: P, format: FeatureFormat) -> io::Result<Vec<Feature>> {
+    return parse_features(&fs::read_to_string(file_path)?, format);
+}
+
+pub fn parse_features(text: &str, format: FeatureFormat) -> io::Result<Vec<Feature>> {
+    let lines = text.lines().enumerate().map(|(i, l)| (i + 1, l.trim_end_matches('\r')));
+    return match format {
+        FeatureFormat::Bed => parse_bed(lines),
+        FeatureFormat::Gff3 => parse_gff3(lines),
+    };
+}
+
+pub fn write_features_to_path<P: AsRef<Path>>(file_path: P, features: &[Feature], format: FeatureFormat) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(file_path)?);
+    write_features(&mut writer, features, format)?;
+    return writer.flush();
+}
+
+pub fn write_features<W: Write>(writer: &mut W, features: &[Feature], format: FeatureFormat) -> io::Result<()> {
+    match format {
+        FeatureFormat::Bed => {
+            for f in features {
+                write_bed_line(writer, f)?;
+            }
+        }
+        FeatureFormat::Gff3 => write_gff3(writer, features, &[])?,
+    }
+    return Ok(());
+}
+
+/// Writes GFF3 with a `##sequence-region` line for each `(seq_id, length)` in `regions`, so that tools know how long
+/// the sequences are.
+pub fn write_gff3<W: Write>(writer: &mut W, features: &[Feature], regions: &[(&str, i64)]) -> io::Result<()> {
+    writeln!(writer, "##gff-version 3")?;
+    for (seq_id, len) in regions {
+        writeln!(writer, "##sequence-region {} 1 {}", encode(seq_id), len)?;
+    }
+    for (i, f) in features.iter().enumerate() {
+        write_gff3_lines(writer, f, i + 1)?;
+    }
+    return Ok(());
+}
+
+/// Reads BED3 up to BED12. Blocks are checked to lie inside the interval, and `track` and `browser` lines are skipped.
+fn parse_bed<'a, I: Iterator<Item = (usize, &'a str)>>(lines: I) -> io::Result<Vec<Feature>> {
+    let mut features = Vec::new();
+    for (line_no, line) in lines {
+        if line.trim().is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').map(|f| f.trim()).collect();
+        if fields.len() < 3 {
+            return Err(invalid(line_no, "expected 'chrom start end [name score strand ...]'".to_string()));
+        }
+        let position = |i: usize| -> io::Result<i64> {
+            return fields[i].parse().map_err(|_| invalid(line_no, format!("'{}' is not a position", fields[i])));
+        };
+        let (start, end) = (position(1)?, position(2)?);
+        if start < 0 || end < start {
+            return Err(invalid(line_no, format!("'{}..{}' is not an interval", start, end)));
+        }
+
+        let mut feature = Feature::new(fields[0], BED_KIND, start, end, None);
+        feature.name = fields.get(3).filter(|n| !n.is_empty() && **n != ".").map(|n| n.to_string());
+        feature.score = match fields.get(4) {
+            Some(&".") | None => None,
+            Some(s) => Some(s.parse().map_err(|_| invalid(line_no, format!("'{}' is not a score", s)))?),
+        };
+        feature.strand = match fields.get(5) {
+            Some(s) => parse_strand(s).ok_or_else(|| invalid(line_no, format!("'{}' is not a strand", s)))?,
+            None => None,
+        };
+        // An empty thick part, conventionally at the start, means there's none
+        if fields.len() >= 8 && fields[6] != fields[7] {
+            feature.thick = Some((position(6)?, position(7)?));
+        }
+        if fields.len() >= 12 {
+            let count: usize = fields[9]
+                .parse()
+                .map_err(|_| invalid(line_no, format!("'{}' is not a block count", fields[9])))?;
+            let sizes = parse_list(line_no, fields[10])?;
+            let starts = parse_list(line_no, fields[11])?;
+            if sizes.len() != count || starts.len() != count {
+                return Err(invalid(line_no, format!("expected {} block sizes and starts", count)));
+            }
+            feature.blocks = starts.iter().zip(&sizes).map(|(s, l)| (start + s, start + s + l)).collect();
+            if feature.blocks.iter().any(|(s, e)| *s < start || *e > end) {
+                return Err(invalid(line_no, "blocks run outside the interval".to_string()));
+            }
+            if count == 1 {
+                feature.blocks.clear();
+            }
+        }
+        features.push(feature);
+    }
+    return Ok(features);
+}
+
+/// Reads GFF3 up to a `##FASTA` section. Lines of the same type that share an `ID` on the same sequence are one
+/// discontinuous feature, which becomes a feature with blocks.
+fn parse_gff3<'a, I: Iterator<Item = (usize, &'a str)>>(lines: I) -> io::Result<Vec<Feature>> {
+    let mut features: Vec<Feature> = Vec::new();
+    let mut by_id: HashMap<(String, String, String), usize> = HashMap::new();
+    for (line_no, line) in lines {
+        if line.starts_with("##FASTA") {
+            break;
+        }
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 9 {
+            return Err(invalid(line_no, format!("expected 9 tab-separated columns, found {}", fields.len())));
+        }
+        let position = |i: usize| -> io::Result<i64> {
+            return fields[i].parse().map_err(|_| invalid(line_no, format!("'{}' is not a position", fields[i])));
+        };
+        // GFF3 positions are 1-based and inclusive
+        let (start, end) = (position(3)? - 1, position(4)?);
+        if start < 0 || end < start {
+            return Err(invalid(line_no, format!("'{}..{}' is not an interval", fields[3], fields[4])));
+        }
+
+        let mut feature = Feature::new(&decode(fields[0]), &decode(fields[2]), start, end, None);
+        feature.source = Some(decode(fields[1])).filter(|s| s != ".");
+        feature.score = match fields[5] {
+            "." => None,
+            s => Some(s.parse().map_err(|_| invalid(line_no, format!("'{}' is not a score", s)))?),
+        };
+        feature.strand = parse_strand(fields[6]).ok_or_else(|| invalid(line_no, format!("'{}' is not a strand", fields[6])))?;
+        feature.phase = match fields[7] {
+            "." => None,
+            "0" => Some(0),
+            "1" => Some(1),
+            "2" => Some(2),
+            p => return Err(invalid(line_no, format!("'{}' is not a phase", p))),
+        };
+        for attribute in fields[8].split(';').map(|a| a.trim()).filter(|a| !a.is_empty() && *a != ".") {
+            let (key, value) = attribute
+                .split_once('=')
+                .ok_or_else(|| invalid(line_no, format!("attribute '{}' has no '='", attribute)))?;
+            feature.attributes.push((decode(key), decode(value)));
+        }
+        feature.name = feature.attribute("Name").or(feature.attribute("ID")).map(|n| n.to_string());
+
+        let Some(id) = feature.attribute("ID").map(|id| id.to_string()) else {
+            features.push(feature);
+            continue;
+        };
+        let key = (feature.seq_id.clone(), feature.kind.clone(), id);
+        match by_id.get(&key) {
+            Some(i) => {
+                let existing = &mut features[*i];
+                // The phase kept is that of the 5'-most piece, which comes last on the reverse strand
+                let five_prime = match feature.strand {
+                    Some(Strand::Reverse) => feature.end > existing.end,
+                    _ => feature.start < existing.start,
+                };
+                if five_prime {
+                    existing.phase = feature.phase;
+                }
+                let mut blocks = existing.pieces();
+                blocks.push((feature.start, feature.end));
+                blocks.sort();
+                existing.start = existing.start.min(feature.start);
+                existing.end = existing.end.max(feature.end);
+                existing.blocks = blocks;
+            }
+            None => {
+                by_id.insert(key, features.len());
+                features.push(feature);
+            }
+        }
+    }
+    return Ok(features);
+}
+
+/// BED scores are whole numbers from 0 to 1000, which genome browsers shade features by and reject anything else.
+fn bed_score(score: f64) -> i64 {
+    return score.round().clamp(0.0, 1000.0) as i64;
+}
+
+fn write_bed_line<W: Write>(writer: &mut W, f: &Feature) -> io::Result<()> {
+    write!(
+        writer,
+        "{}\t{}\t{}\t{}\t{}\t{}",
+        f.seq_id,
+        f.start,
+        f.end,
+        f.name.as_deref().unwrap_or("."),
+        f.score.map_or(0, bed_score),
+        strand_symbol(f.strand)
+    )?;
+    if !f.blocks.is_empty() || f.thick.is_some() {
+        let (thick_start, thick_end) = f.thick.unwrap_or((f.start, f.start));
+        let pieces = f.pieces();
+        let sizes: Vec<String> = pieces.iter().map(|(s, e)| (e - s).to_string()).collect();
+        let starts: Vec<String> = pieces.iter().map(|(s, _)| (s - f.start).to_string()).collect();
+        write!(
+            writer,
+            "\t{}\t{}\t0\t{}\t{},\t{},",
+            thick_start,
+            thick_end,
+            pieces.len(),
+            sizes.join(","),
+            starts.join(",")
+        )?;
+    }
+    return writeln!(writer);
+}
+
+/// Writes one line per piece. Features without an `ID` are given one when they have several pieces, since that's
+/// what ties the lines together.
+fn write_gff3_lines<W: Write>(writer: &mut W, f: &Feature, number: usize) -> io::Result<()> {
+    let mut attributes = f.attributes.clone();
+    if f.attribute("Name").is_none() && f.attribute("ID") != f.name.as_deref() {
+        if let Some(name) = &f.name {
+            attributes.insert(0, ("Name".to_string(), name.clone()));
+        }
+    }
+    if f.attribute("ID").is_none() && !f.blocks.is_empty() {
+        attributes.insert(0, ("ID".to_string(), format!("{}_{}", f.kind, number)));
+    }
+    let attributes = if attributes.is_empty() {
+        ".".to_string()
+    } else {
+        attributes
+            .iter()
+            .map(|(k, v)| format!("{}={}", encode(k), encode(v)))
+            .collect::<Vec<String>>()
+            .join(";")
+    };
+    let pieces = f.pieces();
+    let phases = phases(f, &pieces);
+    for ((start, end), phase) in pieces.into_iter().zip(phases) {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            encode(&f.seq_id),
+            f.source.as_deref().map_or(".".to_string(), encode),
+            encode(&f.kind),
+            start + 1,
+            end,
+            f.score.map_or(".".to_string(), |s| s.to_string()),
+            strand_symbol(f.strand),
+            phase.map_or(".".to_string(), |p| p.to_string()),
+            attributes
+        )?;
+    }
+    return Ok(());
+}
+
+/// Phase of every piece, worked out from the phase of the first piece read along the strand, since codons carry on
+/// from one piece into the next.
+fn phases(f: &Feature, pieces: &[(i64, i64)]) -> Vec<Option<u8>> {
+    let Some(first) = f.phase else {
+        return vec![None; pieces.len()];
+    };
+    let mut order: Vec<usize> = (0..pieces.len()).collect();
+    if f.strand == Some(Strand::Reverse) {
+        order.reverse();
+    }
+    let mut phases = vec![None; pieces.len()];
+    let mut read = 0;
+    for i in order {
+        phases[i] = Some(((3 - (read - first as i64).rem_euclid(3)) % 3) as u8);
+        read += pieces[i].1 - pieces[i].0;
+    }
+    return phases;
+}
+
+fn parse_strand(s: &str) -> Option<Option<Strand>> {
+    return match s {
+        "+" => Some(Some(Strand::Forward)),
+        "-" => Some(Some(Strand::Reverse)),
+        "." | "?" => Some(None),
+        _ => None,
+    };
+}
+
+fn strand_symbol(strand: Option<Strand>) -> char {
+    return match strand {
+        Some(Strand::Forward) => '+',
+        Some(Strand::Reverse) => '-',
+        None => '.',
+    };
+}
+
+/// Comma-separated BED12 block sizes or starts, which usually end with a trailing comma.
+fn parse_list(line_no: usize, field: &str) -> io::Result<Vec<i64>> {
+    return field
+        .split(',')
+        .filter(|v| !v.is_empty())
+        .map(|v| v.trim().parse().map_err(|_| invalid(line_no, format!("'{}' is not a block size or start", v))))
+        .collect();
+}
+
+/// Undoes GFF3 percent-encoding, e.g. `%3B` back to `;`. Malformed escapes are left as they are.
+fn decode(s: &str) -> String {
+    if !s.contains('%') {
+        return s.to_string();
+    }
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(b)) => {
+                decoded.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    return String::from_utf8_lossy(&decoded).into_owned();
+}
+
+fn encode(s: &str) -> String {
+    return s
+        .chars()
+        .map(|c| {
+            if GFF3_RESERVED.contains(&c) {
+                format!("%{:02X}", c as u32)
+            } else {
+                c.to_string()
+            }
+        })
+        .collect();
+}
+
+fn invalid(line_no: usize, message: String) -> Error {
+    return LineError::invalid_data(line_no, message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(text: &str, format: FeatureFormat) -> String {
+        let features = parse_features(text, format).unwrap();
+        let mut written = Vec::new();
+        write_features(&mut written, &features, format).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert_eq!(parse_features(&written, format).unwrap(), features);
+        return written;
+    }
+
+    #[test]
+    fn gff3_keeps_the_phases_of_spliced_features_on_both_strands() {
+        let forward = "##gff-version 3\n\
+                       chr1\t.\tCDS\t1\t50\t.\t+\t0\tID=cds1\n\
+                       chr1\t.\tCDS\t201\t300\t.\t+\t1\tID=cds1\n";
+        assert_eq!(round_trip(forward, FeatureFormat::Gff3), forward);
+
+        // The 5' piece of a reverse-strand feature is the last one, and its phase carries into the one before it
+        let reverse = "##gff-version 3\n\
+                       chr1\t.\tCDS\t1\t50\t.\t-\t2\tID=cds1\n\
+                       chr1\t.\tCDS\t201\t300\t.\t-\t0\tID=cds1\n";
+        let features = parse_features(reverse, FeatureFormat::Gff3).unwrap();
+        assert_eq!(features.len(), 1);
+        assert_eq!(features[0].phase, Some(0));
+        assert_eq!(features[0].blocks, vec![(0, 50), (200, 300)]);
+        assert_eq!(round_trip(reverse, FeatureFormat::Gff3), reverse);
+    }
+
+    #[test]
+    fn bed_keeps_the_blocks_of_spliced_features_on_both_strands() {
+        let bed = "chr1\t0\t300\tfwd\t0\t+\t0\t300\t0\t2\t50,100,\t0,200,\n\
+                   chr1\t1000\t1300\trev\t0\t-\t1000\t1300\t0\t2\t50,100,\t0,200,\n";
+        assert_eq!(round_trip(bed, FeatureFormat::Bed), bed);
+        let features = parse_features(bed, FeatureFormat::Bed).unwrap();
+        assert_eq!(features[1].strand, Some(Strand::Reverse));
+        assert_eq!(features[1].blocks, vec![(1000, 1050), (1200, 1300)]);
+    }
+
+    #[test]
+    fn bed_lines_with_blocks_outside_the_interval_are_rejected_with_their_line_number() {
+        let error = parse_features("chr1\t0\t100\n\nchr1\t0\t100\tx\t0\t+\t0\t100\t0\t1\t50,\t60,\n", FeatureFormat::Bed).unwrap_err();
+        assert!(error.to_string().contains("line 3"), "{}", error);
+    }
+}
@@ -0,0 +1,497 @@
+use crate::util::nucleotide::reverse_complement;
+use crate::util::strand::Strand;
+use crate::util::suffix_array::SuffixArray;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"BIOFMI02";
+const OCC_RATE: usize = 64;
+/// BWT code shared by every sequence separator. Real symbols are numbered from 1 in byte order.
+const SEPARATOR: u8 = 0;
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// One located occurrence of a pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IndexHit {
+    /// Index of the sequence among those the index was built over
+    pub sequence: usize,
+    /// 0-based start on the forward strand
+    pub start: usize,
+    /// `Reverse` when it was the pattern's reverse complement that matched the forward strand
+    pub strand: Strand,
+}
+
+/// FM-index (Ferragina & Manzini 2000) over the forward strands of a set of sequences.
+///
+/// Stores the Burrows-Wheeler transform, occurrence counts checkpointed every `OCC_RATE` rows and every
+/// `sample_rate`-th suffix array value, so the full suffix array can be dropped once the index is built. The length and
+/// a checksum of every sequence are kept too, so that a saved index can be checked against the sequences it's for.
+pub struct FmIndex {
+    symbols: Vec<u8>,
+    codes: Vec<u8>,
+    bwt: Vec<u8>,
+    counts: Vec<usize>,
+    occ: Vec<usize>,
+    sample_rate: usize,
+    sampled: Vec<u64>,
+    sampled_ranks: Vec<usize>,
+    samples: Vec<usize>,
+    starts: Vec<usize>,
+    lengths: Vec<usize>,
+    checksum: u64,
+}
+
+impl FmIndex {
+    /// Builds the index over some of the sequences of a suffix array, given by their indices in increasing order, so
+    /// the reverse strands that share the array can be left out. Every sequence indexed must be a forward strand.
+    pub fn new(suffix_array: &SuffixArray, sequences: &[usize], sample_rate: usize) -> FmIndex {
+        assert!(sequences.windows(2).all(|w| w[0] < w[1]), "Indexed sequences must be in increasing order");
+        let text = suffix_array.text();
+        let k = suffix_array.sequence_count();
+        let starts: Vec<usize> = sequences.iter().map(|s| suffix_array.sequence_start(*s)).collect();
+        let lengths: Vec<usize> = sequences.iter().map(|s| suffix_array.sequence_len(*s)).collect();
+        let bases: Vec<Vec<u8>> = starts
+            .iter()
+            .zip(&lengths)
+            .map(|(start, len)| text[*start..*start + len].iter().map(|t| (*t as usize - k) as u8).collect())
+            .collect();
+        let checksum = checksum(bases.iter().map(|s| s.as_slice()));
+
+        // The suffix array leaves out the suffixes that begin at separators. Separator `i` sorts before separator
+        // `i + 1` and before every real symbol, so those suffixes are simply the first rows in sequence order. Leaving
+        // out whole sequences keeps the rest sorted, as no suffix is compared past its own separator.
+        let full_sa: Vec<usize> = starts
+            .iter()
+            .zip(&lengths)
+            .map(|(start, len)| start + len)
+            .chain(
+                suffix_array
+                    .suffixes()
+                    .iter()
+                    .copied()
+                    .filter(|p| sequences.binary_search(&suffix_array.source(*p).0).is_ok()),
+            )
+            .collect();
+        let n = full_sa.len();
+
+        let mut present = [false; 256];
+        for b in bases.iter().flatten() {
+            present[*b as usize] = true;
+        }
+        let mut symbols = vec![SEPARATOR];
+        let mut codes = vec![SEPARATOR; 256];
+        for b in 0..256 {
+            if present[b] {
+                codes[b] = symbols.len() as u8;
+                symbols.push(b as u8);
+            }
+        }
+        let to_code = |t: u32| if (t as usize) < k { SEPARATOR } else { codes[t as usize - k] };
+
+        let sigma = symbols.len();
+        let mut bwt = Vec::with_capacity(n);
+        let mut occ = Vec::with_capacity((n / OCC_RATE + 1) * sigma);
+        let mut running = vec![0; sigma];
+        let mut sampled = vec![0_u64; n / 64 + 1];
+        let mut samples = Vec::new();
+        for (row, pos) in full_sa.iter().enumerate() {
+            if row % OCC_RATE == 0 {
+                occ.extend_from_slice(&running);
+            }
+            let code = if *pos == 0 { SEPARATOR } else { to_code(text[*pos - 1]) };
+            running[code as usize] += 1;
+            bwt.push(code);
+
+            // Sequence starts are always sampled so that walking the LF mapping never crosses a separator
+            if pos % sample_rate == 0 || starts.binary_search(pos).is_ok() {
+                sampled[row / 64] |= 1 << (row % 64);
+                samples.push(*pos);
+            }
+        }
+        // Queries at row `n` need a checkpoint for the block past the end when `n` lands on a block boundary
+        if occ.len() < (n / OCC_RATE + 1) * sigma {
+            occ.extend_from_slice(&running);
+        }
+
+        let mut counts = vec![0; sigma + 1];
+        for c in 0..sigma {
+            counts[c + 1] = counts[c] + running[c];
+        }
+
+        let sampled_ranks = rank_blocks(&sampled);
+
+        return FmIndex {
+            symbols,
+            codes,
+            bwt,
+            counts,
+            occ,
+            sample_rate,
+            sampled,
+            sampled_ranks,
+            samples,
+            starts,
+            lengths,
+            checksum,
+        };
+    }
+
+    /// Number of occurrences of the pattern on the forward and reverse strands.
+    pub fn count(&self, pattern: &[u8]) -> (usize, usize) {
+        let forward = self.backward_search(pattern).map(|(lo, hi)| hi - lo).unwrap_or(0);
+        let reverse = self.backward_search(&reverse_complement(pattern)).map(|(lo, hi)| hi - lo).unwrap_or(0);
+        return (forward, reverse);
+    }
+
+    /// Every occurrence of the pattern on either strand, sorted by sequence and position.
+    pub fn locate(&self, pattern: &[u8]) -> Vec<IndexHit> {
+        let mut hits = Vec::new();
+        for (strand, query) in [(Strand::Forward, pattern.to_vec()), (Strand::Reverse, reverse_complement(pattern))] {
+            if let Some((lo, hi)) = self.backward_search(&query) {
+                for row in lo..hi {
+                    let pos = self.locate_row(row);
+                    let sequence = self.starts.partition_point(|s| *s <= pos) - 1;
+                    hits.push(IndexHit {
+                        sequence,
+                        start: pos - self.starts[sequence],
+                        strand,
+                    });
+                }
+            }
+        }
+        hits.sort();
+        return hits;
+    }
+
+    /// Whether the index was built from exactly these sequences, in this order, judged by their lengths and checksum.
+    pub fn indexes(&self, sequences: &[&[u8]]) -> bool {
+        return self.lengths.len() == sequences.len()
+            && self.lengths.iter().zip(sequences).all(|(len, s)| *len == s.len())
+            && self.checksum == checksum(sequences.iter().copied());
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, file_path: P) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(file_path)?);
+        self.write_to(&mut writer)?;
+        return writer.flush();
+    }
+
+    pub fn load<P: AsRef<Path>>(file_path: P) -> io::Result<FmIndex> {
+        let file = File::open(file_path)?;
+        let size = file.metadata()?.len();
+        return FmIndex::read_from(&mut BufReader::new(file), size);
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        write_usize(writer, self.sample_rate)?;
+        write_bytes(writer, &self.symbols)?;
+        write_bytes(writer, &self.bwt)?;
+        write_usizes(writer, &self.counts)?;
+        write_usizes(writer, &self.occ)?;
+        write_usize(writer, self.sampled.len())?;
+        for word in self.sampled.iter() {
+            write_u64(writer, *word)?;
+        }
+        write_usizes(writer, &self.samples)?;
+        write_usizes(writer, &self.starts)?;
+        write_usizes(writer, &self.lengths)?;
+        write_u64(writer, self.checksum)?;
+        return Ok(());
+    }
+
+    /// Reads an index written by `write_to` from a reader holding `size` bytes. Every length is checked against
+    /// what's left of them, and against the length of the text, before anything is allocated for it.
+    pub fn read_from<R: Read>(reader: &mut R, size: u64) -> io::Result<FmIndex> {
+        let mut reader = IndexReader { reader, remaining: size };
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not an FM-index file"));
+        }
+
+        let sample_rate = reader.read_usize()?;
+        let symbols = reader.read_bytes(u8::MAX as usize + 1)?;
+        let bwt = reader.read_bytes(usize::MAX)?;
+        let n = bwt.len();
+        let counts = reader.read_usizes(symbols.len() + 1)?;
+        let occ = reader.read_usizes((n / OCC_RATE + 1) * symbols.len())?;
+        let sampled_len = reader.read_len(n / 64 + 1, 8)?;
+        let mut sampled = Vec::with_capacity(sampled_len);
+        for _ in 0..sampled_len {
+            sampled.push(reader.read_u64()?);
+        }
+        let samples = reader.read_usizes(n)?;
+        let starts = reader.read_usizes(n)?;
+        let lengths = reader.read_usizes(n)?;
+        let checksum = reader.read_u64()?;
+
+        if symbols.first() != Some(&SEPARATOR)
+            || counts.len() != symbols.len() + 1
+            || occ.len() != (n / OCC_RATE + 1) * symbols.len()
+            || sampled_len != n / 64 + 1
+            || lengths.len() != starts.len()
+        {
+            return Err(Error::new(ErrorKind::InvalidData, "corrupt FM-index file"));
+        }
+
+        let mut codes = vec![SEPARATOR; 256];
+        for (code, b) in symbols.iter().enumerate().skip(1) {
+            codes[*b as usize] = code as u8;
+        }
+        let sampled_ranks = rank_blocks(&sampled);
+
+        return Ok(FmIndex {
+            symbols,
+            codes,
+            bwt,
+            counts,
+            occ,
+            sample_rate,
+            sampled,
+            sampled_ranks,
+            samples,
+            starts,
+            lengths,
+            checksum,
+        });
+    }
+
+    /// Half-open range of BWT rows whose suffixes start with the pattern, or `None` if it doesn't occur.
+    fn backward_search(&self, pattern: &[u8]) -> Option<(usize, usize)> {
+        let (mut lo, mut hi) = (0, self.bwt.len());
+        for b in pattern.iter().rev() {
+            let code = self.codes[*b as usize];
+            if code == SEPARATOR {
+                return None;
+            }
+            lo = self.counts[code as usize] + self.occ(code, lo);
+            hi = self.counts[code as usize] + self.occ(code, hi);
+            if lo >= hi {
+                return None;
+            }
+        }
+        return Some((lo, hi));
+    }
+
+    /// Number of times `code` appears in `bwt[..row]`.
+    fn occ(&self, code: u8, row: usize) -> usize {
+        let block = row / OCC_RATE;
+        let checkpoint = self.occ[block * self.symbols.len() + code as usize];
+        return checkpoint + self.bwt[block * OCC_RATE..row].iter().filter(|c| **c == code).count();
+    }
+
+    fn locate_row(&self, mut row: usize) -> usize {
+        let mut steps = 0;
+        while self.sampled[row / 64] & (1 << (row % 64)) == 0 {
+            let code = self.bwt[row];
+            row = self.counts[code as usize] + self.occ(code, row);
+            steps += 1;
+        }
+        let word = self.sampled[row / 64] & ((1 << (row % 64)) - 1);
+        let rank = self.sampled_ranks[row / 64] + word.count_ones() as usize;
+        return self.samples[rank] + steps;
+    }
+}
+
+/// 64-bit FNV-1a hash of the sequences, each followed by its length so that moving bases between them changes it.
+fn checksum<'a, I: IntoIterator<Item = &'a [u8]>>(sequences: I) -> u64 {
+    let mut hash = FNV_OFFSET;
+    for sequence in sequences {
+        for b in sequence.iter().chain(&(sequence.len() as u64).to_le_bytes()) {
+            hash = (hash ^ *b as u64).wrapping_mul(FNV_PRIME);
+        }
+    }
+    return hash;
+}
+
+fn rank_blocks(bits: &[u64]) -> Vec<usize> {
+    let mut ranks = Vec::with_capacity(bits.len());
+    let mut total = 0;
+    for word in bits {
+        ranks.push(total);
+        total += word.count_ones() as usize;
+    }
+    return ranks;
+}
+
+fn write_u64<W: Write>(writer: &mut W, value: u64) -> io::Result<()> {
+    return writer.write_all(&value.to_le_bytes());
+}
+
+fn write_usize<W: Write>(writer: &mut W, value: usize) -> io::Result<()> {
+    return write_u64(writer, value as u64);
+}
+
+fn write_bytes<W: Write>(writer: &mut W, values: &[u8]) -> io::Result<()> {
+    write_usize(writer, values.len())?;
+    return writer.write_all(values);
+}
+
+fn write_usizes<W: Write>(writer: &mut W, values: &[usize]) -> io::Result<()> {
+    write_usize(writer, values.len())?;
+    for v in values {
+        write_usize(writer, *v)?;
+    }
+    return Ok(());
+}
+
+/// Reads the fields of a saved index, keeping count of the bytes left so that a corrupt length can't make it allocate
+/// more than the file could hold.
+struct IndexReader<'a, R: Read> {
+    reader: &'a mut R,
+    remaining: u64,
+}
+
+impl<R: Read> IndexReader<'_, R> {
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.reader.read_exact(buf)?;
+        self.remaining = self.remaining.saturating_sub(buf.len() as u64);
+        return Ok(());
+    }
+
+    fn read_u64(&mut self) -> io::Result<u64> {
+        let mut buf = [0; 8];
+        self.read_exact(&mut buf)?;
+        return Ok(u64::from_le_bytes(buf));
+    }
+
+    fn read_usize(&mut self) -> io::Result<usize> {
+        return Ok(self.read_u64()? as usize);
+    }
+
+    /// Length of a field of `width`-byte values, which can't be more than `max` or run past the end of the file.
+    fn read_len(&mut self, max: usize, width: u64) -> io::Result<usize> {
+        let len = self.read_u64()?;
+        if len > max as u64 || len.saturating_mul(width) > self.remaining {
+            return Err(Error::new(ErrorKind::InvalidData, "corrupt FM-index file"));
+        }
+        return Ok(len as usize);
+    }
+
+    fn read_bytes(&mut self, max: usize) -> io::Result<Vec<u8>> {
+        let len = self.read_len(max, 1)?;
+        let mut values = vec![0; len];
+        self.read_exact(&mut values)?;
+        return Ok(values);
+    }
+
+    fn read_usizes(&mut self, max: usize) -> io::Result<Vec<usize>> {
+        let len = self.read_len(max, 8)?;
+        let mut values = Vec::with_capacity(len);
+        for _ in 0..len {
+            values.push(self.read_usize()?);
+        }
+        return Ok(values);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_sequences(rng: &mut StdRng) -> Vec<Vec<u8>> {
+        return [300, 1, 0, 150]
+            .iter()
+            .map(|len| (0..*len).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect())
+            .collect();
+    }
+
+    fn naive_locate(sequences: &[Vec<u8>], pattern: &[u8]) -> Vec<IndexHit> {
+        let mut hits = Vec::new();
+        for (strand, query) in [(Strand::Forward, pattern.to_vec()), (Strand::Reverse, reverse_complement(pattern))] {
+            for (sequence, seq) in sequences.iter().enumerate() {
+                for start in 0..seq.len() {
+                    if seq[start..].starts_with(&query) {
+                        hits.push(IndexHit { sequence, start, strand });
+                    }
+                }
+            }
+        }
+        hits.sort();
+        return hits;
+    }
+
+    #[test]
+    fn count_and_locate_match_a_naive_search() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let sequences = random_sequences(&mut rng);
+        let strings: Vec<&[u8]> = sequences.iter().map(|s| s.as_slice()).collect();
+        for sample_rate in [1, 3, 32] {
+            let index = FmIndex::new(&SuffixArray::new(&strings), &[0, 1, 2, 3], sample_rate);
+            for len in 1..8 {
+                for _ in 0..20 {
+                    let pattern: Vec<u8> = (0..len).map(|_| b"ACGTN"[rng.gen_range(0..5)]).collect();
+                    let expected = naive_locate(&sequences, &pattern);
+                    let forward = expected.iter().filter(|h| h.strand == Strand::Forward).count();
+                    assert_eq!(index.count(&pattern), (forward, expected.len() - forward));
+                    assert_eq!(index.locate(&pattern), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let sequences = random_sequences(&mut rng);
+        let strings: Vec<&[u8]> = sequences.iter().map(|s| s.as_slice()).collect();
+        let index = FmIndex::new(&SuffixArray::new(&strings), &[0, 1, 2, 3], 8);
+
+        let mut saved = Vec::new();
+        index.write_to(&mut saved).unwrap();
+        let loaded = FmIndex::read_from(&mut saved.as_slice(), saved.len() as u64).unwrap();
+        assert!(loaded.indexes(&strings));
+        for pattern in [&b"ACG"[..], b"T", b"GATTACA"] {
+            assert_eq!(loaded.locate(pattern), index.locate(pattern));
+        }
+
+        let mut changed = sequences.clone();
+        changed[0][10] = if changed[0][10] == b'A' { b'C' } else { b'A' };
+        let changed: Vec<&[u8]> = changed.iter().map(|s| s.as_slice()).collect();
+        assert!(!loaded.indexes(&changed));
+        assert!(!loaded.indexes(&strings[..3]));
+
+        assert!(FmIndex::read_from(&mut &saved[..saved.len() - 1], saved.len() as u64 - 1).is_err());
+        assert!(FmIndex::read_from(&mut &b"BIOFMI01"[..], 8).is_err());
+    }
+
+    #[test]
+    fn corrupt_lengths_are_rejected_before_they_are_allocated() {
+        let strings: Vec<&[u8]> = vec![b"GATTACA", b"CAT"];
+        let mut saved = Vec::new();
+        FmIndex::new(&SuffixArray::new(&strings), &[0, 1], 4).write_to(&mut saved).unwrap();
+        let symbols = u64::from_le_bytes(saved[16..24].try_into().unwrap()) as usize;
+        let bwt_len = 24 + symbols;
+
+        let mut corrupt = saved.clone();
+        corrupt[bwt_len..bwt_len + 8].copy_from_slice(&(u64::MAX / 2).to_le_bytes());
+        let error = FmIndex::read_from(&mut corrupt.as_slice(), corrupt.len() as u64).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        // The counts have to be one per symbol plus one, however much file is left
+        let counts_len = bwt_len + 8 + 12;
+        let mut corrupt = saved.clone();
+        corrupt[counts_len..counts_len + 8].copy_from_slice(&(symbols as u64 + 2).to_le_bytes());
+        let error = FmIndex::read_from(&mut corrupt.as_slice(), corrupt.len() as u64).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn sequences_left_out_of_the_index_are_never_hit() {
+        // Laid out as hw1 lays out its suffix array: a genome, then the other's forward and reverse strands
+        let forward: &[u8] = b"ACCGTTAGGC";
+        let reverse = reverse_complement(forward);
+        let strings: Vec<&[u8]> = vec![b"TTACGGA", forward, &reverse];
+        let index = FmIndex::new(&SuffixArray::new(&strings), &[0, 1], 2);
+        assert!(index.indexes(&strings[..2]));
+        let sequences = vec![strings[0].to_vec(), forward.to_vec()];
+        for pattern in [&b"ACG"[..], b"CC", b"TTA", b"GCCTAACGGT"] {
+            assert_eq!(index.locate(pattern), naive_locate(&sequences, pattern));
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use crate::util::location::{self, Anchor};
+use crate::util::markov::MarkovModel;
+use crate::util::nucleotide::Base;
+use crate::util::orf::Orf;
+use crate::util::pwm::Pwm;
+use gb_io::seq::{Location, Seq};
+use std::f64::consts::LN_2;
+use std::io;
+
+/// Order of the coding and non-coding Markov models.
+pub const DEFAULT_ORDER: usize = 5;
+/// Bases either side of a start codon that the start-site matrix covers, as in `hw3`.
+pub const START_FLANK: usize = 10;
+/// ORFs at least this long are taken to be genes when training on an unannotated genome.
+pub const SELF_TRAINING_LENGTH: i64 = 900;
+/// Times the models are retrained on their own calls when training on an unannotated genome.
+pub const SELF_TRAINING_ROUNDS: usize = 2;
+/// Most bases two called genes may share.
+pub const DEFAULT_MAX_OVERLAP: i64 = 60;
+
+const MARKOV_PSEUDOCOUNT: f64 = 1.0;
+const PWM_PSEUDOCOUNT: f64 = 0.5;
+
+/// An ORF begun at its best scoring start codon, with the scores that ranked it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gene {
+    pub orf: Orf,
+    /// Log-odds, in bits, of the ORF's bases under the coding model against the non-coding one
+    pub coding_score: f64,
+    /// Score of the bases around the start codon against the start-site matrix
+    pub start_score: f64,
+}
+
+impl Gene {
+    pub fn score(&self) -> f64 {
+        return self.coding_score + self.start_score;
+    }
+}
+
+/// Models that tell genes from chance ORFs: a Markov chain over coding sequence, one over the whole genome, and a
+/// position weight matrix over the bases around start codons.
+#[derive(Debug, Clone)]
+pub struct GeneModel {
+    coding: MarkovModel,
+    noncoding: MarkovModel,
+    start: Pwm,
+}
+
+impl GeneModel {
+    /// Trains on the given genes of each record, e.g. the annotated CDSs of a related genome. `genes[i]` holds the
+    /// locations of the genes on `seqs[i]`.
+    pub fn train(seqs: &[Seq], genes: &[Vec<Location>], order: usize) -> io::Result<GeneModel> {
+        let mut coding = MarkovModel::new(order, MARKOV_PSEUDOCOUNT);
+        let mut noncoding = MarkovModel::new(order, MARKOV_PSEUDOCOUNT);
+        let mut sites = Vec::new();
+        for (seq, locations) in seqs.iter().zip(genes) {
+            noncoding.train(&seq.seq);
+            noncoding.train(&seq.revcomp().seq);
+            for l in locations {
+                if let Ok(bases) = seq.extract_location(l) {
+                    coding.train(&bases);
+                }
+                if let Ok(site) = location::flank(seq, l, Anchor::Start, START_FLANK, START_FLANK) {
+                    sites.push(site);
+                }
+            }
+        }
+        if sites.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "no genes to train on"));
+        }
+        let background = Base::ACGT.map(|b| noncoding.probability(&[], b));
+        let start = Pwm::from_sites(sites, -(START_FLANK as i64), PWM_PSEUDOCOUNT, background)?;
+        return Ok(GeneModel { coding, noncoding, start });
+    }
+
+    /// Trains on the CDS features of annotated records.
+    pub fn from_annotations(seqs: &[Seq], order: usize) -> io::Result<GeneModel> {
+        let genes: Vec<Vec<Location>> = seqs
+            .iter()
+            .map(|seq| seq.features.iter().filter(|f| &*f.kind == "CDS").map(|f| f.location.clone()).collect())
+            .collect();
+        return GeneModel::train(seqs, &genes, order);
+    }
+
+    /// Trains on an unannotated genome. Its longest ORFs are taken to be genes to begin with, since chance ones are
+    /// rarely that long, though their first start codon is often too far upstream. The models are then retrained on
+    /// the genes they call, which sharpens the start-site matrix. `orfs[i]` holds the ORFs found on `seqs[i]`.
+    pub fn from_orfs(seqs: &[Seq], orfs: &[Vec<Orf>], order: usize) -> io::Result<GeneModel> {
+        let genes: Vec<Vec<Location>> = seqs
+            .iter()
+            .zip(orfs)
+            .map(|(seq, orfs)| orfs.iter().filter(|o| o.len() >= SELF_TRAINING_LENGTH).map(|o| o.location(seq)).collect())
+            .collect();
+        let mut model = GeneModel::train(seqs, &genes, order)?;
+        for _ in 0..SELF_TRAINING_ROUNDS {
+            let genes: Vec<Vec<Location>> = seqs
+                .iter()
+                .zip(orfs)
+                .map(|(seq, orfs)| {
+                    let called = call_genes(&model, seq, orfs, DEFAULT_MAX_OVERLAP);
+                    called.iter().map(|g| g.orf.location(seq)).collect()
+                })
+                .collect();
+            model = GeneModel::train(seqs, &genes, order)?;
+        }
+        return Ok(model);
+    }
+
+    pub fn start_matrix(&self) -> &Pwm {
+        return &self.start;
+    }
+
+    /// Scores every start codon of the ORF and keeps the best.
+    pub fn score(&self, seq: &Seq, orf: &Orf) -> Gene {
+        return orf
+            .starts
+            .iter()
+            .map(|offset| {
+                let candidate = orf.at_start(*offset);
+                let bases = candidate.sequence(&seq.seq);
+                let coding_score = (self.coding.log_likelihood(&bases) - self.noncoding.log_likelihood(&bases)) / LN_2;
+                // A start too close to the end of a linear record for a full window has nothing to say either way
+                let start_score =
+                    location::flank(seq, &candidate.location(seq), Anchor::Start, START_FLANK, START_FLANK).map_or(0.0, |site| self.start.score(&site));
+                Gene {
+                    orf: candidate,
+                    coding_score,
+                    start_score,
+                }
+            })
+            .max_by(|a, b| a.score().total_cmp(&b.score()))
+            .unwrap_or_else(|| Gene {
+                orf: orf.clone(),
+                coding_score: 0.0,
+                start_score: 0.0,
+            });
+    }
+}
+
+/// Calls genes among the ORFs of one record. Every ORF is scored at its best start, and those that look more like
+/// coding than non-coding sequence and score above zero overall are kept, as `resolve_overlaps` allows.
+pub fn call_genes(model: &GeneModel, seq: &Seq, orfs: &[Orf], max_overlap: i64) -> Vec<Gene> {
+    let candidates: Vec<Gene> = orfs
+        .iter()
+        .map(|o| model.score(seq, o))
+        .filter(|g| g.coding_score > 0.0 && g.score() > 0.0)
+        .collect();
+    return resolve_overlaps(candidates, seq.seq.len() as i64, max_overlap);
+}
+
+/// Takes candidate genes best first, skipping any that overlap an already taken gene by more than `max_overlap`
+/// bases. The genes are returned sorted by start.
+fn resolve_overlaps(mut candidates: Vec<Gene>, seq_len: i64, max_overlap: i64) -> Vec<Gene> {
+    candidates.sort_by(|a, b| b.score().total_cmp(&a.score()));
+    let mut genes: Vec<Gene> = Vec::new();
+    for candidate in candidates {
+        let overlaps = genes.iter().any(|g| shared_bases(&g.orf, &candidate.orf, seq_len) > max_overlap);
+        if !overlaps {
+            genes.push(candidate);
+        }
+    }
+    genes.sort_by_key(|g| (g.orf.start, g.orf.strand));
+    return genes;
+}
+
+/// Number of bases two ORFs share. An ORF that spans the origin of a circular record ends past `seq_len`, so each is
+/// also compared with the other shifted a whole sequence length either way.
+fn shared_bases(a: &Orf, b: &Orf, seq_len: i64) -> i64 {
+    return [-seq_len, 0, seq_len]
+        .iter()
+        .map(|shift| a.end.min(b.end + shift) - a.start.max(b.start + shift))
+        .max()
+        .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::strand::Strand;
+
+    fn gene(start: i64, end: i64, score: f64) -> Gene {
+        return Gene {
+            orf: Orf {
+                start,
+                end,
+                strand: Strand::Forward,
+                starts: vec![0],
+            },
+            coding_score: score,
+            start_score: 0.0,
+        };
+    }
+
+    #[test]
+    fn better_genes_win_overlaps_longer_than_allowed() {
+        let candidates = vec![
+            gene(350, 700, 5.0),
+            gene(100, 400, 10.0),
+            // Shares more than 60 bases with the first gene, but is taken before it as it scores higher
+            gene(380, 900, 8.0),
+        ];
+        let called: Vec<(i64, i64)> = resolve_overlaps(candidates, 1000, DEFAULT_MAX_OVERLAP)
+            .iter()
+            .map(|g| (g.orf.start, g.orf.end))
+            .collect();
+        assert_eq!(called, vec![(100, 400), (380, 900)]);
+
+        // Sharing 50 bases is allowed
+        let candidates = vec![gene(100, 400, 10.0), gene(350, 700, 5.0)];
+        assert_eq!(resolve_overlaps(candidates, 1000, DEFAULT_MAX_OVERLAP).len(), 2);
+    }
+
+    #[test]
+    fn overlaps_are_counted_across_the_origin() {
+        // 950..1100 covers 0..100 once it wraps round a 1000 base record
+        let candidates = vec![gene(950, 1100, 3.0), gene(10, 95, 1.0), gene(60, 130, 2.0), gene(120, 300, 0.5)];
+        let called: Vec<(i64, i64)> = resolve_overlaps(candidates, 1000, DEFAULT_MAX_OVERLAP)
+            .iter()
+            .map(|g| (g.orf.start, g.orf.end))
+            .collect();
+        assert_eq!(called, vec![(60, 130), (120, 300), (950, 1100)]);
+    }
+}
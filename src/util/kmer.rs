@@ -0,0 +1,322 @@
+use crate::util::nucleotide::Base;
+use std::io::{self, Write};
+
+/// Longest k-mer that can be counted. The table for every length up to it takes `4^(k + 1) / 3` entries, about 1.4M at 10.
+pub const MAX_K: usize = 10;
+
+/// Counts of every k-mer over A, C, G and T, for each length from 1 up to `k`.
+///
+/// Tables are dense and indexed by the 2-bit packed k-mer, first base in the highest bits. Ambiguous bases break the
+/// sequence, so no k-mer spans one.
+#[derive(Debug, Clone)]
+pub struct KmerSpectrum {
+    k: usize,
+    counts: Vec<Vec<u64>>,
+}
+
+impl KmerSpectrum {
+    pub fn new(k: usize) -> KmerSpectrum {
+        assert!((1..=MAX_K).contains(&k), "k-mer length {} is outside 1..={}", k, MAX_K);
+        let counts = (0..=k).map(|len| vec![0; 1 << (2 * len)]).collect();
+        return KmerSpectrum { k, counts };
+    }
+
+    pub fn add(&mut self, seq: &[u8]) {
+        let mut packed = 0;
+        let mut run = 0;
+        for b in seq {
+            let Some(code) = Base::from_u8(*b).and_then(|base| base.to_2bit()) else {
+                run = 0;
+                continue;
+            };
+            packed = (packed << 2 | code as usize) & ((1 << (2 * self.k)) - 1);
+            run += 1;
+            // The empty k-mer is counted once per base, so it's the total that every length is relative to
+            self.counts[0][0] += 1;
+            for len in 1..=run.min(self.k) {
+                self.counts[len][packed & ((1 << (2 * len)) - 1)] += 1;
+            }
+        }
+    }
+
+    /// Occurrences of the k-mer. Ambiguous k-mers, and those longer than `k`, are never counted.
+    pub fn count(&self, kmer: &[Base]) -> u64 {
+        return match (self.counts.get(kmer.len()), pack(kmer)) {
+            (Some(counts), Some(code)) => counts[code],
+            _ => 0,
+        };
+    }
+
+    /// Number of k-mers of the given length that were counted, none if it's longer than `k`.
+    pub fn total(&self, len: usize) -> u64 {
+        return self.counts.get(len).map_or(0, |counts| counts.iter().sum());
+    }
+
+    pub fn frequency(&self, kmer: &[Base]) -> f64 {
+        let total = self.total(kmer.len());
+        return if total == 0 { 0.0 } else { self.count(kmer) as f64 / total as f64 };
+    }
+
+    /// Count expected from the k-mer's overlapping (k - 1)-mers under a maximal-order Markov model,
+    /// `c(w[..k-1]) * c(w[1..]) / c(w[1..k-1])`. For a dinucleotide `xy` that's `c(x) * c(y) / n`.
+    /// Single bases are expected at a quarter of the total.
+    pub fn expected(&self, kmer: &[Base]) -> f64 {
+        let len = kmer.len();
+        if len <= 1 {
+            return self.total(len) as f64 / 4.0;
+        }
+        let middle = self.count(&kmer[1..len - 1]);
+        return if middle == 0 {
+            0.0
+        } else {
+            self.count(&kmer[..len - 1]) as f64 * self.count(&kmer[1..]) as f64 / middle as f64
+        };
+    }
+
+    /// Observed over expected count, e.g. well below 1 for CG in CpG-depleted genomes. Zero when nothing is expected.
+    pub fn observed_expected(&self, kmer: &[Base]) -> f64 {
+        let expected = self.expected(kmer);
+        return if expected == 0.0 { 0.0 } else { self.count(kmer) as f64 / expected };
+    }
+
+    /// How far the counts of the given length are from Chargaff's second parity rule, where every k-mer is as common
+    /// as its reverse complement on the same strand: `sum |c(w) - c(rc(w))| / sum (c(w) + c(rc(w)))` over the k-mer
+    /// pairs. Zero is perfectly symmetric and one completely one-sided.
+    pub fn strand_asymmetry(&self, len: usize) -> f64 {
+        let (mut diff, mut sum) = (0, 0);
+        for (code, count) in self.counts[len].iter().enumerate() {
+            let rc = reverse_complement_code(code, len);
+            if rc > code {
+                diff += count.abs_diff(self.counts[len][rc]);
+                sum += count + self.counts[len][rc];
+            }
+        }
+        return if sum == 0 { 0.0 } else { diff as f64 / sum as f64 };
+    }
+
+    /// Every k-mer of the given length in lexicographic order.
+    pub fn kmers(&self, len: usize) -> impl Iterator<Item = Vec<Base>> {
+        return (0..1 << (2 * len)).map(move |code| unpack(code, len));
+    }
+
+    /// One line per k-mer of the given length: k-mer, count, frequency, expected count, observed/expected and the count
+    /// of its reverse complement.
+    pub fn write_tsv<W: Write>(&self, writer: &mut W, len: usize) -> io::Result<()> {
+        writeln!(writer, "kmer\tcount\tfrequency\texpected\tobs_exp\trevcomp_count")?;
+        for row in self.rows(len) {
+            writeln!(
+                writer,
+                "{}\t{}\t{:.6}\t{:.2}\t{:.4}\t{}",
+                row.kmer, row.count, row.frequency, row.expected, row.observed_expected, row.revcomp_count
+            )?;
+        }
+        return Ok(());
+    }
+
+    /// The same fields as `write_tsv`, as a JSON object with the asymmetry and a `kmers` array.
+    pub fn write_json<W: Write>(&self, writer: &mut W, len: usize) -> io::Result<()> {
+        write!(
+            writer,
+            "{{\"k\":{},\"total\":{},\"strand_asymmetry\":{:.6},\"kmers\":[",
+            len,
+            self.total(len),
+            self.strand_asymmetry(len)
+        )?;
+        for (i, row) in self.rows(len).enumerate() {
+            write!(
+                writer,
+                "{}{{\"kmer\":\"{}\",\"count\":{},\"frequency\":{:.6},\"expected\":{:.2},\"obs_exp\":{:.4},\"revcomp_count\":{}}}",
+                if i == 0 { "" } else { "," },
+                row.kmer,
+                row.count,
+                row.frequency,
+                row.expected,
+                row.observed_expected,
+                row.revcomp_count
+            )?;
+        }
+        return writeln!(writer, "]}}");
+    }
+
+    fn rows(&self, len: usize) -> impl Iterator<Item = KmerRow> + '_ {
+        return self.kmers(len).enumerate().map(move |(code, kmer)| KmerRow {
+            kmer: kmer.iter().map(|b| b.to_char()).collect(),
+            count: self.counts[len][code],
+            frequency: self.frequency(&kmer),
+            expected: self.expected(&kmer),
+            observed_expected: self.observed_expected(&kmer),
+            revcomp_count: self.counts[len][reverse_complement_code(code, len)],
+        });
+    }
+}
+
+struct KmerRow {
+    kmer: String,
+    count: u64,
+    frequency: f64,
+    expected: f64,
+    observed_expected: f64,
+    revcomp_count: u64,
+}
+
+/// GC content and GC skew over one window of a sequence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GcWindow {
+    pub id: String,
+    /// 0-based, exclusive bounds
+    pub start: usize,
+    pub end: usize,
+    /// `(G + C) / (A + C + G + T)`, ignoring ambiguous bases
+    pub gc: f64,
+    /// `(G - C) / (G + C)`, which changes sign at the origin and terminus of replication in many bacteria
+    pub skew: f64,
+}
+
+/// GC content and skew of every `window`-long stretch of the sequence, starting every `step` bases. The last window
+/// is cut short at the end of the sequence.
+pub fn gc_windows(id: &str, seq: &[u8], window: usize, step: usize) -> Vec<GcWindow> {
+    assert!(window > 0 && step > 0, "window and step have to be positive");
+    let mut windows = Vec::new();
+    let mut start = 0;
+    while start < seq.len() {
+        let end = (start + window).min(seq.len());
+        let (mut at, mut c, mut g) = (0, 0, 0);
+        for b in &seq[start..end] {
+            match Base::from_u8(*b) {
+                Some(Base::A) | Some(Base::T) => at += 1,
+                Some(Base::C) => c += 1,
+                Some(Base::G) => g += 1,
+                _ => {}
+            }
+        }
+        windows.push(GcWindow {
+            id: id.to_string(),
+            start,
+            end,
+            gc: if at + c + g == 0 { 0.0 } else { (c + g) as f64 / (at + c + g) as f64 },
+            skew: if c + g == 0 { 0.0 } else { (g as f64 - c as f64) / (c + g) as f64 },
+        });
+        if end == seq.len() {
+            break;
+        }
+        start += step;
+    }
+    return windows;
+}
+
+pub fn write_gc_tsv<W: Write>(writer: &mut W, windows: &[GcWindow]) -> io::Result<()> {
+    writeln!(writer, "id\tstart\tend\tgc\tskew")?;
+    for w in windows {
+        writeln!(writer, "{}\t{}\t{}\t{:.4}\t{:.4}", w.id, w.start, w.end, w.gc, w.skew)?;
+    }
+    return Ok(());
+}
+
+pub fn write_gc_json<W: Write>(writer: &mut W, windows: &[GcWindow]) -> io::Result<()> {
+    write!(writer, "[")?;
+    for (i, w) in windows.iter().enumerate() {
+        write!(
+            writer,
+            "{}{{\"id\":{},\"start\":{},\"end\":{},\"gc\":{:.4},\"skew\":{:.4}}}",
+            if i == 0 { "" } else { "," },
+            json_string(&w.id),
+            w.start,
+            w.end,
+            w.gc,
+            w.skew
+        )?;
+    }
+    return writeln!(writer, "]");
+}
+
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    return quoted;
+}
+
+fn pack(kmer: &[Base]) -> Option<usize> {
+    let mut packed = 0;
+    for base in kmer {
+        packed = packed << 2 | base.to_2bit()? as usize;
+    }
+    return Some(packed);
+}
+
+fn unpack(code: usize, len: usize) -> Vec<Base> {
+    return (0..len).map(|i| Base::from_2bit((code >> (2 * (len - 1 - i))) as u8)).collect();
+}
+
+/// With A=0, C=1, G=2, T=3 the complement of a code is `3 - code`, so only the order needs reversing.
+fn reverse_complement_code(code: usize, len: usize) -> usize {
+    let mut rc = 0;
+    for i in 0..len {
+        rc = rc << 2 | (3 - (code >> (2 * i) & 3));
+    }
+    return rc;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kmer(s: &str) -> Vec<Base> {
+        return s.bytes().map(|b| Base::from_u8(b).unwrap()).collect();
+    }
+
+    #[test]
+    fn counts_every_kmer_up_to_k_without_spanning_ambiguous_bases() {
+        let mut spectrum = KmerSpectrum::new(3);
+        spectrum.add(b"ACGTNACG");
+        assert_eq!(spectrum.total(1), 7);
+        assert_eq!(spectrum.total(3), 3);
+        assert_eq!(spectrum.count(&kmer("A")), 2);
+        assert_eq!(spectrum.count(&kmer("ACG")), 2);
+        assert_eq!(spectrum.count(&kmer("CGT")), 1);
+        // Only across the N
+        assert_eq!(spectrum.count(&kmer("GTA")), 0);
+        assert_eq!(spectrum.count(&kmer("ANA")), 0);
+        assert_eq!(spectrum.frequency(&kmer("CG")), 2.0 / 5.0);
+    }
+
+    #[test]
+    fn kmers_longer_than_k_are_never_counted() {
+        let mut spectrum = KmerSpectrum::new(2);
+        spectrum.add(b"ACGACG");
+        assert_eq!(spectrum.count(&kmer("ACG")), 0);
+        assert_eq!(spectrum.total(3), 0);
+        assert_eq!(spectrum.frequency(&kmer("ACGA")), 0.0);
+    }
+
+    #[test]
+    fn reverse_complements_are_paired_up_on_the_same_strand() {
+        let mut spectrum = KmerSpectrum::new(2);
+        // AC and its reverse complement GT both occur once, AA three times and TT never, and there are four As to one T
+        spectrum.add(b"AAAACGT");
+        assert_eq!(reverse_complement_code(pack(&kmer("AC")).unwrap(), 2), pack(&kmer("GT")).unwrap());
+        assert_eq!(spectrum.strand_asymmetry(1), 3.0 / 7.0);
+        let mut tsv = Vec::new();
+        spectrum.write_tsv(&mut tsv, 2).unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+        // Count and reverse complement count of a k-mer's row
+        let row = |k: &str| -> (&str, &str) {
+            let fields: Vec<&str> = tsv.lines().find(|l| l.starts_with(&format!("{}\t", k))).unwrap().split('\t').collect();
+            return (fields[1], fields[5]);
+        };
+        assert_eq!(row("AA"), ("3", "0"));
+        assert_eq!(row("TT"), ("0", "3"));
+        assert_eq!(row("AC"), ("1", "1"));
+        // Palindromes are their own reverse complement, so they don't count towards the asymmetry
+        assert_eq!(row("CG"), ("1", "1"));
+        assert_eq!(spectrum.strand_asymmetry(2), 3.0 / 5.0);
+    }
+}
@@ -0,0 +1,331 @@
+use crate::util::nucleotide::Base;
+use crate::util::strand::Strand;
+use gb_io::seq::{After, Before, Location, Seq};
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Why a location couldn't be resolved against a sequence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LocationError {
+    /// The location has no pieces, e.g. an empty `join()`
+    Empty,
+    /// The location points into another entry, e.g. `J00194.1:100..202`
+    External(String),
+    /// The location is a gap of unknown or given length, which has no coordinates
+    Gap,
+    /// The requested end of the feature isn't known, e.g. `<1..100` has no known 5' end
+    Partial,
+    /// A flank runs off the end of a linear sequence
+    OutOfBounds { start: i64, end: i64, len: i64 },
+}
+
+impl fmt::Display for LocationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            LocationError::Empty => write!(f, "location has no pieces"),
+            LocationError::External(accession) => write!(f, "location refers to another entry, '{}'", accession),
+            LocationError::Gap => write!(f, "location is a gap"),
+            LocationError::Partial => write!(f, "location is partial at the requested end"),
+            LocationError::OutOfBounds { start, end, len } => {
+                write!(f, "range {}..{} runs off the end of a linear sequence of length {}", start, end, len)
+            }
+        };
+    }
+}
+
+impl error::Error for LocationError {}
+
+impl From<LocationError> for io::Error {
+    fn from(error: LocationError) -> io::Error {
+        return io::Error::new(io::ErrorKind::InvalidData, error);
+    }
+}
+
+/// Which end of a feature a flank is taken around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anchor {
+    /// The first base of the feature on its own strand, e.g. the A of a start codon
+    Start,
+    /// The last base of the feature on its own strand, e.g. the last base of a stop codon
+    End,
+}
+
+/// A contiguous piece of a location, with 0-based, exclusive bounds on the forward strand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: i64,
+    pub end: i64,
+    pub strand: Strand,
+    /// Whether the piece carries on past its 5' end, on its own strand
+    pub partial_five_prime: bool,
+    /// Whether the piece carries on past its 3' end, on its own strand
+    pub partial_three_prime: bool,
+}
+
+impl Span {
+    /// Every position of the span in the order it's read on its strand.
+    fn positions(&self) -> Box<dyn Iterator<Item = i64>> {
+        return match self.strand {
+            Strand::Forward => Box::new(self.start..self.end),
+            Strand::Reverse => Box::new((self.start..self.end).rev()),
+        };
+    }
+}
+
+/// Splits a location into its contiguous pieces, in the order they're read (5' to 3').
+///
+/// Complements reverse the order of their pieces and swap which end is partial, however deeply they're nested. Of a
+/// `one-of` the first alternative is used, and `order` and `bond` are treated like `join`. A site between two bases
+/// (`x^y`) is an empty span at the second base.
+pub fn spans(location: &Location) -> Result<Vec<Span>, LocationError> {
+    let spans = match location {
+        Location::Range((start, Before(before)), (end, After(after))) => vec![Span {
+            start: *start,
+            end: *end,
+            strand: Strand::Forward,
+            partial_five_prime: *before,
+            partial_three_prime: *after,
+        }],
+        Location::Between(before, _) => vec![Span {
+            start: before + 1,
+            end: before + 1,
+            strand: Strand::Forward,
+            partial_five_prime: false,
+            partial_three_prime: false,
+        }],
+        Location::Complement(inner) => spans(inner)?
+            .into_iter()
+            .rev()
+            .map(|s| Span {
+                strand: match s.strand {
+                    Strand::Forward => Strand::Reverse,
+                    Strand::Reverse => Strand::Forward,
+                },
+                partial_five_prime: s.partial_three_prime,
+                partial_three_prime: s.partial_five_prime,
+                ..s
+            })
+            .collect(),
+        Location::Join(ls) | Location::Order(ls) | Location::Bond(ls) => {
+            let mut spans = Vec::new();
+            for l in ls {
+                spans.extend(self::spans(l)?);
+            }
+            spans
+        }
+        Location::OneOf(ls) => spans(ls.first().ok_or(LocationError::Empty)?)?,
+        Location::External(accession, _) => return Err(LocationError::External(accession.clone())),
+        Location::Gap(_) => return Err(LocationError::Gap),
+    };
+    if spans.is_empty() {
+        return Err(LocationError::Empty);
+    }
+    return Ok(spans);
+}
+
+/// The strand a location is read on. Mixed-strand locations, such as trans-spliced genes, count as forward unless
+/// every piece is on the reverse strand.
+pub fn strand(location: &Location) -> Result<Strand, LocationError> {
+    let spans = spans(location)?;
+    return Ok(if spans.iter().all(|s| s.strand == Strand::Reverse) {
+        Strand::Reverse
+    } else {
+        Strand::Forward
+    });
+}
+
+/// 0-based, exclusive bounds covering every piece of a location. On a circular sequence a feature that spans the
+/// origin (`join(x..last,1..y)`) starts at `x` and ends past the length of the sequence.
+pub fn bounds(location: &Location, seq: &Seq) -> Result<(i64, i64), LocationError> {
+    let mut spans = spans(location)?;
+    if spans[0].strand == Strand::Reverse {
+        spans.reverse();
+    }
+    // Pieces are in genome order unless one jumps back towards the origin
+    let wrap = spans.windows(2).position(|w| w[1].start < w[0].start);
+    return Ok(match wrap {
+        Some(i) if seq.is_circular() => {
+            let start = spans[..=i].iter().map(|s| s.start).min().unwrap();
+            let end = spans[i + 1..].iter().map(|s| s.end).max().unwrap();
+            (start, end + seq.len())
+        }
+        _ => (spans.iter().map(|s| s.start).min().unwrap(), spans.iter().map(|s| s.end).max().unwrap()),
+    });
+}
+
+/// 0-based position of the first (`Anchor::Start`) or last (`Anchor::End`) base of a location, on the strand the
+/// location is read on.
+pub fn anchor(location: &Location, anchor: Anchor) -> Result<(i64, Strand), LocationError> {
+    let spans = spans(location)?;
+    let span = match anchor {
+        Anchor::Start => spans.first().unwrap(),
+        Anchor::End => spans.last().unwrap(),
+    };
+    let partial = match anchor {
+        Anchor::Start => span.partial_five_prime,
+        Anchor::End => span.partial_three_prime,
+    };
+    if partial {
+        return Err(LocationError::Partial);
+    }
+    let pos = match (anchor, span.strand) {
+        (Anchor::Start, Strand::Forward) | (Anchor::End, Strand::Reverse) => span.start,
+        (Anchor::Start, Strand::Reverse) | (Anchor::End, Strand::Forward) => span.end - 1,
+    };
+    // An empty span, a site between two bases, is anchored on the base after it
+    return Ok((pos.max(span.start), span.strand));
+}
+
+/// Bases of a location read on its own strand, e.g. a spliced CDS from its start codon to its stop codon. Pieces on the
+/// reverse strand are complemented.
+pub fn extract(seq: &Seq, location: &Location) -> Result<Vec<u8>, LocationError> {
+    let len = seq.len();
+    let mut bases = Vec::new();
+    for span in spans(location)? {
+        if span.start < 0 || span.end > len {
+            return Err(LocationError::OutOfBounds {
+                start: span.start,
+                end: span.end,
+                len,
+            });
+        }
+        bases.extend(span.positions().map(|p| {
+            let b = seq.seq[p as usize].to_ascii_uppercase();
+            match span.strand {
+                Strand::Forward => b,
+                Strand::Reverse => Base::from_u8_lossy(b).complement().to_u8(),
+            }
+        }));
+    }
+    return Ok(bases);
+}
+
+/// `upstream` bases before the anchor, the anchor itself and `downstream` bases after it, read on the feature's strand.
+/// The anchor ends up at index `upstream`.
+///
+/// Bases inside the feature follow its pieces, so a flank into a spliced CDS skips its introns, and once the flank
+/// leaves the feature it carries on along the genome. On a circular sequence flanks wrap around the origin, and on a
+/// linear one running off either end is an error. An anchor at a partial end is an error too, since it isn't known
+/// where the feature really starts or ends.
+pub fn flank(seq: &Seq, location: &Location, anchor: Anchor, upstream: usize, downstream: usize) -> Result<Vec<u8>, LocationError> {
+    let (anchor_pos, strand) = self::anchor(location, anchor)?;
+    let spans = spans(location)?;
+    let inside: Vec<i64> = spans.iter().filter(|s| s.strand == strand).flat_map(|s| s.positions()).collect();
+    let step = match strand {
+        Strand::Forward => 1,
+        Strand::Reverse => -1,
+    };
+
+    // Walk outwards from the anchor: through the feature's own bases on the side it extends to, then along the genome
+    let mut positions: Vec<i64> = Vec::with_capacity(upstream + downstream + 1);
+    match anchor {
+        Anchor::Start => {
+            positions.extend((1..=upstream as i64).rev().map(|i| anchor_pos - i * step));
+            match inside.iter().position(|p| *p == anchor_pos) {
+                Some(i) => positions.extend(inside.iter().skip(i).take(downstream + 1)),
+                None => positions.push(anchor_pos),
+            }
+            let last = *positions.last().unwrap();
+            let missing = upstream + downstream + 1 - positions.len();
+            positions.extend((1..=missing as i64).map(|i| last + i * step));
+        }
+        Anchor::End => {
+            let within: Vec<i64> = match inside.iter().rposition(|p| *p == anchor_pos) {
+                Some(i) => inside[..=i].iter().rev().take(upstream + 1).copied().collect(),
+                None => vec![anchor_pos],
+            };
+            let first = *within.last().unwrap();
+            let missing = upstream + 1 - within.len();
+            positions.extend((1..=missing as i64).rev().map(|i| first - i * step));
+            positions.extend(within.iter().rev());
+            positions.extend((1..=downstream as i64).map(|i| anchor_pos + i * step));
+        }
+    }
+
+    let len = seq.len();
+    // An empty circular record has no bases to wrap round to
+    if !seq.is_circular() || len == 0 {
+        let (start, end) = (*positions.iter().min().unwrap(), *positions.iter().max().unwrap() + 1);
+        if start < 0 || end > len {
+            return Err(LocationError::OutOfBounds { start, end, len });
+        }
+    }
+    return Ok(positions
+        .iter()
+        .map(|p| {
+            let b = seq.seq[p.rem_euclid(len) as usize].to_ascii_uppercase();
+            match strand {
+                Strand::Forward => b,
+                Strand::Reverse => Base::from_u8_lossy(b).complement().to_u8(),
+            }
+        })
+        .collect());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gb_io::seq::Topology;
+
+    const BASES: &[u8] = b"AACGTTGCATGC";
+
+    fn record(topology: Topology) -> Seq {
+        return Seq {
+            topology,
+            len: Some(BASES.len()),
+            seq: BASES.to_vec(),
+            ..Seq::empty()
+        };
+    }
+
+    fn location(text: &str) -> Location {
+        return Location::from_gb_format(text).unwrap();
+    }
+
+    #[test]
+    fn joins_are_read_in_order_across_the_origin_and_complements_backwards() {
+        let seq = record(Topology::Circular);
+        assert_eq!(extract(&seq, &location("join(10..12,1..3)")).unwrap(), b"TGCAAC");
+        assert_eq!(bounds(&location("join(10..12,1..3)"), &seq), Ok((9, 15)));
+        assert_eq!(extract(&seq, &location("complement(join(2..4,7..9))")).unwrap(), b"TGCCGT");
+        assert_eq!(extract(&seq, &location("join(complement(7..9),complement(2..4))")).unwrap(), b"TGCCGT");
+        assert_eq!(strand(&location("complement(join(2..4,7..9))")), Ok(Strand::Reverse));
+        assert_eq!(
+            extract(&record(Topology::Linear), &location("10..13")),
+            Err(LocationError::OutOfBounds { start: 9, end: 13, len: 12 })
+        );
+    }
+
+    #[test]
+    fn flanks_wrap_round_the_origin_of_a_circular_record() {
+        let seq = record(Topology::Circular);
+        assert_eq!(flank(&seq, &location("2..4"), Anchor::Start, 3, 2).unwrap(), b"GCAACG");
+        // Read on the reverse strand, upstream of 11 is past the end of the record
+        assert_eq!(flank(&seq, &location("complement(10..11)"), Anchor::Start, 2, 1).unwrap(), b"TGCA");
+        assert_eq!(flank(&seq, &location("10..12"), Anchor::End, 1, 2).unwrap(), b"GCAA");
+    }
+
+    #[test]
+    fn flanks_past_the_ends_of_a_linear_record_are_out_of_bounds() {
+        let seq = record(Topology::Linear);
+        assert_eq!(
+            flank(&seq, &location("2..4"), Anchor::Start, 3, 2),
+            Err(LocationError::OutOfBounds { start: -2, end: 4, len: 12 })
+        );
+        assert_eq!(
+            flank(&seq, &location("10..12"), Anchor::End, 0, 2),
+            Err(LocationError::OutOfBounds { start: 11, end: 14, len: 12 })
+        );
+        assert_eq!(flank(&seq, &location("2..4"), Anchor::Start, 1, 2).unwrap(), b"AACG");
+    }
+
+    #[test]
+    fn flanks_into_a_spliced_feature_skip_its_introns() {
+        let seq = record(Topology::Linear);
+        assert_eq!(flank(&seq, &location("join(2..3,6..8)"), Anchor::Start, 1, 3).unwrap(), b"AACTG");
+        assert_eq!(flank(&seq, &location("join(2..3,6..8)"), Anchor::End, 3, 1).unwrap(), b"CTGCA");
+        assert_eq!(flank(&seq, &location("<2..4"), Anchor::Start, 1, 1), Err(LocationError::Partial));
+        assert_eq!(flank(&seq, &location("<2..4"), Anchor::End, 1, 1).unwrap(), b"CGT");
+    }
+}
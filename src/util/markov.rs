@@ -0,0 +1,169 @@
+use crate::util::nucleotide::Base;
+use rand::Rng;
+
+/// Highest supported order. The count tables for every order up to it take `4^(k + 2) / 3` entries, about 350k at 8.
+pub const MAX_ORDER: usize = 8;
+
+/// A k-th order Markov chain over A, C, G and T.
+///
+/// Transition counts are kept in dense tables, one per order `0..=k`, indexed by the 2-bit packed context followed by
+/// the next base. The lower orders are used at the start of a sequence, and after an ambiguous base, until `k` bases
+/// of context are available. Probabilities are smoothed by adding `pseudocount` to every transition.
+#[derive(Debug, Clone)]
+pub struct MarkovModel {
+    order: usize,
+    pseudocount: f64,
+    counts: Vec<Vec<u64>>,
+}
+
+impl MarkovModel {
+    /// An untrained model. With a non-zero pseudocount it starts out uniform.
+    pub fn new(order: usize, pseudocount: f64) -> MarkovModel {
+        assert!(order <= MAX_ORDER, "Markov order {} is above the maximum of {}", order, MAX_ORDER);
+        let counts = (0..=order).map(|j| vec![0; 4 << (2 * j)]).collect();
+        return MarkovModel { order, pseudocount, counts };
+    }
+
+    pub fn order(&self) -> usize {
+        return self.order;
+    }
+
+    /// Adds the transitions of a sequence to the counts. Ambiguous or unknown symbols break the chain.
+    pub fn train(&mut self, seq: &[u8]) {
+        let mut context = 0;
+        let mut context_len = 0;
+        for b in seq {
+            let Some(code) = code_of(*b) else {
+                context_len = 0;
+                continue;
+            };
+            // Every order sees the same transition, with its context trimmed to its last `j` bases
+            for j in 0..=context_len {
+                self.counts[j][index(context & ((1 << (2 * j)) - 1), code)] += 1;
+            }
+            (context, context_len) = self.push(context, context_len, code);
+        }
+    }
+
+    /// Smoothed probability of `base` following the given context, which must be at most `order` bases long.
+    pub fn probability(&self, context: &[Base], base: Base) -> f64 {
+        let (Some(context), Some(code)) = (pack(context), base.to_2bit()) else {
+            return 0.0;
+        };
+        return self.transition(context.0, context.1, code as usize);
+    }
+
+    /// Natural-log likelihood of a sequence under the model. Ambiguous or unknown symbols are skipped and break the chain.
+    pub fn log_likelihood(&self, seq: &[u8]) -> f64 {
+        let mut score = 0.0;
+        let mut context = 0;
+        let mut context_len = 0;
+        for b in seq {
+            let Some(code) = code_of(*b) else {
+                context_len = 0;
+                continue;
+            };
+            score += self.transition(context, context_len, code).ln();
+            (context, context_len) = self.push(context, context_len, code);
+        }
+        return score;
+    }
+
+    /// Draws the base following `context` and appends it, so a sequence can be generated one base at a time.
+    pub fn next_base<R: Rng + ?Sized>(&self, context: &mut Context, rng: &mut R) -> Base {
+        let mut score: f64 = rng.gen();
+        let mut code = 3;
+        for c in 0..4 {
+            score -= self.transition(context.packed, context.len, c);
+            if score < 0.0 {
+                code = c;
+                break;
+            }
+        }
+        (context.packed, context.len) = self.push(context.packed, context.len, code);
+        return Base::from_2bit(code as u8);
+    }
+
+    fn transition(&self, context: usize, context_len: usize, code: usize) -> f64 {
+        let row = &self.counts[context_len][index(context, 0)..index(context, 4)];
+        let total = row.iter().sum::<u64>() as f64 + 4.0 * self.pseudocount;
+        return if total == 0.0 {
+            // Nothing was seen after this context and there's no smoothing to fall back on
+            0.25
+        } else {
+            (row[code] as f64 + self.pseudocount) / total
+        };
+    }
+
+    /// Appends a base to the context, dropping the oldest one once it holds `order` bases.
+    fn push(&self, context: usize, context_len: usize, code: usize) -> (usize, usize) {
+        let context_len = (context_len + 1).min(self.order);
+        let mask = (1 << (2 * context_len)) - 1;
+        return ((context << 2 | code) & mask, context_len);
+    }
+}
+
+/// The most recent bases of a sequence being generated, up to the model's order.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Context {
+    packed: usize,
+    len: usize,
+}
+
+fn code_of(b: u8) -> Option<usize> {
+    return Base::from_u8(b).and_then(|base| base.to_2bit()).map(|code| code as usize);
+}
+
+fn index(context: usize, code: usize) -> usize {
+    return context * 4 + code;
+}
+
+fn pack(context: &[Base]) -> Option<(usize, usize)> {
+    let mut packed = 0;
+    for base in context {
+        packed = packed << 2 | base.to_2bit()? as usize;
+    }
+    return Some((packed, context.len()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn lower_orders_are_used_until_there_is_enough_context() {
+        let mut model = MarkovModel::new(1, 0.0);
+        model.train(b"ACACAC");
+        assert_eq!(model.probability(&[], Base::A), 0.5);
+        assert_eq!(model.probability(&[Base::A], Base::C), 1.0);
+        assert_eq!(model.probability(&[Base::C], Base::G), 0.0);
+        // Nothing ever followed G, and without a pseudocount every base is as likely
+        assert_eq!(model.probability(&[Base::G], Base::T), 0.25);
+
+        assert!((model.log_likelihood(b"ACA") - 0.5f64.ln()).abs() < 1e-12);
+        // The N breaks the chain, so the second A is scored without context
+        assert!((model.log_likelihood(b"ANA") - 2.0 * 0.5f64.ln()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn pseudocounts_smooth_every_transition() {
+        let mut model = MarkovModel::new(2, 1.0);
+        model.train(b"AAAA");
+        // AA was followed by A twice, out of 2 + 4 pseudocounts
+        assert_eq!(model.probability(&[Base::A, Base::A], Base::A), 3.0 / 6.0);
+        assert_eq!(model.probability(&[Base::A, Base::A], Base::C), 1.0 / 6.0);
+        assert_eq!(model.probability(&[Base::C, Base::A], Base::A), 0.25);
+    }
+
+    #[test]
+    fn generated_bases_follow_the_trained_transitions() {
+        let mut model = MarkovModel::new(1, 0.0);
+        model.train(b"ACACACAC");
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut context = Context::default();
+        let bases: Vec<Base> = (0..20).map(|_| model.next_base(&mut context, &mut rng)).collect();
+        assert!(bases.windows(2).all(|pair| pair[0] != pair[1] && pair[0].matches(Base::M)));
+    }
+}
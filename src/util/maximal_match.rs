@@ -0,0 +1,244 @@
+use crate::util::strand::Strand;
+use crate::util::suffix_array::SuffixArray;
+
+/// Where an input sequence of a `SuffixArray` came from: which genome, and which strand of it.
+/// Reverse-strand sequences are expected to be the reverse complement of the genome's record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceInfo {
+    pub genome: usize,
+    pub strand: Strand,
+}
+
+/// One occurrence of a maximal match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchHit {
+    pub genome: usize,
+    /// Index of the input sequence in the `SuffixArray`
+    pub sequence: usize,
+    /// 0-based start in forward-strand coordinates, so reverse-strand hits are translated out of the reverse complement
+    pub start: usize,
+    pub strand: Strand,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MaximalMatch {
+    pub len: usize,
+    pub hits: Vec<MatchHit>,
+}
+
+/// Enumerates every maximal exact match (MEM) of at least `min_len` between two different genomes.
+///
+/// Each MEM is a pair of occurrences, in genome order, that cannot be extended to the right (their suffixes diverge or
+/// hit a sequence end) or to the left (the preceding bases differ, or one occurrence starts its sequence).
+pub fn maximal_exact_matches(suffix_array: &SuffixArray, info: &[SequenceInfo], min_len: usize) -> Vec<MaximalMatch> {
+    let mut matches = Vec::new();
+    let sa = suffix_array.suffixes();
+
+    for_each_lcp_interval(suffix_array, min_len, |lb, rb, len, splits| {
+        // Suffixes in different child intervals diverge right after `len` characters, so every pair across children
+        // is right-maximal. Pairs within a child share a longer prefix and are reported by that child instead.
+        let mut bounds = Vec::with_capacity(splits.len() + 2);
+        bounds.push(lb);
+        bounds.extend_from_slice(splits);
+        bounds.push(rb + 1);
+
+        for c1 in 0..bounds.len() - 1 {
+            for c2 in c1 + 1..bounds.len() - 1 {
+                for r1 in bounds[c1]..bounds[c1 + 1] {
+                    for r2 in bounds[c2]..bounds[c2 + 1] {
+                        let (seq1, _) = suffix_array.source(sa[r1]);
+                        let (seq2, _) = suffix_array.source(sa[r2]);
+                        if info[seq1].genome == info[seq2].genome {
+                            continue;
+                        }
+                        let left1 = left_char(suffix_array, sa[r1]);
+                        if left1.is_some() && left1 == left_char(suffix_array, sa[r2]) {
+                            continue;
+                        }
+                        let mut hits = vec![to_hit(suffix_array, info, sa[r1], len), to_hit(suffix_array, info, sa[r2], len)];
+                        hits.sort_by_key(|h| h.genome);
+                        matches.push(MaximalMatch { len, hits });
+                    }
+                }
+            }
+        }
+    });
+
+    return matches;
+}
+
+/// Enumerates every maximal unique match (MUM) of at least `min_len`: a maximal match that occurs exactly once in
+/// each of the genomes, counting the strands given in `info`.
+pub fn maximal_unique_matches(suffix_array: &SuffixArray, info: &[SequenceInfo], min_len: usize) -> Vec<MaximalMatch> {
+    let genome_count = info.iter().map(|i| i.genome + 1).max().unwrap_or(0);
+    let mut matches = Vec::new();
+    let sa = suffix_array.suffixes();
+
+    for_each_lcp_interval(suffix_array, min_len, |lb, rb, len, _| {
+        if rb + 1 - lb != genome_count {
+            return;
+        }
+
+        let mut seen = vec![false; genome_count];
+        for pos in &sa[lb..=rb] {
+            let (seq, _) = suffix_array.source(*pos);
+            if seen[info[seq].genome] {
+                return;
+            }
+            seen[info[seq].genome] = true;
+        }
+
+        let left = left_char(suffix_array, sa[lb]);
+        if left.is_some() && (lb + 1..=rb).all(|r| left_char(suffix_array, sa[r]) == left) {
+            return;
+        }
+
+        let mut hits: Vec<MatchHit> = (lb..=rb).map(|r| to_hit(suffix_array, info, sa[r], len)).collect();
+        hits.sort_by_key(|h| h.genome);
+        matches.push(MaximalMatch { len, hits });
+    });
+
+    return matches;
+}
+
+/// Bottom-up traversal of the LCP intervals (Abouelhoda, Kurtz & Ohlebusch 2004) whose LCP value is at least
+/// `min_len`. Calls `f(lb, rb, lcp, splits)` where `splits` are the ranks that start each child interval after the first.
+fn for_each_lcp_interval<F>(suffix_array: &SuffixArray, min_len: usize, mut f: F)
+where
+    F: FnMut(usize, usize, usize, &[usize]),
+{
+    let n = suffix_array.len();
+    let lcp = suffix_array.lcps();
+    let mut stack: Vec<(usize, usize, Vec<usize>)> = vec![(0, 0, Vec::new())];
+
+    for i in 1..=n {
+        let current = lcp.get(i).copied().unwrap_or(0);
+        let mut lb = i - 1;
+        while current < stack.last().unwrap().0 {
+            let (value, interval_lb, splits) = stack.pop().unwrap();
+            if value >= min_len {
+                f(interval_lb, i - 1, value, &splits);
+            }
+            lb = interval_lb;
+        }
+        let top = stack.last_mut().unwrap();
+        if current > top.0 {
+            stack.push((current, lb, vec![i]));
+        } else if current == top.0 && i < n {
+            top.2.push(i);
+        }
+    }
+}
+
+fn left_char(suffix_array: &SuffixArray, pos: usize) -> Option<u32> {
+    let (_, offset) = suffix_array.source(pos);
+    return if offset == 0 { None } else { Some(suffix_array.text()[pos - 1]) };
+}
+
+fn to_hit(suffix_array: &SuffixArray, info: &[SequenceInfo], pos: usize, len: usize) -> MatchHit {
+    let (sequence, offset) = suffix_array.source(pos);
+    let strand = info[sequence].strand;
+    let start = match strand {
+        Strand::Forward => offset,
+        Strand::Reverse => suffix_array.sequence_len(sequence) - offset - len,
+    };
+    return MatchHit {
+        genome: info[sequence].genome,
+        sequence,
+        start,
+        strand,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::nucleotide::reverse_complement;
+
+    fn hit(genome: usize, sequence: usize, start: usize, strand: Strand) -> MatchHit {
+        return MatchHit {
+            genome,
+            sequence,
+            start,
+            strand,
+        };
+    }
+
+    /// Lays the genomes out the way `hw1` does: the first on its forward strand only, the second on both.
+    fn hw1_layout(first: &[u8], second: &[u8]) -> (SuffixArray, Vec<SequenceInfo>) {
+        let reverse = reverse_complement(second);
+        let suffix_array = SuffixArray::new(&[first, second, &reverse]);
+        let info = vec![
+            SequenceInfo {
+                genome: 0,
+                strand: Strand::Forward,
+            },
+            SequenceInfo {
+                genome: 1,
+                strand: Strand::Forward,
+            },
+            SequenceInfo {
+                genome: 1,
+                strand: Strand::Reverse,
+            },
+        ];
+        return (suffix_array, info);
+    }
+
+    #[test]
+    fn a_match_starting_both_sequences_is_maximal() {
+        let (suffix_array, info) = hw1_layout(b"GATTACAT", b"CCGATTAC");
+        let expected = vec![MaximalMatch {
+            len: 6,
+            hits: vec![hit(0, 0, 0, Strand::Forward), hit(1, 1, 2, Strand::Forward)],
+        }];
+        assert_eq!(maximal_exact_matches(&suffix_array, &info, 3), expected);
+        assert_eq!(maximal_unique_matches(&suffix_array, &info, 3), expected);
+        assert!(maximal_unique_matches(&suffix_array, &info, 7).is_empty());
+    }
+
+    #[test]
+    fn reverse_strand_hits_are_given_in_forward_coordinates() {
+        // GTAATC is the reverse complement of GATTAC at 2..8 of the second genome
+        let (suffix_array, info) = hw1_layout(b"TTGTAATCAA", b"CCGATTAC");
+        let expected = vec![MaximalMatch {
+            len: 6,
+            hits: vec![hit(0, 0, 2, Strand::Forward), hit(1, 2, 2, Strand::Reverse)],
+        }];
+        assert_eq!(maximal_exact_matches(&suffix_array, &info, 3), expected);
+        assert_eq!(maximal_unique_matches(&suffix_array, &info, 3), expected);
+    }
+
+    #[test]
+    fn a_repeated_match_is_a_mem_for_each_copy_but_not_a_mum() {
+        let first: &[u8] = b"AGATTACTTGATTACA";
+        let second: &[u8] = b"CGATTACG";
+        let suffix_array = SuffixArray::new(&[first, second]);
+        let info = vec![
+            SequenceInfo {
+                genome: 0,
+                strand: Strand::Forward,
+            },
+            SequenceInfo {
+                genome: 1,
+                strand: Strand::Forward,
+            },
+        ];
+        let mut mems = maximal_exact_matches(&suffix_array, &info, 4);
+        mems.sort_by_key(|m| m.hits[0].start);
+        assert_eq!(
+            mems,
+            vec![
+                MaximalMatch {
+                    len: 6,
+                    hits: vec![hit(0, 0, 1, Strand::Forward), hit(1, 1, 1, Strand::Forward)],
+                },
+                MaximalMatch {
+                    len: 6,
+                    hits: vec![hit(0, 0, 9, Strand::Forward), hit(1, 1, 1, Strand::Forward)],
+                },
+            ]
+        );
+        assert!(maximal_unique_matches(&suffix_array, &info, 4).is_empty());
+    }
+}
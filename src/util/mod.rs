@@ -1 +1,26 @@
+pub mod annotation;
+pub mod dag;
+pub mod dag_io;
+pub mod fasta;
+pub mod feature_io;
+pub mod fm_index;
+pub mod gene_caller;
+pub mod genetic_code;
+pub mod kmer;
+pub mod location;
+pub mod markov;
+pub mod maximal_match;
+pub mod motif;
+pub mod motif_io;
+pub mod nucleotide;
+pub mod orf;
+pub mod pwm;
 pub mod read;
+pub mod read_starts;
+pub mod scan;
+pub mod segments;
+pub mod significance;
+pub mod simulate;
+pub mod strand;
+pub mod suffix_array;
+pub mod translate;
@@ -0,0 +1,554 @@
+use crate::util::location::{self, Anchor};
+use crate::util::nucleotide::Base;
+use crate::util::pwm::Pwm;
+use gb_io::qualifier_key;
+use gb_io::seq::Seq;
+use rand::Rng;
+
+/// Windows used to seed EM. Each is scored after a single iteration and the best is run to convergence, like MEME's
+/// search over starting points but without trying every window.
+const EM_SEEDS: usize = 200;
+const EM_SEED_ITERATIONS: usize = 1;
+/// Independent Gibbs runs. Each stops once a full sweep hasn't improved on its best alignment for this many sweeps.
+const GIBBS_RESTARTS: usize = 5;
+const GIBBS_PLATEAU: usize = 20;
+/// Furthest every site is moved at once when checking for a shifted motif, and how many times EM is restarted from a
+/// shifted alignment.
+const MAX_SHIFT: i64 = 3;
+const SHIFT_ROUNDS: usize = 3;
+
+/// Code given to ambiguous bases, which no site may contain.
+const AMBIGUOUS: u8 = 4;
+
+/// How many sites each sequence is assumed to have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SiteModel {
+    /// One occurrence per sequence
+    Oops,
+    /// Zero or one occurrence per sequence
+    Zoops,
+}
+
+/// A motif occurrence.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Site {
+    /// Index of the sequence the site is in
+    pub sequence: usize,
+    /// 0-based start of the site in the sequence
+    pub start: usize,
+    /// Posterior probability of the site under the final model
+    pub probability: f64,
+}
+
+/// A motif learned from a set of sequences.
+#[derive(Debug, Clone)]
+pub struct DiscoveredMotif {
+    /// Matrix built from the reported sites, over the background the search used
+    pub pwm: Pwm,
+    pub sites: Vec<Site>,
+    /// Natural-log likelihood of the sequences under the motif and background mixture
+    pub log_likelihood: f64,
+    /// Fraction of sequences expected to have a site. Always one with `SiteModel::Oops`.
+    pub site_fraction: f64,
+}
+
+/// Finds over-represented, fixed-width motifs in unaligned sequences, e.g. the upstream regions of every CDS.
+///
+/// Motifs are found one at a time. The sites of each motif are masked out before looking for the next, so the same
+/// signal isn't found twice. Sites are only looked for on the given strand of each sequence.
+#[derive(Debug, Clone)]
+pub struct MotifSearch {
+    width: usize,
+    site_model: SiteModel,
+    pseudocount: f64,
+    iterations: usize,
+    tolerance: f64,
+    motifs: usize,
+}
+
+impl MotifSearch {
+    pub fn new(width: usize) -> MotifSearch {
+        assert!(width > 0, "motif width has to be positive");
+        return MotifSearch {
+            width,
+            site_model: SiteModel::Oops,
+            pseudocount: 1.0,
+            iterations: 200,
+            tolerance: 1e-6,
+            motifs: 1,
+        };
+    }
+
+    pub fn site_model(mut self, site_model: SiteModel) -> MotifSearch {
+        self.site_model = site_model;
+        return self;
+    }
+
+    /// Number of motifs to find.
+    pub fn motifs(mut self, motifs: usize) -> MotifSearch {
+        self.motifs = motifs;
+        return self;
+    }
+
+    /// The background the search uses for the given sequences, which is their base composition.
+    pub fn background_for<S: AsRef<[u8]>>(&self, seqs: &[S]) -> [f64; 4] {
+        let mut counts = [1.0; 4];
+        for b in seqs.iter().flat_map(|s| s.as_ref()) {
+            if let Some(code) = Base::from_u8(*b).and_then(|base| base.to_2bit()) {
+                counts[code as usize] += 1.0;
+            }
+        }
+        let total: f64 = counts.iter().sum();
+        return counts.map(|c| c / total);
+    }
+
+    /// Learns motifs by expectation-maximization, as MEME does.
+    pub fn em<S: AsRef<[u8]>>(&self, seqs: &[S]) -> Vec<DiscoveredMotif> {
+        let background = self.background_for(seqs);
+        let mut codes = encode(seqs);
+        let mut motifs = Vec::new();
+        for _ in 0..self.motifs {
+            let windows = self.valid_windows(&codes);
+            let Some(motif) = self.em_one(&codes, &windows, background) else {
+                break;
+            };
+            self.erase(&mut codes, &motif.sites);
+            motifs.push(motif);
+        }
+        return motifs;
+    }
+
+    /// Learns motifs by Gibbs sampling (Lawrence et al. 1993), resampling one sequence's site at a time.
+    pub fn gibbs<S: AsRef<[u8]>, R: Rng>(&self, seqs: &[S], rng: &mut R) -> Vec<DiscoveredMotif> {
+        let background = self.background_for(seqs);
+        let mut codes = encode(seqs);
+        let mut motifs = Vec::new();
+        for _ in 0..self.motifs {
+            let windows = self.valid_windows(&codes);
+            let Some(motif) = self.gibbs_one(&codes, &windows, background, rng) else {
+                break;
+            };
+            self.erase(&mut codes, &motif.sites);
+            motifs.push(motif);
+        }
+        return motifs;
+    }
+
+    fn em_one(&self, codes: &[Vec<u8>], windows: &[Vec<usize>], background: [f64; 4]) -> Option<DiscoveredMotif> {
+        let total: usize = windows.iter().map(|w| w.len()).sum();
+        if total == 0 {
+            return None;
+        }
+
+        // Seed from windows spread evenly over the data, keeping whichever looks best after a few iterations
+        let step = total.div_ceil(EM_SEEDS);
+        let all_windows: Vec<(usize, usize)> = windows.iter().enumerate().flat_map(|(i, w)| w.iter().map(move |j| (i, *j))).collect();
+        let mut best: Option<(f64, Vec<[f64; 4]>, f64)> = None;
+        for (i, j) in all_windows.iter().step_by(step) {
+            let mut theta: Vec<[f64; 4]> = codes[*i][*j..*j + self.width]
+                .iter()
+                .map(|c| [0, 1, 2, 3].map(|b| if b == *c as usize { 0.5 } else { 0.5 / 3.0 }))
+                .collect();
+            let mut gamma = 0.5;
+            let mut ll = f64::MIN;
+            for _ in 0..EM_SEED_ITERATIONS {
+                let (posteriors, seed_ll) = self.expectation(codes, windows, &theta, background, gamma);
+                ll = seed_ll;
+                (theta, gamma) = self.maximization(codes, windows, &posteriors, background);
+            }
+            if best.as_ref().is_none_or(|b| ll > b.0) {
+                best = Some((ll, theta, gamma));
+            }
+        }
+
+        let (_, mut theta, mut gamma) = best?;
+        for _ in 0..=SHIFT_ROUNDS {
+            let mut previous = f64::MIN;
+            for _ in 0..self.iterations {
+                let (posteriors, ll) = self.expectation(codes, windows, &theta, background, gamma);
+                (theta, gamma) = self.maximization(codes, windows, &posteriors, background);
+                if (ll - previous).abs() < self.tolerance {
+                    break;
+                }
+                previous = ll;
+            }
+            // EM can settle on a shifted copy of the motif. If the best sites fit better moved over, restart from there
+            let posteriors = self.expectation(codes, windows, &theta, background, gamma).0;
+            let sites: Vec<Option<usize>> = windows
+                .iter()
+                .zip(&posteriors)
+                .map(|(w, z)| w.iter().zip(z).max_by(|a, b| a.1.partial_cmp(b.1).unwrap()).map(|(j, _)| *j))
+                .collect();
+            let (shift, _, shifted) = self.best_shift(codes, windows, &sites, background, gamma);
+            if shift == 0 {
+                break;
+            }
+            theta = self.estimate(codes, &shifted, background);
+        }
+        return Some(self.finish(codes, windows, &theta, background, gamma));
+    }
+
+    fn gibbs_one<R: Rng>(&self, codes: &[Vec<u8>], windows: &[Vec<usize>], background: [f64; 4], rng: &mut R) -> Option<DiscoveredMotif> {
+        let sequences: Vec<usize> = (0..codes.len()).filter(|i| !windows[*i].is_empty()).collect();
+        if sequences.is_empty() {
+            return None;
+        }
+
+        let mut best: Option<(f64, Vec<Option<usize>>)> = None;
+        for _ in 0..GIBBS_RESTARTS {
+            let mut sites: Vec<Option<usize>> = vec![None; codes.len()];
+            for i in &sequences {
+                sites[*i] = Some(windows[*i][rng.gen_range(0..windows[*i].len())]);
+            }
+            let mut restart_best = f64::MIN;
+            let mut since_improved = 0;
+            for _ in 0..self.iterations {
+                // Counts are kept up to date as each site is taken out and put back, rather than recounted every time
+                let mut counts = self.site_counts(codes, &sites);
+                let mut placed = sites.iter().flatten().count();
+                for i in &sequences {
+                    if let Some(j) = sites[*i].take() {
+                        self.count_site(&mut counts, &codes[*i], j, -1.0);
+                        placed -= 1;
+                    }
+                    let theta = self.frequencies(&counts, background);
+                    let gamma = self.gibbs_gamma(placed, sequences.len());
+                    sites[*i] = self.sample_site(&codes[*i], &windows[*i], &theta, background, gamma, rng);
+                    if let Some(j) = sites[*i] {
+                        self.count_site(&mut counts, &codes[*i], j, 1.0);
+                        placed += 1;
+                    }
+                }
+                // Sampling one site at a time can't escape a shifted copy of the motif, so try moving them all
+                let gamma = self.gibbs_gamma(placed, sequences.len());
+                let (_, score, shifted) = self.best_shift(codes, windows, &sites, background, gamma);
+                sites = shifted;
+                if score > restart_best {
+                    restart_best = score;
+                    since_improved = 0;
+                    if best.as_ref().is_none_or(|b| score > b.0) {
+                        best = Some((score, sites.clone()));
+                    }
+                } else {
+                    since_improved += 1;
+                    if since_improved >= GIBBS_PLATEAU {
+                        break;
+                    }
+                }
+            }
+        }
+
+        let (_, sites) = best?;
+        let theta = self.estimate(codes, &sites, background);
+        let gamma = self.gibbs_gamma(sites.iter().flatten().count(), sequences.len());
+        return Some(self.finish(codes, windows, &theta, background, gamma));
+    }
+
+    /// Of the sites moved together by up to `MAX_SHIFT` bases either way, the shift whose sites give the most likely
+    /// model, along with that likelihood and the moved sites. Sites that would move onto an invalid window stay put.
+    fn best_shift(
+        &self,
+        codes: &[Vec<u8>],
+        windows: &[Vec<usize>],
+        sites: &[Option<usize>],
+        background: [f64; 4],
+        gamma: f64,
+    ) -> (i64, f64, Vec<Option<usize>>) {
+        let mut best: Option<(i64, f64, Vec<Option<usize>>)> = None;
+        for shift in -MAX_SHIFT..=MAX_SHIFT {
+            let shifted: Vec<Option<usize>> = sites
+                .iter()
+                .zip(windows)
+                .map(|(site, w)| {
+                    let j = (*site)?;
+                    let moved = j as i64 + shift;
+                    Some(if moved >= 0 && w.binary_search(&(moved as usize)).is_ok() {
+                        moved as usize
+                    } else {
+                        j
+                    })
+                })
+                .collect();
+            let theta = self.estimate(codes, &shifted, background);
+            let score = self.expectation(codes, windows, &theta, background, gamma).1;
+            // Ties go to the smallest shift, so an alignment that's already best isn't moved
+            if best.as_ref().is_none_or(|b| score > b.1 || (score == b.1 && shift.abs() < b.0.abs())) {
+                best = Some((shift, score, shifted));
+            }
+        }
+        return best.unwrap();
+    }
+
+    /// Posterior probability of a site at every window, and the log-likelihood of the sequences.
+    fn expectation(&self, codes: &[Vec<u8>], windows: &[Vec<usize>], theta: &[[f64; 4]], background: [f64; 4], gamma: f64) -> (Vec<Vec<f64>>, f64) {
+        let log_theta: Vec<[f64; 4]> = theta.iter().map(|t| t.map(f64::ln)).collect();
+        let log_background = background.map(f64::ln);
+        let mut ll = 0.0;
+        let mut posteriors = Vec::with_capacity(codes.len());
+        for (seq, windows) in codes.iter().zip(windows) {
+            ll += seq.iter().filter(|c| **c != AMBIGUOUS).map(|c| log_background[*c as usize]).sum::<f64>();
+            if windows.is_empty() {
+                posteriors.push(Vec::new());
+                continue;
+            }
+            let m = windows.len() as f64;
+            let (prior, none) = match self.site_model {
+                SiteModel::Oops => ((1.0 / m).ln(), f64::NEG_INFINITY),
+                SiteModel::Zoops => ((gamma / m).ln(), (1.0 - gamma).ln()),
+            };
+            let scores: Vec<f64> = windows
+                .iter()
+                .map(|j| prior + log_odds(&seq[*j..*j + self.width], &log_theta, &log_background))
+                .collect();
+            let max = scores.iter().copied().fold(none, f64::max);
+            let norm = max + ((none - max).exp() + scores.iter().map(|s| (s - max).exp()).sum::<f64>()).ln();
+            ll += norm;
+            posteriors.push(scores.iter().map(|s| (s - norm).exp()).collect());
+        }
+        return (posteriors, ll);
+    }
+
+    /// Motif frequencies from the expected counts, and the expected fraction of sequences with a site.
+    fn maximization(&self, codes: &[Vec<u8>], windows: &[Vec<usize>], posteriors: &[Vec<f64>], background: [f64; 4]) -> (Vec<[f64; 4]>, f64) {
+        let mut counts = vec![[0.0; 4]; self.width];
+        let mut expected_sites = 0.0;
+        let mut sequences = 0;
+        for ((seq, windows), z) in codes.iter().zip(windows).zip(posteriors) {
+            if !windows.is_empty() {
+                sequences += 1;
+            }
+            for (j, p) in windows.iter().zip(z) {
+                expected_sites += p;
+                for (column, c) in counts.iter_mut().zip(&seq[*j..*j + self.width]) {
+                    column[*c as usize] += p;
+                }
+            }
+        }
+        let gamma = (expected_sites / sequences.max(1) as f64).clamp(1e-6, 1.0 - 1e-6);
+        return (self.frequencies(&counts, background), gamma);
+    }
+
+    /// Motif frequencies from the chosen sites.
+    fn estimate(&self, codes: &[Vec<u8>], sites: &[Option<usize>], background: [f64; 4]) -> Vec<[f64; 4]> {
+        return self.frequencies(&self.site_counts(codes, sites), background);
+    }
+
+    fn site_counts(&self, codes: &[Vec<u8>], sites: &[Option<usize>]) -> Vec<[f64; 4]> {
+        let mut counts = vec![[0.0; 4]; self.width];
+        for (seq, site) in codes.iter().zip(sites) {
+            if let Some(j) = site {
+                self.count_site(&mut counts, seq, *j, 1.0);
+            }
+        }
+        return counts;
+    }
+
+    /// Adds `weight` to the counts of the bases of the site at `j`, or takes it away when negative.
+    fn count_site(&self, counts: &mut [[f64; 4]], seq: &[u8], j: usize, weight: f64) {
+        for (column, c) in counts.iter_mut().zip(&seq[j..j + self.width]) {
+            column[*c as usize] += weight;
+        }
+    }
+
+    fn frequencies(&self, counts: &[[f64; 4]], background: [f64; 4]) -> Vec<[f64; 4]> {
+        // A small floor keeps a base from being ruled out for good when there's no pseudocount
+        let pseudocount = self.pseudocount.max(1e-6);
+        return counts
+            .iter()
+            .map(|column| {
+                let total: f64 = column.iter().sum::<f64>() + pseudocount;
+                [0, 1, 2, 3].map(|c| (column[c] + pseudocount * background[c]) / total)
+            })
+            .collect();
+    }
+
+    /// Fraction of sequences expected to have a site, given how many of them have one placed.
+    fn gibbs_gamma(&self, placed: usize, sequences: usize) -> f64 {
+        return match self.site_model {
+            SiteModel::Oops => 1.0,
+            // Sequences without a site have to stay possible, or ZOOPS collapses back to OOPS
+            SiteModel::Zoops => ((placed as f64 + 1.0) / (sequences as f64 + 2.0)).clamp(1e-6, 1.0 - 1e-6),
+        };
+    }
+
+    fn sample_site<R: Rng>(&self, seq: &[u8], windows: &[usize], theta: &[[f64; 4]], background: [f64; 4], gamma: f64, rng: &mut R) -> Option<usize> {
+        let log_theta: Vec<[f64; 4]> = theta.iter().map(|t| t.map(f64::ln)).collect();
+        let log_background = background.map(f64::ln);
+        let m = windows.len() as f64;
+        let (prior, none) = match self.site_model {
+            SiteModel::Oops => ((1.0 / m).ln(), f64::NEG_INFINITY),
+            SiteModel::Zoops => ((gamma / m).ln(), (1.0 - gamma).ln()),
+        };
+        let scores: Vec<f64> = windows
+            .iter()
+            .map(|j| prior + log_odds(&seq[*j..*j + self.width], &log_theta, &log_background))
+            .collect();
+        let max = scores.iter().copied().fold(none, f64::max);
+        let weights: Vec<f64> = scores.iter().map(|s| (s - max).exp()).collect();
+        let total = (none - max).exp() + weights.iter().sum::<f64>();
+
+        let mut draw = rng.gen::<f64>() * total;
+        for (j, w) in windows.iter().zip(&weights) {
+            draw -= w;
+            if draw < 0.0 {
+                return Some(*j);
+            }
+        }
+        return match self.site_model {
+            SiteModel::Oops => windows.last().copied(),
+            SiteModel::Zoops => None,
+        };
+    }
+
+    /// Picks the sites of the final model and builds the matrix from them.
+    fn finish(&self, codes: &[Vec<u8>], windows: &[Vec<usize>], theta: &[[f64; 4]], background: [f64; 4], gamma: f64) -> DiscoveredMotif {
+        let (posteriors, log_likelihood) = self.expectation(codes, windows, theta, background, gamma);
+        let mut sites = Vec::new();
+        for (i, (windows, z)) in windows.iter().zip(&posteriors).enumerate() {
+            let Some((j, p)) = windows.iter().zip(z).max_by(|a, b| a.1.partial_cmp(b.1).unwrap()) else {
+                continue;
+            };
+            // Under ZOOPS a sequence only has a site if it's more likely to have one than not
+            if self.site_model == SiteModel::Oops || z.iter().sum::<f64>() >= 0.5 {
+                sites.push(Site {
+                    sequence: i,
+                    start: *j,
+                    probability: *p,
+                });
+            }
+        }
+        let instances: Vec<Vec<u8>> = sites
+            .iter()
+            .map(|s| {
+                codes[s.sequence][s.start..s.start + self.width]
+                    .iter()
+                    .map(|c| Base::from_2bit(*c).to_u8())
+                    .collect()
+            })
+            .collect();
+        let pwm = if instances.is_empty() {
+            Pwm::from_counts(vec![[0.0; 4]; self.width], 0, self.pseudocount, background)
+        } else {
+            Pwm::from_sites(instances, 0, self.pseudocount, background).unwrap()
+        };
+        return DiscoveredMotif {
+            pwm,
+            sites,
+            log_likelihood,
+            site_fraction: if self.site_model == SiteModel::Oops { 1.0 } else { gamma },
+        };
+    }
+
+    /// Masks the sites so later motifs can't reuse them.
+    fn erase(&self, codes: &mut [Vec<u8>], sites: &[Site]) {
+        for site in sites {
+            codes[site.sequence][site.start..site.start + self.width].fill(AMBIGUOUS);
+        }
+    }
+
+    /// Start of every window of each sequence that doesn't contain an ambiguous base.
+    fn valid_windows(&self, codes: &[Vec<u8>]) -> Vec<Vec<usize>> {
+        return codes
+            .iter()
+            .map(|seq| {
+                if seq.len() < self.width {
+                    return Vec::new();
+                }
+                (0..=seq.len() - self.width)
+                    .filter(|j| !seq[*j..*j + self.width].contains(&AMBIGUOUS))
+                    .collect()
+            })
+            .collect();
+    }
+}
+
+/// The `len` bases upstream of the 5' end of every feature of the given kinds, read on the feature's strand and named
+/// by the feature's locus tag, gene or position. Features with a partial 5' end, or too close to the end of a linear
+/// record, are skipped.
+pub fn upstream_regions(seqs: &[Seq], kinds: &[&str], len: usize) -> Vec<(String, Vec<u8>)> {
+    let mut regions = Vec::new();
+    for seq in seqs {
+        for feature in seq.features.iter().filter(|f| kinds.contains(&&*f.kind)) {
+            let Ok(mut region) = location::flank(seq, &feature.location, Anchor::Start, len, 0) else {
+                continue;
+            };
+            // The flank ends with the first base of the feature itself
+            region.pop();
+            let name = feature
+                .qualifier_values(qualifier_key!("locus_tag"))
+                .chain(feature.qualifier_values(qualifier_key!("gene")))
+                .next()
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| format!("{}:{}", seq.name.as_deref().unwrap_or(""), feature.location));
+            regions.push((name, region));
+        }
+    }
+    return regions;
+}
+
+fn encode<S: AsRef<[u8]>>(seqs: &[S]) -> Vec<Vec<u8>> {
+    return seqs
+        .iter()
+        .map(|s| {
+            s.as_ref()
+                .iter()
+                .map(|b| Base::from_u8(*b).and_then(|base| base.to_2bit()).unwrap_or(AMBIGUOUS))
+                .collect()
+        })
+        .collect();
+}
+
+fn log_odds(window: &[u8], log_theta: &[[f64; 4]], log_background: &[f64; 4]) -> f64 {
+    return window.iter().zip(log_theta).map(|(c, t)| t[*c as usize] - log_background[*c as usize]).sum();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    const PLANTED: &[u8] = b"TTGACGATCA";
+
+    /// Random sequences with `PLANTED` at a random place in each, and where it was put.
+    fn planted_sequences(rng: &mut StdRng) -> (Vec<Vec<u8>>, Vec<usize>) {
+        let mut seqs = Vec::new();
+        let mut starts = Vec::new();
+        for _ in 0..30 {
+            let mut seq: Vec<u8> = (0..60).map(|_| b"ACGT"[rng.gen_range(0..4)]).collect();
+            let start = rng.gen_range(0..=seq.len() - PLANTED.len());
+            seq[start..start + PLANTED.len()].copy_from_slice(PLANTED);
+            seqs.push(seq);
+            starts.push(start);
+        }
+        return (seqs, starts);
+    }
+
+    fn assert_recovered(motifs: &[DiscoveredMotif], starts: &[usize]) {
+        assert_eq!(motifs.len(), 1);
+        let motif = &motifs[0];
+        assert_eq!(motif.pwm.consensus().as_bytes(), PLANTED);
+        let found = motif.sites.iter().filter(|s| s.start == starts[s.sequence]).count();
+        assert!(found >= 28, "only {} of {} planted sites found", found, starts.len());
+    }
+
+    #[test]
+    fn em_recovers_a_planted_motif() {
+        let mut rng = StdRng::seed_from_u64(14);
+        let (seqs, starts) = planted_sequences(&mut rng);
+        for site_model in [SiteModel::Oops, SiteModel::Zoops] {
+            let search = MotifSearch::new(PLANTED.len()).site_model(site_model);
+            assert_recovered(&search.em(&seqs), &starts);
+        }
+    }
+
+    #[test]
+    fn gibbs_recovers_a_planted_motif() {
+        let mut rng = StdRng::seed_from_u64(14);
+        let (seqs, starts) = planted_sequences(&mut rng);
+        for site_model in [SiteModel::Oops, SiteModel::Zoops] {
+            let search = MotifSearch::new(PLANTED.len()).site_model(site_model);
+            assert_recovered(&search.gibbs(&seqs, &mut rng), &starts);
+        }
+    }
+}
@@ -0,0 +1,400 @@
+use crate::error::LineError;
+use crate::util::pwm::Pwm;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Error, Write};
+use std::path::Path;
+
+/// Number of sites assumed for a MEME matrix without an `nsites=` field, the same default MEME's own converters use.
+const DEFAULT_MEME_SITES: f64 = 20.0;
+
+/// Text formats for sharing motifs with other tools.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotifFormat {
+    /// JASPAR count matrix: an optional `>id name` header and four rows of counts for A, C, G and T
+    Pfm,
+    /// JASPAR's own format, with lettered and bracketed count rows
+    Jaspar,
+    /// MEME minimal motif format, with letter-probability matrices
+    Meme,
+    /// TRANSFAC matrix entries, one count row per position
+    Transfac,
+}
+
+impl MotifFormat {
+    /// Guesses the format from a file extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<MotifFormat> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        return match extension.as_str() {
+            "pfm" => Some(MotifFormat::Pfm),
+            "jaspar" => Some(MotifFormat::Jaspar),
+            "meme" => Some(MotifFormat::Meme),
+            "transfac" | "dat" => Some(MotifFormat::Transfac),
+            _ => None,
+        };
+    }
+}
+
+/// A named position weight matrix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Motif {
+    pub id: String,
+    /// Free-text name, often the transcription factor. Empty when the file doesn't give one.
+    pub name: String,
+    pub pwm: Pwm,
+}
+
+/// Reads every motif in a file. Counts are kept as written, fractions included, and MEME probabilities are turned
+/// back into counts using the matrix's `nsites` without rounding, so the frequencies match the file's. The matrices
+/// are built with the given pseudocount and background.
+pub fn read_motifs<P: AsRef<Path>>(file_path: P, format: MotifFormat, pseudocount: f64, background: [f64; 4]) -> io::Result<Vec<Motif>> {
+    return parse_motifs(&fs::read_to_string(file_path)?, format, pseudocount, background);
+}
+
+pub fn parse_motifs(text: &str, format: MotifFormat, pseudocount: f64, background: [f64; 4]) -> io::Result<Vec<Motif>> {
+    let lines: Vec<(usize, &str)> = text.lines().enumerate().map(|(i, l)| (i + 1, l.trim())).collect();
+    let motifs = match format {
+        MotifFormat::Pfm => parse_jaspar(&lines, false)?,
+        MotifFormat::Jaspar => parse_jaspar(&lines, true)?,
+        MotifFormat::Meme => parse_meme(&lines)?,
+        MotifFormat::Transfac => parse_transfac(&lines)?,
+    };
+    return Ok(motifs
+        .into_iter()
+        .map(|(id, name, counts)| Motif {
+            id,
+            name,
+            pwm: Pwm::from_counts(counts, 0, pseudocount, background),
+        })
+        .collect());
+}
+
+/// Writes the motifs to a file. Position labels aren't part of any of the formats, so the offset is lost. Whole counts
+/// are written as integers and fractional ones with as many decimals as they need, up to six.
+pub fn write_motifs_to_path<P: AsRef<Path>>(file_path: P, motifs: &[Motif], format: MotifFormat) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(file_path)?);
+    write_motifs(&mut writer, motifs, format)?;
+    return writer.flush();
+}
+
+pub fn write_motifs<W: Write>(writer: &mut W, motifs: &[Motif], format: MotifFormat) -> io::Result<()> {
+    if format == MotifFormat::Meme {
+        // MEME has a single background for the whole file
+        let background = motifs.first().map(|m| m.pwm.background()).unwrap_or([0.25; 4]);
+        writeln!(writer, "MEME version 4\n\nALPHABET= ACGT\n\nstrands: + -\n\nBackground letter frequencies")?;
+        writeln!(
+            writer,
+            "A {:.6} C {:.6} G {:.6} T {:.6}\n",
+            background[0], background[1], background[2], background[3]
+        )?;
+    }
+    for motif in motifs {
+        let counts = motif.pwm.counts();
+        match format {
+            MotifFormat::Pfm => {
+                writeln!(writer, ">{}", header(motif, ' '))?;
+                for c in 0..4 {
+                    writeln!(
+                        writer,
+                        "{}",
+                        counts.iter().map(|column| format_count(column[c])).collect::<Vec<String>>().join(" ")
+                    )?;
+                }
+            }
+            MotifFormat::Jaspar => {
+                writeln!(writer, ">{}", header(motif, '\t'))?;
+                for (c, letter) in "ACGT".chars().enumerate() {
+                    let row: String = counts.iter().map(|column| format!(" {:>6}", format_count(column[c]))).collect();
+                    writeln!(writer, "{}  [{} ]", letter, row)?;
+                }
+            }
+            MotifFormat::Meme => {
+                writeln!(writer, "MOTIF {}", header(motif, ' '))?;
+                writeln!(
+                    writer,
+                    "letter-probability matrix: alength= 4 w= {} nsites= {} E= 0",
+                    motif.pwm.len(),
+                    // MEME's site count is a whole number
+                    motif.pwm.site_count().round()
+                )?;
+                for freq in motif.pwm.frequencies() {
+                    writeln!(writer, " {:.6}  {:.6}  {:.6}  {:.6}", freq[0], freq[1], freq[2], freq[3])?;
+                }
+                writeln!(writer)?;
+            }
+            MotifFormat::Transfac => {
+                writeln!(writer, "AC  {}\nXX", motif.id)?;
+                if !motif.name.is_empty() {
+                    writeln!(writer, "ID  {}\nXX", motif.name)?;
+                }
+                writeln!(writer, "P0      A      C      G      T")?;
+                for (i, column) in counts.iter().enumerate() {
+                    let [a, c, g, t] = column.map(format_count);
+                    writeln!(writer, "{:02} {:>6} {:>6} {:>6} {:>6}", i + 1, a, c, g, t)?;
+                }
+                writeln!(writer, "XX\n//")?;
+            }
+        }
+    }
+    return Ok(());
+}
+
+fn format_count(count: f64) -> String {
+    if count.fract() == 0.0 {
+        return format!("{}", count);
+    }
+    let formatted = format!("{:.6}", count);
+    return formatted.trim_end_matches('0').trim_end_matches('.').to_string();
+}
+
+fn header(motif: &Motif, separator: char) -> String {
+    return if motif.name.is_empty() {
+        motif.id.clone()
+    } else {
+        format!("{}{}{}", motif.id, separator, motif.name)
+    };
+}
+
+type ParsedMotif = (String, String, Vec<[f64; 4]>);
+/// Header line, id, name and the count rows under it
+type CountBlock<'a> = (usize, String, String, Vec<(usize, &'a str)>);
+
+/// Both JASPAR formats: motifs start at a `>` header (optional when there's only one) followed by four count rows.
+/// In the `jaspar` format each row starts with its letter and the counts are in brackets.
+fn parse_jaspar(lines: &[(usize, &str)], lettered: bool) -> io::Result<Vec<ParsedMotif>> {
+    // Group the count rows under their headers first
+    let mut blocks: Vec<CountBlock> = Vec::new();
+    for (line_no, line) in lines {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('>') {
+            let mut fields = header.trim().splitn(2, char::is_whitespace);
+            let id = fields.next().unwrap_or("").to_string();
+            blocks.push((*line_no, id, fields.next().unwrap_or("").trim().to_string(), Vec::new()));
+            continue;
+        }
+        if blocks.is_empty() {
+            blocks.push((*line_no, "motif_1".to_string(), String::new(), Vec::new()));
+        }
+        blocks.last_mut().unwrap().3.push((*line_no, line));
+    }
+    return blocks
+        .into_iter()
+        .map(|(line_no, id, name, rows)| parse_count_rows(line_no, id, name, &rows, lettered))
+        .collect();
+}
+
+fn parse_count_rows(line_no: usize, id: String, name: String, rows: &[(usize, &str)], lettered: bool) -> io::Result<ParsedMotif> {
+    if rows.len() != 4 {
+        return Err(invalid(line_no, format!("motif '{}' has {} count rows, expected 4", id, rows.len())));
+    }
+    let mut ordered = vec![Vec::new(); 4];
+    for (i, (row_no, row)) in rows.iter().enumerate() {
+        let (c, row) = if lettered {
+            let letter = row.chars().next().unwrap().to_ascii_uppercase();
+            let c = "ACGT"
+                .find(letter)
+                .ok_or_else(|| invalid(*row_no, format!("expected a row for A, C, G or T, found '{}'", row)))?;
+            (c, row[1..].trim().trim_start_matches('[').trim_end_matches(']'))
+        } else {
+            (i, *row)
+        };
+        ordered[c] = parse_numbers(*row_no, row.split_whitespace())?;
+    }
+    if ordered.iter().any(|r| r.is_empty()) {
+        return Err(invalid(line_no, format!("motif '{}' doesn't have a row for every base", id)));
+    }
+    return Ok((id, name, columns_from_rows(line_no, &ordered)?));
+}
+
+/// MEME minimal format: a `MOTIF id [name]` line, then a `letter-probability matrix:` line giving the width and
+/// site count, then one row of A, C, G and T probabilities per position.
+fn parse_meme(lines: &[(usize, &str)]) -> io::Result<Vec<ParsedMotif>> {
+    let mut motifs = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let (line_no, line) = lines[i];
+        i += 1;
+        if let Some(alphabet) = line.strip_prefix("ALPHABET=") {
+            if !["ACGT", "ACGU"].contains(&alphabet.trim()) {
+                return Err(invalid(line_no, format!("only DNA motifs are supported, found alphabet '{}'", alphabet.trim())));
+            }
+            continue;
+        }
+        let Some(header) = line.strip_prefix("MOTIF") else {
+            continue;
+        };
+        let mut fields = header.split_whitespace();
+        let id = fields
+            .next()
+            .ok_or_else(|| invalid(line_no, "MOTIF line without an id".to_string()))?
+            .to_string();
+        let name = fields.collect::<Vec<&str>>().join(" ");
+
+        // The matrix header can be a few lines down, after e.g. a log-odds matrix or a URL
+        while i < lines.len() && !lines[i].1.starts_with("letter-probability matrix") && !lines[i].1.starts_with("MOTIF") {
+            i += 1;
+        }
+        if i == lines.len() || lines[i].1.starts_with("MOTIF") {
+            return Err(invalid(line_no, format!("motif '{}' has no letter-probability matrix", id)));
+        }
+        let (matrix_line_no, matrix_line) = lines[i];
+        i += 1;
+        let width = meme_field(matrix_line, "w=");
+        let sites = meme_field(matrix_line, "nsites=").unwrap_or(DEFAULT_MEME_SITES);
+
+        let mut probabilities = Vec::new();
+        while i < lines.len() && width.is_none_or(|w| probabilities.len() < w as usize) {
+            let (row_no, row) = lines[i];
+            if row.is_empty() || !row.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+                if width.is_none() {
+                    break;
+                }
+                if row.is_empty() {
+                    i += 1;
+                    continue;
+                }
+                return Err(invalid(row_no, format!("expected a row of probabilities, found '{}'", row)));
+            }
+            let values = parse_numbers(row_no, row.split_whitespace())?;
+            if values.len() != 4 {
+                return Err(invalid(row_no, format!("expected 4 probabilities, found {}", values.len())));
+            }
+            probabilities.push(values);
+            i += 1;
+        }
+        if let Some(w) = width {
+            if probabilities.len() != w as usize {
+                return Err(invalid(
+                    matrix_line_no,
+                    format!("motif '{}' has {} rows, but w= {}", id, probabilities.len(), w),
+                ));
+            }
+        }
+        let counts = probabilities.iter().map(|p| [0, 1, 2, 3].map(|c| p[c] * sites)).collect();
+        motifs.push((id, name, counts));
+    }
+    return Ok(motifs);
+}
+
+fn meme_field(line: &str, key: &str) -> Option<f64> {
+    let start = line.find(key)? + key.len();
+    return line[start..].split_whitespace().next()?.parse().ok();
+}
+
+/// TRANSFAC: two-letter line codes, with `AC` the accession, `ID` the name, a `P0` (or `PO`) line giving the base
+/// order and then one numbered row of counts per position. Entries end at `//`.
+fn parse_transfac(lines: &[(usize, &str)]) -> io::Result<Vec<ParsedMotif>> {
+    let mut motifs = Vec::new();
+    let (mut accession, mut name) = (String::new(), String::new());
+    let mut order: Option<Vec<usize>> = None;
+    let mut columns: Vec<[f64; 4]> = Vec::new();
+    let mut start = 0;
+
+    for (line_no, line) in lines {
+        let (code, value) = match line.split_once(char::is_whitespace) {
+            Some((code, value)) => (code, value.trim()),
+            None => (*line, ""),
+        };
+        match code {
+            "AC" => accession = value.to_string(),
+            "ID" => name = value.to_string(),
+            "P0" | "PO" => {
+                start = *line_no;
+                let letters: Vec<&str> = value.split_whitespace().collect();
+                let indices: Option<Vec<usize>> = letters.iter().map(|l| "ACGT".find(&l.to_ascii_uppercase())).collect();
+                order = match indices {
+                    Some(indices) if letters.len() == 4 => Some(indices),
+                    _ => return Err(invalid(*line_no, format!("expected the bases A, C, G and T, found '{}'", value))),
+                };
+            }
+            "//" => {
+                if order.take().is_some() {
+                    // JASPAR's exports put the matrix id in AC and the factor in ID, others only have an ID
+                    let id = if accession.is_empty() { name.clone() } else { accession.clone() };
+                    let name = if accession.is_empty() { String::new() } else { name.clone() };
+                    motifs.push((id, name, std::mem::take(&mut columns)));
+                } else if !columns.is_empty() {
+                    return Err(invalid(*line_no, "matrix rows without a P0 line".to_string()));
+                }
+                accession.clear();
+                name.clear();
+            }
+            _ if order.is_some() && code.chars().all(|c| c.is_ascii_digit()) && !code.is_empty() => {
+                let order = order.as_ref().unwrap();
+                let values = parse_numbers(*line_no, value.split_whitespace().take(4))?;
+                if values.len() != 4 {
+                    return Err(invalid(*line_no, format!("expected 4 counts, found {}", values.len())));
+                }
+                let mut column = [0.0; 4];
+                for (value, c) in values.iter().zip(order) {
+                    column[*c] = *value;
+                }
+                columns.push(column);
+            }
+            _ => {}
+        }
+    }
+    if order.is_some() {
+        return Err(invalid(start, "matrix doesn't end with '//'".to_string()));
+    }
+    return Ok(motifs);
+}
+
+/// Transposes four rows of counts (A, C, G and T) into per-position columns.
+fn columns_from_rows(line_no: usize, rows: &[Vec<f64>]) -> io::Result<Vec<[f64; 4]>> {
+    let width = rows[0].len();
+    if rows.iter().any(|r| r.len() != width) {
+        return Err(invalid(line_no, "count rows have different lengths".to_string()));
+    }
+    return Ok((0..width).map(|i| [0, 1, 2, 3].map(|c| rows[c][i])).collect());
+}
+
+fn parse_numbers<'a, I: Iterator<Item = &'a str>>(line_no: usize, fields: I) -> io::Result<Vec<f64>> {
+    return fields
+        .map(|f| match f.parse::<f64>() {
+            Ok(v) if v.is_finite() && v >= 0.0 => Ok(v),
+            _ => Err(invalid(line_no, format!("'{}' is not a count or probability", f))),
+        })
+        .collect();
+}
+
+fn invalid(line_no: usize, message: String) -> Error {
+    return LineError::invalid_data(line_no, message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(motifs: &[Motif], format: MotifFormat) -> Vec<Motif> {
+        let mut written = Vec::new();
+        write_motifs(&mut written, motifs, format).unwrap();
+        return parse_motifs(&String::from_utf8(written).unwrap(), format, 0.0, [0.25; 4]).unwrap();
+    }
+
+    #[test]
+    fn fractional_counts_survive_a_round_trip() {
+        let text = ">MA0001.1 AGL3\nA [ 0.5 3.25 0 ]\nC [ 1 0.75 0 ]\nG [ 2.5 0 4 ]\nT [ 0 0 0 ]\n";
+        let motifs = parse_motifs(text, MotifFormat::Jaspar, 0.0, [0.25; 4]).unwrap();
+        assert_eq!(motifs[0].pwm.counts(), &[[0.5, 1.0, 2.5, 0.0], [3.25, 0.75, 0.0, 0.0], [0.0, 0.0, 4.0, 0.0]]);
+        for format in [MotifFormat::Pfm, MotifFormat::Jaspar, MotifFormat::Transfac] {
+            assert_eq!(round_trip(&motifs, format)[0].pwm.counts(), motifs[0].pwm.counts());
+        }
+    }
+
+    #[test]
+    fn small_meme_probabilities_are_kept() {
+        let text = "MEME version 4\n\nALPHABET= ACGT\n\nMOTIF m1\nletter-probability matrix: alength= 4 w= 2 nsites= 20\n\
+                    0.010000 0.970000 0.010000 0.010000\n0.250000 0.250000 0.250000 0.250000\n";
+        let motifs = parse_motifs(text, MotifFormat::Meme, 0.0, [0.25; 4]).unwrap();
+        let freqs = motifs[0].pwm.frequencies();
+        assert!((freqs[0][0] - 0.01).abs() < 1e-9);
+        let again = round_trip(&motifs, MotifFormat::Meme);
+        for (a, b) in again[0].pwm.frequencies().iter().zip(freqs) {
+            for c in 0..4 {
+                assert!((a[c] - b[c]).abs() < 1e-6);
+            }
+        }
+    }
+}
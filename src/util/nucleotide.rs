@@ -0,0 +1,259 @@
+use std::fmt;
+
+/// A nucleotide symbol, including the IUPAC ambiguity codes.
+///
+/// Each variant's discriminant is the set of bases it can stand for, with one bit per base (A=1, C=2, G=4, T=8), so
+/// complementing is a bit swap and two symbols are compatible when their sets intersect.
+#[derive(Eq, Hash, PartialEq, Debug, Clone, Copy, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Base {
+    A = 0b0001,
+    C = 0b0010,
+    M = 0b0011,
+    G = 0b0100,
+    R = 0b0101,
+    S = 0b0110,
+    V = 0b0111,
+    T = 0b1000,
+    W = 0b1001,
+    Y = 0b1010,
+    H = 0b1011,
+    K = 0b1100,
+    D = 0b1101,
+    B = 0b1110,
+    N = 0b1111,
+}
+
+const BY_MASK: [Option<Base>; 16] = [
+    None,
+    Some(Base::A),
+    Some(Base::C),
+    Some(Base::M),
+    Some(Base::G),
+    Some(Base::R),
+    Some(Base::S),
+    Some(Base::V),
+    Some(Base::T),
+    Some(Base::W),
+    Some(Base::Y),
+    Some(Base::H),
+    Some(Base::K),
+    Some(Base::D),
+    Some(Base::B),
+    Some(Base::N),
+];
+
+impl Base {
+    /// The four unambiguous bases, in 2-bit code order.
+    pub const ACGT: [Base; 4] = [Base::A, Base::C, Base::G, Base::T];
+
+    /// Parses an IUPAC nucleotide symbol in either case. `U` is read as `T`.
+    pub const fn from_u8(b: u8) -> Option<Base> {
+        return match b.to_ascii_uppercase() {
+            b'A' => Some(Base::A),
+            b'C' => Some(Base::C),
+            b'G' => Some(Base::G),
+            b'T' | b'U' => Some(Base::T),
+            b'R' => Some(Base::R),
+            b'Y' => Some(Base::Y),
+            b'S' => Some(Base::S),
+            b'W' => Some(Base::W),
+            b'K' => Some(Base::K),
+            b'M' => Some(Base::M),
+            b'B' => Some(Base::B),
+            b'D' => Some(Base::D),
+            b'H' => Some(Base::H),
+            b'V' => Some(Base::V),
+            b'N' => Some(Base::N),
+            _ => None,
+        };
+    }
+
+    pub fn from_char(c: char) -> Option<Base> {
+        return if c.is_ascii() { Base::from_u8(c as u8) } else { None };
+    }
+
+    /// Like `from_u8`, but anything that isn't a nucleotide symbol becomes `N`.
+    pub const fn from_u8_lossy(b: u8) -> Base {
+        return match Base::from_u8(b) {
+            Some(base) => base,
+            None => Base::N,
+        };
+    }
+
+    pub fn from_char_lossy(c: char) -> Base {
+        return Base::from_char(c).unwrap_or(Base::N);
+    }
+
+    /// The symbol for a set of bases (A=1, C=2, G=4, T=8), e.g. `0b0101` is `R`. The empty set gives `N`.
+    pub const fn from_mask(mask: u8) -> Base {
+        return match BY_MASK[(mask & 0b1111) as usize] {
+            Some(base) => base,
+            None => Base::N,
+        };
+    }
+
+    /// Uppercase IUPAC symbol.
+    pub const fn to_u8(self) -> u8 {
+        return match self {
+            Base::A => b'A',
+            Base::C => b'C',
+            Base::M => b'M',
+            Base::G => b'G',
+            Base::R => b'R',
+            Base::S => b'S',
+            Base::V => b'V',
+            Base::T => b'T',
+            Base::W => b'W',
+            Base::Y => b'Y',
+            Base::H => b'H',
+            Base::K => b'K',
+            Base::D => b'D',
+            Base::B => b'B',
+            Base::N => b'N',
+        };
+    }
+
+    pub const fn to_char(self) -> char {
+        return self.to_u8() as char;
+    }
+
+    pub const fn complement(self) -> Base {
+        let mask = self as u8;
+        // A <-> T and C <-> G, i.e. reverse the four bits
+        return Base::from_mask((mask & 0b0001) << 3 | (mask & 0b0010) << 1 | (mask & 0b0100) >> 1 | (mask & 0b1000) >> 3);
+    }
+
+    pub const fn is_ambiguous(self) -> bool {
+        return (self as u8).count_ones() > 1;
+    }
+
+    /// Whether the two symbols can stand for the same base, e.g. `R` matches `A` but not `C`.
+    pub const fn matches(self, other: Base) -> bool {
+        return self as u8 & other as u8 != 0;
+    }
+
+    /// Keeps A, C, G and T and collapses every ambiguity code to `N`.
+    pub const fn to_acgtn(self) -> Base {
+        return if self.is_ambiguous() { Base::N } else { self };
+    }
+
+    /// 2-bit code (A=0, C=1, G=2, T=3), or `None` for ambiguity codes.
+    pub const fn to_2bit(self) -> Option<u8> {
+        return match self {
+            Base::A => Some(0),
+            Base::C => Some(1),
+            Base::G => Some(2),
+            Base::T => Some(3),
+            _ => None,
+        };
+    }
+
+    /// Decodes the low two bits of `bits`.
+    pub const fn from_2bit(bits: u8) -> Base {
+        return Base::ACGT[(bits & 0b11) as usize];
+    }
+}
+
+impl fmt::Display for Base {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}", self.to_char());
+    }
+}
+
+impl From<Base> for u8 {
+    fn from(base: Base) -> u8 {
+        return base.to_u8();
+    }
+}
+
+impl From<Base> for char {
+    fn from(base: Base) -> char {
+        return base.to_char();
+    }
+}
+
+/// A `Base` as it appeared in a sequence, remembering whether it was soft-masked (written in lowercase).
+#[derive(Eq, Hash, PartialEq, Debug, Clone, Copy)]
+pub struct Nucleotide {
+    pub base: Base,
+    pub soft_masked: bool,
+}
+
+impl Nucleotide {
+    pub const fn from_u8(b: u8) -> Option<Nucleotide> {
+        return match Base::from_u8(b) {
+            Some(base) => Some(Nucleotide {
+                base,
+                soft_masked: b.is_ascii_lowercase(),
+            }),
+            None => None,
+        };
+    }
+
+    /// The IUPAC symbol, lowercase when soft-masked.
+    pub const fn to_u8(self) -> u8 {
+        let b = self.base.to_u8();
+        return if self.soft_masked { b.to_ascii_lowercase() } else { b };
+    }
+
+    pub const fn to_char(self) -> char {
+        return self.to_u8() as char;
+    }
+
+    /// Complements the base and keeps the masking.
+    pub const fn complement(self) -> Nucleotide {
+        return Nucleotide {
+            base: self.base.complement(),
+            soft_masked: self.soft_masked,
+        };
+    }
+}
+
+impl fmt::Display for Nucleotide {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "{}", self.to_char());
+    }
+}
+
+/// Reverse complement of a nucleotide sequence. Case is preserved and anything that isn't a nucleotide symbol becomes `N`.
+pub fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    return seq
+        .iter()
+        .rev()
+        .map(|b| Nucleotide::from_u8(*b).map(|n| n.complement().to_u8()).unwrap_or(b'N'))
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ambiguity_codes_complement_to_the_set_of_complements() {
+        let pairs = [
+            (b'A', b'T'),
+            (b'C', b'G'),
+            (b'R', b'Y'),
+            (b'S', b'S'),
+            (b'W', b'W'),
+            (b'K', b'M'),
+            (b'B', b'V'),
+            (b'D', b'H'),
+            (b'N', b'N'),
+        ];
+        for (b, c) in pairs {
+            assert_eq!(Base::from_u8(b).unwrap().complement().to_u8(), c);
+            assert_eq!(Base::from_u8(c).unwrap().complement().to_u8(), b);
+        }
+        assert_eq!(Base::from_u8(b'u'), Some(Base::T));
+        assert!(Base::R.matches(Base::A) && !Base::R.matches(Base::C));
+        assert_eq!(Base::from_mask(Base::A as u8 | Base::G as u8), Base::R);
+    }
+
+    #[test]
+    fn reverse_complements_keep_soft_masking() {
+        assert_eq!(reverse_complement(b"ACgtRn"), b"nYacGT");
+        assert_eq!(reverse_complement(b"A-C"), b"GNT");
+    }
+}
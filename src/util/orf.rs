@@ -0,0 +1,280 @@
+use crate::util::genetic_code::{self, GeneticCode};
+use crate::util::nucleotide::Base;
+use crate::util::strand::Strand;
+use gb_io::seq::{Location, Seq};
+
+/// Shortest ORF reported by default, start and stop codons included.
+pub const DEFAULT_MIN_LENGTH: usize = 90;
+
+/// An open reading frame: a start codon followed in frame by a stop codon, with no stop in between.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Orf {
+    /// 0-based, exclusive bounds on the forward strand, stop codon included. On a circular sequence an ORF that spans
+    /// the origin ends past the length of the sequence.
+    pub start: i64,
+    pub end: i64,
+    pub strand: Strand,
+    /// Offset of every in-frame start codon from the 5' end of the ORF, along its strand, the first being 0. Only
+    /// starts that leave an ORF of the minimum length are kept.
+    pub starts: Vec<i64>,
+}
+
+impl Orf {
+    /// Length in bases, stop codon included.
+    pub fn len(&self) -> i64 {
+        return self.end - self.start;
+    }
+
+    /// Reading frame, 1 to 3 on the forward strand and -1 to -3 on the reverse, counted from the start of the strand.
+    pub fn frame(&self, seq_len: i64) -> i8 {
+        return match self.strand {
+            Strand::Forward => (self.start % 3 + 1) as i8,
+            Strand::Reverse => -((seq_len - self.end).rem_euclid(3) as i8 + 1),
+        };
+    }
+
+    /// The same frame begun at one of its later start codons, given by its offset.
+    pub fn at_start(&self, offset: i64) -> Orf {
+        let (start, end) = match self.strand {
+            Strand::Forward => (self.start + offset, self.end),
+            Strand::Reverse => (self.start, self.end - offset),
+        };
+        return Orf {
+            start,
+            end,
+            strand: self.strand,
+            starts: self.starts.iter().filter(|s| **s >= offset).map(|s| s - offset).collect(),
+        };
+    }
+
+    /// GenBank location of the ORF on `seq`, joined across the origin if it spans it.
+    pub fn location(&self, seq: &Seq) -> Location {
+        let location = seq.range_to_location(self.start, self.end);
+        return match self.strand {
+            Strand::Forward => location,
+            Strand::Reverse => Location::Complement(Box::new(location)),
+        };
+    }
+
+    /// Bases of the ORF read on its own strand, from the start codon to the stop codon.
+    pub fn sequence(&self, seq: &[u8]) -> Vec<u8> {
+        let len = seq.len() as i64;
+        let bases = (self.start..self.end).map(|p| seq[p.rem_euclid(len) as usize].to_ascii_uppercase());
+        return match self.strand {
+            Strand::Forward => bases.collect(),
+            Strand::Reverse => bases.rev().map(|b| Base::from_u8_lossy(b).complement().to_u8()).collect(),
+        };
+    }
+}
+
+/// Finds open reading frames on all six frames of a sequence.
+///
+/// Start and stop codons come from a genetic code, though the starts can be narrowed down, e.g. to `ATG` alone. Each
+/// ORF begins at the first start codon after the previous in-frame stop, with the later ones kept as alternatives.
+/// Frames that run off the end of a linear sequence before reaching a stop aren't reported.
+#[derive(Debug, Clone)]
+pub struct OrfFinder {
+    code: &'static GeneticCode,
+    start_codons: Option<Vec<[u8; 3]>>,
+    min_length: usize,
+}
+
+impl OrfFinder {
+    pub fn new() -> OrfFinder {
+        return OrfFinder {
+            code: GeneticCode::from_id(genetic_code::BACTERIAL).unwrap(),
+            start_codons: None,
+            min_length: DEFAULT_MIN_LENGTH,
+        };
+    }
+
+    pub fn genetic_code(mut self, code: &'static GeneticCode) -> Self {
+        self.code = code;
+        return self;
+    }
+
+    /// Replaces the genetic code's start codons.
+    pub fn start_codons(mut self, start_codons: Vec<[u8; 3]>) -> Self {
+        self.start_codons = Some(start_codons);
+        return self;
+    }
+
+    /// Shortest ORF to report, in bases, start and stop codons included.
+    pub fn min_length(mut self, min_length: usize) -> Self {
+        self.min_length = min_length;
+        return self;
+    }
+
+    pub fn code(&self) -> &'static GeneticCode {
+        return self.code;
+    }
+
+    /// Every ORF of at least the minimum length on either strand, sorted by start. On a circular sequence frames carry
+    /// on through the origin.
+    pub fn find(&self, seq: &[u8], circular: bool) -> Vec<Orf> {
+        let len = seq.len() as i64;
+        let mut orfs = Vec::new();
+        if len < 3 {
+            return orfs;
+        }
+        let forward: Vec<u8> = seq.iter().map(|b| b.to_ascii_uppercase()).collect();
+        let reverse: Vec<u8> = forward.iter().rev().map(|b| Base::from_u8_lossy(*b).complement().to_u8()).collect();
+        for (strand, bases) in [(Strand::Forward, &forward), (Strand::Reverse, &reverse)] {
+            for (first, codons) in tracks(len, circular) {
+                self.scan_track(bases, first, codons, circular, |start, end, starts| {
+                    // Bounds so far are on the strand being read, so the reverse strand's are flipped onto the forward
+                    let (mut start, mut end) = match strand {
+                        Strand::Forward => (start, end),
+                        Strand::Reverse => (len - end, len - start),
+                    };
+                    if start < 0 {
+                        start += len;
+                        end += len;
+                    }
+                    orfs.push(Orf { start, end, strand, starts });
+                });
+            }
+        }
+        orfs.sort_by_key(|o| (o.start, o.strand));
+        return orfs;
+    }
+
+    /// Reads `codons` codons from `first`, handing every ORF found to `f` with its bounds on the strand being read.
+    fn scan_track<F: FnMut(i64, i64, Vec<i64>)>(&self, bases: &[u8], first: i64, codons: i64, circular: bool, mut f: F) {
+        let len = bases.len() as i64;
+        let codon = |k: i64| -> [u8; 3] {
+            let p = first + 3 * k;
+            return [0, 1, 2].map(|i| bases[(p + i).rem_euclid(len) as usize]);
+        };
+
+        // A circular track has no beginning, so it's read from just after its first stop all the way round to it
+        let ks = if circular {
+            let Some(stop) = (0..codons).find(|k| self.code.is_stop(&codon(*k))) else {
+                return;
+            };
+            stop + 1..=stop + codons
+        } else {
+            0..=codons - 1
+        };
+
+        let mut starts: Vec<i64> = Vec::new();
+        for k in ks {
+            let c = codon(k);
+            if self.code.is_stop(&c) {
+                let end = first + 3 * k + 3;
+                let min_length = self.min_length as i64;
+                if let Some(start) = starts.iter().copied().find(|s| end - s >= min_length && end - s <= len) {
+                    let shift = start.div_euclid(len) * len;
+                    let alternatives = starts.iter().filter(|s| **s >= start && end - **s >= min_length).map(|s| s - start).collect();
+                    f(start - shift, end - shift, alternatives);
+                }
+                starts.clear();
+            } else if self.is_start(&c) {
+                starts.push(first + 3 * k);
+            }
+        }
+    }
+
+    fn is_start(&self, codon: &[u8; 3]) -> bool {
+        return match &self.start_codons {
+            Some(start_codons) => start_codons.contains(codon),
+            None => self.code.is_start(codon),
+        };
+    }
+}
+
+impl Default for OrfFinder {
+    fn default() -> Self {
+        return OrfFinder::new();
+    }
+}
+
+/// First position and number of codons of every distinct run of codons on one strand. A linear strand has one per
+/// frame. On a circular strand whose length is a multiple of three each frame loops back on itself, and otherwise a
+/// single run passes through all three frames before coming back round.
+fn tracks(len: i64, circular: bool) -> Vec<(i64, i64)> {
+    return if !circular {
+        (0..3).map(|first| (first, (len - first) / 3)).collect()
+    } else if len % 3 == 0 {
+        (0..3).map(|first| (first, len / 3)).collect()
+    } else {
+        vec![(0, len)]
+    };
+}
+
+/// Parses a codon such as `ATG`, or `AUG` in RNA.
+pub fn parse_codon(codon: &str) -> Option<[u8; 3]> {
+    genetic_code::codon_index(codon.as_bytes())?;
+    let bases: Vec<u8> = codon
+        .bytes()
+        .map(|b| if b.eq_ignore_ascii_case(&b'U') { b'T' } else { b.to_ascii_uppercase() })
+        .collect();
+    return bases.try_into().ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gb_io::seq::Topology;
+
+    fn atg_finder(min_length: usize) -> OrfFinder {
+        return OrfFinder::new().start_codons(vec![*b"ATG"]).min_length(min_length);
+    }
+
+    fn orf(start: i64, end: i64, strand: Strand, starts: Vec<i64>) -> Orf {
+        return Orf { start, end, strand, starts };
+    }
+
+    #[test]
+    fn orfs_are_found_on_both_strands() {
+        // ATG AAA TAG forward at 2..11, and ATG CCC TAA on the reverse strand at 13..22
+        let seq = b"CCATGAAATAGCCTTAGGGCATCC";
+        let orfs = atg_finder(9).find(seq, false);
+        assert_eq!(orfs, vec![orf(2, 11, Strand::Forward, vec![0]), orf(13, 22, Strand::Reverse, vec![0])]);
+        assert_eq!(orfs.iter().map(|o| o.frame(seq.len() as i64)).collect::<Vec<i8>>(), vec![3, -3]);
+        assert_eq!(orfs[0].sequence(seq), b"ATGAAATAG");
+        assert_eq!(orfs[1].sequence(seq), b"ATGCCCTAA");
+        assert!(atg_finder(12).find(seq, false).is_empty());
+    }
+
+    #[test]
+    fn later_starts_are_kept_as_alternatives_when_long_enough() {
+        let seq = b"ATGATGAAATAG";
+        assert_eq!(atg_finder(9).find(seq, false), vec![orf(0, 12, Strand::Forward, vec![0, 3])]);
+        assert_eq!(atg_finder(12).find(seq, false), vec![orf(0, 12, Strand::Forward, vec![0])]);
+        assert_eq!(atg_finder(9).find(seq, false)[0].at_start(3), orf(3, 12, Strand::Forward, vec![0]));
+        // The bacterial code also starts at GTG and TTG
+        assert_eq!(
+            OrfFinder::new().min_length(6).find(b"CGTGAAATAG", false),
+            vec![orf(1, 10, Strand::Forward, vec![0])]
+        );
+    }
+
+    #[test]
+    fn frames_carry_on_through_the_origin_of_a_circular_sequence() {
+        // ATG at 9..12 reads on through the origin to the TAG at 3..6
+        let bases = b"AAATAGCCCATG";
+        assert!(atg_finder(9).find(bases, false).is_empty());
+        let orfs = atg_finder(9).find(bases, true);
+        assert_eq!(orfs, vec![orf(9, 18, Strand::Forward, vec![0])]);
+        assert_eq!(orfs[0].sequence(bases), b"ATGAAATAG");
+
+        let seq = Seq {
+            topology: Topology::Circular,
+            len: Some(bases.len()),
+            seq: bases.to_vec(),
+            ..Seq::empty()
+        };
+        assert_eq!(orfs[0].location(&seq).to_gb_format(), "join(10..12,1..6)");
+    }
+
+    #[test]
+    fn circular_sequences_whose_length_is_not_a_multiple_of_three_are_read_through_every_frame() {
+        // A single run of codons goes round three times, and finds ATG CCC TAA from 6 through the origin
+        let bases = b"CCTAAGATGC";
+        let orfs = atg_finder(9).find(bases, true);
+        assert_eq!(orfs, vec![orf(6, 15, Strand::Forward, vec![0])]);
+        assert_eq!(orfs[0].sequence(bases), b"ATGCCCTAA");
+        assert_eq!(orfs[0].frame(bases.len() as i64), 1);
+    }
+}
@@ -0,0 +1,332 @@
+use crate::util::fasta::{self, Alphabet, Record};
+use crate::util::feature_io::{self, FeatureFormat};
+use crate::util::location::{self, Anchor};
+use crate::util::nucleotide::{reverse_complement, Base};
+use crate::util::strand::Strand;
+use gb_io::seq::Seq;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+
+/// Weight given to a base that never occurs at a position when there's no pseudocount, so that a single unseen base
+/// lowers a score a lot without making it infinite.
+pub const MIN_WEIGHT: f64 = -99.0;
+/// Bases taken either side of a site's anchor by default, as `hw3` does around start codons.
+pub const DEFAULT_FLANK: usize = 10;
+/// Pseudocount spread over every column by default, half a site's worth.
+pub const DEFAULT_PSEUDOCOUNT: f64 = 0.5;
+
+/// Position weight matrix over A, C, G and T, built from a set of aligned, equal-length sites.
+///
+/// Counts, frequencies and log2-odds weights are kept in dense per-column arrays. Counts needn't be whole, since
+/// matrices from other tools often aren't. Columns are labelled relative to an anchor, so with `offset = -10` the first
+/// column is position -10 and the anchor (e.g. the first base of a start codon) is position 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pwm {
+    offset: i64,
+    pseudocount: f64,
+    background: [f64; 4],
+    counts: Vec<[f64; 4]>,
+    freqs: Vec<[f64; 4]>,
+    weights: Vec<[f64; 4]>,
+}
+
+impl Pwm {
+    /// Builds a matrix from sites that all have the same length. Ambiguous bases aren't counted.
+    ///
+    /// `pseudocount` is spread over the four bases in proportion to the background, and `background` holds the
+    /// A, C, G and T frequencies the weights are relative to.
+    pub fn from_sites<I, S>(sites: I, offset: i64, pseudocount: f64, background: [f64; 4]) -> io::Result<Pwm>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<[u8]>,
+    {
+        let mut counts: Vec<[f64; 4]> = Vec::new();
+        for (i, site) in sites.into_iter().enumerate() {
+            let site = site.as_ref();
+            if i == 0 {
+                counts = vec![[0.0; 4]; site.len()];
+            } else if site.len() != counts.len() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("site {} is {} bases long, but the first site is {}", i + 1, site.len(), counts.len()),
+                ));
+            }
+            for (column, b) in counts.iter_mut().zip(site) {
+                if let Some(code) = Base::from_u8(*b).and_then(|base| base.to_2bit()) {
+                    column[code as usize] += 1.0;
+                }
+            }
+        }
+        return Ok(Pwm::from_counts(counts, offset, pseudocount, background));
+    }
+
+    /// Builds a matrix from per-column counts of A, C, G and T.
+    pub fn from_counts(counts: Vec<[f64; 4]>, offset: i64, pseudocount: f64, background: [f64; 4]) -> Pwm {
+        let freqs: Vec<[f64; 4]> = counts
+            .iter()
+            .map(|column| {
+                let total = column.iter().sum::<f64>() + pseudocount;
+                let mut freq = [0.0; 4];
+                for c in 0..4 {
+                    freq[c] = if total == 0.0 {
+                        0.0
+                    } else {
+                        (column[c] + pseudocount * background[c]) / total
+                    };
+                }
+                freq
+            })
+            .collect();
+        let weights = freqs
+            .iter()
+            .map(|freq| {
+                let mut weight = [0.0; 4];
+                for c in 0..4 {
+                    weight[c] = if freq[c] == 0.0 { MIN_WEIGHT } else { freq[c].log2() - background[c].log2() };
+                }
+                weight
+            })
+            .collect();
+
+        return Pwm {
+            offset,
+            pseudocount,
+            background,
+            counts,
+            freqs,
+            weights,
+        };
+    }
+
+    /// Builds a matrix from a FASTA file with one aligned site per record.
+    pub fn from_fasta<P: AsRef<Path>>(file_path: P, offset: i64, pseudocount: f64, background: [f64; 4]) -> io::Result<Pwm> {
+        let records = fasta::Reader::from_path(file_path)?.alphabet(Alphabet::IUPAC).uppercase();
+        let sites = records.map(|r| r.map(|r| r.seq)).collect::<io::Result<Vec<String>>>()?;
+        return Pwm::from_sites(sites, offset, pseudocount, background);
+    }
+
+    /// Builds a matrix from the sites around the 5' end of every feature of the given kinds (e.g. `CDS`), taking
+    /// `upstream` bases before and `downstream` bases after it on the feature's strand.
+    ///
+    /// Downstream flanks follow the feature's pieces, so they skip introns. Features whose 5' end is partial, and
+    /// windows that would fall off either end of a linear record, are skipped.
+    pub fn from_features(seqs: &[Seq], kinds: &[&str], upstream: usize, downstream: usize, pseudocount: f64, background: [f64; 4]) -> io::Result<Pwm> {
+        let mut sites = Vec::new();
+        for seq in seqs {
+            for feature in seq.features.iter().filter(|f| kinds.contains(&&*f.kind)) {
+                if let Ok(site) = location::flank(seq, &feature.location, Anchor::Start, upstream, downstream) {
+                    sites.push(site);
+                }
+            }
+        }
+        return Pwm::from_sites(sites, -(upstream as i64), pseudocount, background);
+    }
+
+    /// Builds a matrix from BED intervals over the records of a FASTA file, widening every interval by `flank` bases
+    /// on both sides. Intervals on the `-` strand are reverse complemented. All intervals must end up the same length.
+    pub fn from_bed<P: AsRef<Path>>(fasta_path: P, bed_path: &str, flank: usize, pseudocount: f64, background: [f64; 4]) -> io::Result<Pwm> {
+        let records = fasta::Reader::from_path(fasta_path)?.alphabet(Alphabet::IUPAC).uppercase();
+        let records = records.collect::<io::Result<Vec<Record>>>()?;
+
+        let mut sites = Vec::new();
+        for interval in feature_io::read_features(bed_path, FeatureFormat::Bed)? {
+            let record = records
+                .iter()
+                .find(|r| r.id == interval.seq_id)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("no record named '{}'", interval.seq_id)))?;
+            let strand = interval.strand.unwrap_or(Strand::Forward);
+            let site = cut_site(record.seq.as_bytes(), interval.start - flank as i64, interval.end + flank as i64, strand).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("interval {}..{} runs off the end of '{}'", interval.start, interval.end, interval.seq_id),
+                )
+            })?;
+            sites.push(site);
+        }
+        return Pwm::from_sites(sites, -(flank as i64), pseudocount, background);
+    }
+
+    /// Number of columns.
+    pub fn len(&self) -> usize {
+        return self.counts.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.counts.is_empty();
+    }
+
+    /// Position label of the first column.
+    pub fn offset(&self) -> i64 {
+        return self.offset;
+    }
+
+    /// Position labels of every column, in order.
+    pub fn positions(&self) -> std::ops::Range<i64> {
+        return self.offset..self.offset + self.len() as i64;
+    }
+
+    pub fn pseudocount(&self) -> f64 {
+        return self.pseudocount;
+    }
+
+    pub fn background(&self) -> [f64; 4] {
+        return self.background;
+    }
+
+    /// Number of sites the matrix was built from, taken from the first column.
+    pub fn site_count(&self) -> f64 {
+        return self.counts.first().map(|c| c.iter().sum()).unwrap_or(0.0);
+    }
+
+    pub fn counts(&self) -> &[[f64; 4]] {
+        return &self.counts;
+    }
+
+    pub fn frequencies(&self) -> &[[f64; 4]] {
+        return &self.freqs;
+    }
+
+    pub fn weights(&self) -> &[[f64; 4]] {
+        return &self.weights;
+    }
+
+    /// Count of `base` at the labelled position, zero outside the matrix or for ambiguous bases.
+    pub fn count(&self, pos: i64, base: Base) -> f64 {
+        return self.lookup(&self.counts, pos, base).unwrap_or(0.0);
+    }
+
+    pub fn frequency(&self, pos: i64, base: Base) -> f64 {
+        return self.lookup(&self.freqs, pos, base).unwrap_or(0.0);
+    }
+
+    /// Log2-odds weight of `base` at the labelled position. Ambiguous bases weigh zero.
+    pub fn weight(&self, pos: i64, base: Base) -> f64 {
+        return self.lookup(&self.weights, pos, base).unwrap_or(0.0);
+    }
+
+    /// Information content of a column in bits, relative to the background.
+    pub fn information_content(&self, column: usize) -> f64 {
+        return (0..4)
+            .filter(|c| self.freqs[column][*c] > 0.0)
+            .map(|c| self.freqs[column][c] * (self.freqs[column][c] / self.background[c]).log2())
+            .sum();
+    }
+
+    pub fn total_information_content(&self) -> f64 {
+        return (0..self.len()).map(|c| self.information_content(c)).sum();
+    }
+
+    /// Consensus sequence using IUPAC codes (Cavener 1987): a base when it's over half the column and twice as common
+    /// as the next, a two-base code when the top two make up three quarters, and `N` otherwise.
+    pub fn consensus(&self) -> String {
+        return self
+            .freqs
+            .iter()
+            .map(|freq| {
+                let mut order = [0, 1, 2, 3];
+                order.sort_by(|a, b| freq[*b].partial_cmp(&freq[*a]).unwrap());
+                let (first, second) = (Base::ACGT[order[0]], Base::ACGT[order[1]]);
+                let base = if freq[order[0]] > 0.5 && freq[order[0]] > 2.0 * freq[order[1]] {
+                    first
+                } else if freq[order[0]] + freq[order[1]] > 0.75 {
+                    Base::from_mask(first as u8 | second as u8)
+                } else {
+                    Base::N
+                };
+                base.to_char()
+            })
+            .collect();
+    }
+
+    /// Sum of the weights of a window as long as the matrix. Ambiguous bases add nothing.
+    pub fn score(&self, window: &[u8]) -> f64 {
+        return self
+            .weights
+            .iter()
+            .zip(window)
+            .map(|(weight, b)| match Base::from_u8(*b).and_then(|base| base.to_2bit()) {
+                Some(code) => weight[code as usize],
+                None => 0.0,
+            })
+            .sum();
+    }
+
+    pub fn max_score(&self) -> f64 {
+        return self.weights.iter().map(|w| w.iter().copied().fold(f64::MIN, f64::max)).sum();
+    }
+
+    pub fn min_score(&self) -> f64 {
+        return self.weights.iter().map(|w| w.iter().copied().fold(f64::MAX, f64::min)).sum();
+    }
+
+    fn lookup<T: Copy>(&self, table: &[[T; 4]], pos: i64, base: Base) -> Option<T> {
+        let column = usize::try_from(pos - self.offset).ok()?;
+        return Some(table.get(column)?[base.to_2bit()? as usize]);
+    }
+}
+
+/// The bases in `start..end` read on the given strand, or `None` if the range isn't inside the sequence.
+fn cut_site(seq: &[u8], start: i64, end: i64, strand: Strand) -> Option<Vec<u8>> {
+    if start < 0 || end as usize > seq.len() || start > end {
+        return None;
+    }
+    let site = &seq[start as usize..end as usize];
+    return Some(match strand {
+        Strand::Forward => site.to_ascii_uppercase(),
+        Strand::Reverse => reverse_complement(&site.to_ascii_uppercase()),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gb_io::feature_kind;
+    use gb_io::seq::{Feature, Location};
+
+    fn feature(kind: gb_io::FeatureKind, location: &str) -> Feature {
+        return Feature {
+            kind,
+            location: Location::from_gb_format(location).unwrap(),
+            qualifiers: Vec::new(),
+        };
+    }
+
+    #[test]
+    fn feature_sites_are_read_from_the_5_prime_end_on_their_own_strand() {
+        let seq = Seq {
+            seq: b"CCATGAAATTTCATGG".to_vec(),
+            len: Some(16),
+            features: vec![
+                feature(feature_kind!("CDS"), "3..8"),
+                feature(feature_kind!("CDS"), "complement(9..14)"),
+                feature(feature_kind!("gene"), "3..8"),
+                // Partial at its 5' end, and too near the start of the record for a full window
+                feature(feature_kind!("CDS"), "<2..8"),
+                feature(feature_kind!("CDS"), "1..8"),
+            ],
+            ..Seq::empty()
+        };
+        let pwm = Pwm::from_features(&[seq], &["CDS"], 2, 3, 0.0, [0.25; 4]).unwrap();
+        assert_eq!(pwm.site_count(), 2.0);
+        assert_eq!(pwm.positions(), -2..4);
+        assert_eq!(pwm.consensus(), "CCATGA");
+        assert_eq!(pwm.count(0, Base::A), 2.0);
+        assert_eq!(pwm.weight(0, Base::A), 2.0);
+        assert_eq!(pwm.weight(0, Base::C), MIN_WEIGHT);
+    }
+
+    #[test]
+    fn sites_of_different_lengths_are_rejected() {
+        let error = Pwm::from_sites(["ACGT", "ACG"], 0, 0.5, [0.25; 4]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "site 2 is 3 bases long, but the first site is 4");
+    }
+
+    #[test]
+    fn cut_sites_are_read_on_their_strand_and_must_lie_inside_the_sequence() {
+        assert_eq!(cut_site(b"aacgtt", 1, 4, Strand::Forward), Some(b"ACG".to_vec()));
+        assert_eq!(cut_site(b"aacgtt", 1, 4, Strand::Reverse), Some(b"CGT".to_vec()));
+        assert_eq!(cut_site(b"aacgtt", -1, 3, Strand::Forward), None);
+        assert_eq!(cut_site(b"aacgtt", 4, 7, Strand::Forward), None);
+    }
+}
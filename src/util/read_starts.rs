@@ -0,0 +1,51 @@
+use crate::error::LineError;
+use crate::util::read;
+use crate::util::segments::{ClassCounts, ClassifyingSegmenter, Segment};
+use std::io;
+use std::path::Path;
+
+/// Read start counts are tallied as 0, 1, 2 and >=3.
+pub const COUNT_CLASSES: usize = 4;
+
+pub fn count_class(reads: usize) -> usize {
+    return reads.min(COUNT_CLASSES - 1);
+}
+
+/// The segments of a chromosome with elevated read start counts, and how many positions had each count.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadStartSegments {
+    /// Chromosome named on the first line, empty if there were no lines
+    pub chr: String,
+    pub segments: Vec<Segment>,
+    pub counts: ClassCounts<COUNT_CLASSES>,
+}
+
+/// Reads a file of `chr position count` lines, one per position, and finds its elevated segments by scoring each
+/// position by its count class. A segment is closed once it falls `drop` below its best, and kept if it scores at
+/// least `min_score`. Segments keep the file's position numbering, with `end` exclusive.
+pub fn segment_read_starts<P: AsRef<Path>>(file_path: P, scores: &[f64; COUNT_CLASSES], drop: f64, min_score: f64) -> io::Result<ReadStartSegments> {
+    let mut chr = String::new();
+    let mut segments = Vec::new();
+    let mut segmenter = ClassifyingSegmenter::new(drop, min_score);
+    for (line_no, line) in read::lines(file_path)?.enumerate() {
+        let line = line?;
+        // Expected format: chr16\t12345\t2
+        let invalid = |message: String| LineError::invalid_data(line_no + 1, message);
+        let [name, pos, count] = line.split_whitespace().collect::<Vec<&str>>()[..] else {
+            return Err(invalid("expected 'chr position count'".to_string()));
+        };
+        if chr.is_empty() {
+            chr = name.to_string();
+        }
+        let pos: usize = pos.parse().map_err(|_| invalid(format!("'{}' is not a position", pos)))?;
+        let count: usize = count.parse().map_err(|_| invalid(format!("'{}' is not a read count", count)))?;
+
+        let class = count_class(count);
+        if let Some(segment) = segmenter.push(pos, scores[class], class) {
+            segments.push(segment);
+        }
+    }
+    let (last, counts) = segmenter.finish();
+    segments.extend(last);
+    return Ok(ReadStartSegments { chr, segments, counts });
+}
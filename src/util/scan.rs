@@ -0,0 +1,221 @@
+use crate::util::nucleotide::Base;
+use crate::util::pwm::Pwm;
+use crate::util::strand::Strand;
+
+/// Weights are rounded to multiples of `1 / DEFAULT_SCALE` bits, fine enough that the rounding rarely moves a score by
+/// more than a few hundredths.
+pub const DEFAULT_SCALE: f64 = 100.0;
+/// P-value a window has to reach to be reported by default, rare enough that a few megabases give only a handful of
+/// chance hits.
+pub const DEFAULT_P_VALUE: f64 = 1e-5;
+
+/// Code given to ambiguous bases, which weigh zero on both strands.
+const AMBIGUOUS: usize = 4;
+
+/// A window of a sequence scored against a matrix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    /// 0-based start of the window on the forward strand, whichever strand it was read on
+    pub start: usize,
+    pub strand: Strand,
+    pub score: f64,
+    /// Chance of a background window scoring at least as high
+    pub p_value: f64,
+}
+
+/// Scans sequences for matches to a position weight matrix.
+///
+/// The weights are scaled to integers up front, with a table for each strand, so each window costs one lookup per
+/// column per strand. The exact distribution of scores over windows drawn from the matrix's background is worked out
+/// once by dynamic programming, which turns any score into a p-value and any p-value into a score threshold.
+#[derive(Debug, Clone)]
+pub struct PwmScanner {
+    scale: f64,
+    forward: Vec<[i64; 5]>,
+    reverse: Vec<[i64; 5]>,
+    min_score: i64,
+    /// `tail[s]` is the chance of a score of at least `min_score + s`
+    tail: Vec<f64>,
+}
+
+impl PwmScanner {
+    pub fn new(pwm: &Pwm, scale: f64) -> PwmScanner {
+        let forward: Vec<[i64; 5]> = pwm
+            .weights()
+            .iter()
+            .map(|w| [0, 1, 2, 3, AMBIGUOUS].map(|c| if c == AMBIGUOUS { 0 } else { (w[c] * scale).round() as i64 }))
+            .collect();
+        // Reading the other strand visits the columns backwards and pairs every base with its complement
+        let reverse = forward.iter().rev().map(|w| [w[3], w[2], w[1], w[0], 0]).collect();
+
+        let min_score: i64 = forward.iter().map(|w| w[..4].iter().min().unwrap()).sum();
+        let max_score: i64 = forward.iter().map(|w| w[..4].iter().max().unwrap()).sum();
+        let background = pwm.background();
+
+        // Probability of every score, built up one column at a time from the lowest reachable score
+        let mut dist = vec![0.0; (max_score - min_score) as usize + 1];
+        dist[0] = 1.0;
+        for w in &forward {
+            let column_min = *w[..4].iter().min().unwrap();
+            let mut next = vec![0.0; dist.len()];
+            for (s, p) in dist.iter().enumerate().filter(|(_, p)| **p > 0.0) {
+                for c in 0..4 {
+                    next[(s as i64 + w[c] - column_min) as usize] += p * background[c];
+                }
+            }
+            dist = next;
+        }
+
+        let mut tail = dist;
+        for s in (0..tail.len().saturating_sub(1)).rev() {
+            tail[s] += tail[s + 1];
+        }
+
+        return PwmScanner {
+            scale,
+            forward,
+            reverse,
+            min_score,
+            tail,
+        };
+    }
+
+    /// Number of columns, and so the length of every window.
+    pub fn len(&self) -> usize {
+        return self.forward.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.forward.is_empty();
+    }
+
+    /// Chance of a background window scoring at least `score`.
+    pub fn p_value(&self, score: f64) -> f64 {
+        return self.p_value_scaled((score * self.scale).round() as i64);
+    }
+
+    /// Lowest score with a p-value of at most `p_value`, or `None` if even the best possible score is more likely.
+    pub fn threshold(&self, p_value: f64) -> Option<f64> {
+        let s = self.tail.iter().position(|p| *p <= p_value)?;
+        return Some((self.min_score + s as i64) as f64 / self.scale);
+    }
+
+    /// Scores every window of the sequence on both strands, handing each to `f`. The forward strand's hit for a
+    /// window comes first.
+    pub fn scan_with<F: FnMut(Hit)>(&self, seq: &[u8], mut f: F) {
+        let len = self.len();
+        if self.is_empty() || seq.len() < len {
+            return;
+        }
+        let codes: Vec<usize> = seq
+            .iter()
+            .map(|b| Base::from_u8(*b).and_then(|base| base.to_2bit()).map_or(AMBIGUOUS, |c| c as usize))
+            .collect();
+        for (start, window) in codes.windows(len).enumerate() {
+            let (mut forward, mut reverse) = (0, 0);
+            for (j, c) in window.iter().enumerate() {
+                forward += self.forward[j][*c];
+                reverse += self.reverse[j][*c];
+            }
+            f(self.hit(start, Strand::Forward, forward));
+            f(self.hit(start, Strand::Reverse, reverse));
+        }
+    }
+
+    /// Every window on either strand with a p-value of at most `p_value`, in order along the sequence.
+    pub fn scan(&self, seq: &[u8], p_value: f64) -> Vec<Hit> {
+        let mut hits = Vec::new();
+        self.scan_with(seq, |hit| {
+            if hit.p_value <= p_value {
+                hits.push(hit);
+            }
+        });
+        return hits;
+    }
+
+    fn hit(&self, start: usize, strand: Strand, score: i64) -> Hit {
+        return Hit {
+            start,
+            strand,
+            score: score as f64 / self.scale,
+            p_value: self.p_value_scaled(score),
+        };
+    }
+
+    fn p_value_scaled(&self, score: i64) -> f64 {
+        return if score <= self.min_score {
+            1.0
+        } else {
+            self.tail.get((score - self.min_score) as usize).copied().unwrap_or(0.0)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::nucleotide::reverse_complement;
+
+    /// Every window of the given length, with its chance under the background.
+    fn all_windows(len: usize, background: [f64; 4]) -> Vec<(Vec<u8>, f64)> {
+        let mut windows = vec![(Vec::new(), 1.0)];
+        for _ in 0..len {
+            windows = windows
+                .into_iter()
+                .flat_map(|(w, p)| {
+                    (0..4).map(move |c| {
+                        let mut w = w.clone();
+                        w.push(b"ACGT"[c]);
+                        (w, p * background[c])
+                    })
+                })
+                .collect();
+        }
+        return windows;
+    }
+
+    fn forward_score(scanner: &PwmScanner, window: &[u8]) -> f64 {
+        return scanner.scan(window, 1.0)[0].score;
+    }
+
+    #[test]
+    fn p_values_match_brute_force_enumeration() {
+        let background = [0.3, 0.2, 0.2, 0.3];
+        let pwm = Pwm::from_sites(["ACGT", "ACGA", "TCGT", "ACCT", "GAGT"], 0, 1.0, background).unwrap();
+        let scanner = PwmScanner::new(&pwm, DEFAULT_SCALE);
+        let windows: Vec<(f64, f64)> = all_windows(4, background).iter().map(|(w, p)| (forward_score(&scanner, w), *p)).collect();
+        let brute = |score: f64| -> f64 { windows.iter().filter(|(s, _)| *s >= score).map(|(_, p)| p).sum() };
+
+        for (window, _) in all_windows(4, background) {
+            let hits = scanner.scan(&window, 1.0);
+            assert!((hits[0].p_value - brute(hits[0].score)).abs() < 1e-12);
+            // The other strand's window is scored as its reverse complement would be on the forward strand
+            assert_eq!(hits[1].score, forward_score(&scanner, &reverse_complement(&window)));
+            assert!((hits[1].p_value - brute(hits[1].score)).abs() < 1e-12);
+        }
+        assert_eq!(scanner.p_value(pwm.min_score() - 1.0), 1.0);
+        assert_eq!(scanner.p_value(pwm.max_score() + 1.0), 0.0);
+
+        for p_value in [0.5, 0.1, 0.01, 0.004] {
+            let threshold = scanner.threshold(p_value).unwrap();
+            assert!(brute(threshold) <= p_value);
+            // Every score below the threshold that some window reaches is more likely than asked for
+            assert!(windows.iter().filter(|(s, _)| *s < threshold).all(|(s, _)| brute(*s) > p_value));
+        }
+        // No window is as unlikely as this, not even the best, ACGT at 0.0036
+        assert_eq!(scanner.threshold(0.001), None);
+    }
+
+    #[test]
+    fn ambiguous_bases_weigh_nothing_on_either_strand() {
+        let pwm = Pwm::from_sites(["ACG", "ACG", "ATG"], 0, 1.0, [0.25; 4]).unwrap();
+        let scanner = PwmScanner::new(&pwm, DEFAULT_SCALE);
+        let hits = scanner.scan(b"ANG", 1.0);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(
+            hits[0].score,
+            forward_score(&scanner, b"AAG") - (pwm.weight(1, Base::A) * DEFAULT_SCALE).round() / DEFAULT_SCALE
+        );
+        assert!(scanner.scan(b"AC", 1.0).is_empty());
+    }
+}
@@ -0,0 +1,353 @@
+use crate::util::nucleotide::Base;
+
+/// A run of positions along a score track and its total score, with `end` exclusive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Segment {
+    pub start: usize,
+    pub end: usize,
+    pub score: f64,
+}
+
+impl Segment {
+    pub fn len(&self) -> usize {
+        return self.end - self.start;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.start == self.end;
+    }
+}
+
+/// Scores for every base of a sequence, e.g. the log-odds of it coming from a GC-rich region rather than the
+/// background. Ambiguity codes score 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BaseScores {
+    acgt: [f64; 4],
+}
+
+impl BaseScores {
+    /// Scores for A, C, G and T, in that order.
+    pub fn new(acgt: [f64; 4]) -> BaseScores {
+        return BaseScores { acgt };
+    }
+
+    pub fn score(&self, base: Base) -> f64 {
+        return match base.to_2bit() {
+            Some(i) => self.acgt[i as usize],
+            None => 0.0,
+        };
+    }
+
+    /// Scores of every base of `seq`. Anything that isn't a nucleotide symbol scores as ambiguous.
+    pub fn scores(&self, seq: &[u8]) -> Vec<f64> {
+        return seq.iter().map(|b| self.score(Base::from_u8_lossy(b.to_ascii_uppercase()))).collect();
+    }
+}
+
+/// Every maximal scoring segment of `scores`, in order, by Ruzzo and Tompa's linear-time algorithm.
+///
+/// A maximal segment has a positive score, and no segment that overlaps it scores higher or contains it and scores as
+/// high. Together they're the highest-scoring segments of the track that don't overlap, so the best single segment is
+/// whichever of them scores highest.
+pub fn maximal_segments(scores: &[f64]) -> Vec<Segment> {
+    let mut candidates: Vec<Candidate> = Vec::new();
+    let mut total = 0.0;
+    for (i, score) in scores.iter().enumerate() {
+        if *score > 0.0 {
+            let mut candidate = Candidate {
+                start: i,
+                end: i + 1,
+                low: total,
+                high: total + score,
+                previous: None,
+            };
+            loop {
+                // The rightmost candidate whose segment starts lower than this one does, skipping along the links
+                let mut j = candidates.len().checked_sub(1);
+                while let Some(k) = j {
+                    if candidates[k].low < candidate.low {
+                        break;
+                    }
+                    j = candidates[k].previous;
+                }
+                match j {
+                    // It ends lower too, so the two and everything between them make a better segment
+                    Some(k) if candidates[k].high < candidate.high => {
+                        candidate.start = candidates[k].start;
+                        candidate.low = candidates[k].low;
+                        candidates.truncate(k);
+                    }
+                    _ => {
+                        candidate.previous = j;
+                        candidates.push(candidate);
+                        break;
+                    }
+                }
+            }
+        }
+        total += score;
+    }
+    return candidates
+        .into_iter()
+        .map(|c| Segment {
+            start: c.start,
+            end: c.end,
+            score: c.high - c.low,
+        })
+        .collect();
+}
+
+/// A segment that may still grow, with the cumulative score of the track just before and at the end of it.
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    start: usize,
+    end: usize,
+    low: f64,
+    high: f64,
+    /// The rightmost earlier candidate that starts lower, which is where searches from this one carry on
+    previous: Option<usize>,
+}
+
+/// What pushing a score onto a `DropOffSegmenter` did.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    /// The open segment grew without reaching a new high
+    Extended,
+    /// The open segment reached a new high, so it now ends here
+    Peaked,
+    /// The open segment fell too far and was closed, and this is it if it scored high enough
+    Closed(Option<Segment>),
+}
+
+/// Finds segments one score at a time, closing the open segment once its score drops to zero or falls more than `drop`
+/// below its best. Unlike `maximal_segments` it needs no memory of the track, so it suits tracks too long to hold.
+#[derive(Debug, Clone)]
+pub struct DropOffSegmenter {
+    drop: f64,
+    min_score: f64,
+    total: f64,
+    best: f64,
+    start: Option<usize>,
+    end: usize,
+}
+
+impl DropOffSegmenter {
+    /// `drop` is how far below its best, as a positive score, a segment may fall before it's closed, and `min_score` the
+    /// lowest score of a segment worth reporting.
+    pub fn new(drop: f64, min_score: f64) -> DropOffSegmenter {
+        return DropOffSegmenter {
+            drop,
+            min_score,
+            total: 0.0,
+            best: 0.0,
+            start: None,
+            end: 0,
+        };
+    }
+
+    /// Adds the score at `position`. Positions should increase by one with each push, but may start anywhere.
+    pub fn push(&mut self, position: usize, score: f64) -> Step {
+        let start = match self.start {
+            Some(start) => start,
+            None => {
+                self.start = Some(position);
+                self.end = position;
+                position
+            }
+        };
+        self.total += score;
+        let mut step = Step::Extended;
+        if self.total >= self.best {
+            self.best = self.total;
+            self.end = position + 1;
+            step = Step::Peaked;
+        }
+        if self.total <= 0.0 || self.total <= self.best - self.drop {
+            step = Step::Closed(self.close(start));
+        }
+        return step;
+    }
+
+    /// Closes whatever segment is still open at the end of the track.
+    pub fn finish(mut self) -> Option<Segment> {
+        return self.start.and_then(|start| self.close(start));
+    }
+
+    fn close(&mut self, start: usize) -> Option<Segment> {
+        let segment = Segment {
+            start,
+            end: self.end,
+            score: self.best,
+        };
+        self.total = 0.0;
+        self.best = 0.0;
+        self.start = None;
+        return if segment.score >= self.min_score && !segment.is_empty() {
+            Some(segment)
+        } else {
+            None
+        };
+    }
+}
+
+/// Positions in each of `N` classes, across a whole track and across the segments found along it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClassCounts<const N: usize> {
+    pub total: [usize; N],
+    pub in_segments: [usize; N],
+}
+
+impl<const N: usize> ClassCounts<N> {
+    pub fn outside_segments(&self) -> [usize; N] {
+        let mut outside = self.total;
+        for (o, s) in outside.iter_mut().zip(self.in_segments) {
+            *o -= s;
+        }
+        return outside;
+    }
+}
+
+/// A `DropOffSegmenter` that also counts the positions of each class, e.g. of each number of reads starting there,
+/// along the whole track and inside the segments it reports. A segment ends at its highest point, so only the positions
+/// up to there count towards it.
+#[derive(Debug, Clone)]
+pub struct ClassifyingSegmenter<const N: usize> {
+    segmenter: DropOffSegmenter,
+    /// Positions of each class in the open segment, overall and up to its highest point
+    open: [usize; N],
+    peak: [usize; N],
+    counts: ClassCounts<N>,
+}
+
+impl<const N: usize> ClassifyingSegmenter<N> {
+    /// Takes the same arguments as `DropOffSegmenter::new`.
+    pub fn new(drop: f64, min_score: f64) -> ClassifyingSegmenter<N> {
+        return ClassifyingSegmenter {
+            segmenter: DropOffSegmenter::new(drop, min_score),
+            open: [0; N],
+            peak: [0; N],
+            counts: ClassCounts {
+                total: [0; N],
+                in_segments: [0; N],
+            },
+        };
+    }
+
+    /// Adds the score at `position`, which is in class `class`, and returns the segment it closed if that scored high
+    /// enough.
+    pub fn push(&mut self, position: usize, score: f64, class: usize) -> Option<Segment> {
+        self.open[class] += 1;
+        self.counts.total[class] += 1;
+        return match self.segmenter.push(position, score) {
+            Step::Extended => None,
+            Step::Peaked => {
+                self.peak = self.open;
+                None
+            }
+            Step::Closed(segment) => {
+                let peak = std::mem::replace(&mut self.peak, [0; N]);
+                self.open = [0; N];
+                if segment.is_some() {
+                    add_counts(&mut self.counts.in_segments, peak);
+                }
+                segment
+            }
+        };
+    }
+
+    /// Closes whatever segment is still open at the end of the track, and returns it with the counts.
+    pub fn finish(self) -> (Option<Segment>, ClassCounts<N>) {
+        let mut counts = self.counts;
+        let segment = self.segmenter.finish();
+        if segment.is_some() {
+            add_counts(&mut counts.in_segments, self.peak);
+        }
+        return (segment, counts);
+    }
+}
+
+fn add_counts<const N: usize>(into: &mut [usize; N], counts: [usize; N]) {
+    for (total, count) in into.iter_mut().zip(counts) {
+        *total += count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// Maximal segments by definition: repeatedly take the highest-scoring segment, shortest first on ties, that doesn't
+    /// overlap one already taken, trying every start and end.
+    fn naive_maximal_segments(scores: &[f64]) -> Vec<Segment> {
+        let mut taken: Vec<Segment> = Vec::new();
+        loop {
+            let mut best: Option<Segment> = None;
+            for start in 0..scores.len() {
+                let mut score = 0.0;
+                for end in start + 1..=scores.len() {
+                    score += scores[end - 1];
+                    let overlaps = taken.iter().any(|s| start < s.end && s.start < end);
+                    if overlaps || score <= 0.0 {
+                        continue;
+                    }
+                    if best.is_none_or(|b| score > b.score || (score == b.score && end - start < b.len())) {
+                        best = Some(Segment { start, end, score });
+                    }
+                }
+            }
+            match best {
+                Some(segment) => taken.push(segment),
+                None => break,
+            }
+        }
+        taken.sort_by_key(|s| s.start);
+        return taken;
+    }
+
+    #[test]
+    fn maximal_segments_match_the_quadratic_oracle() {
+        let mut rng = StdRng::seed_from_u64(24);
+        for len in [0, 1, 2, 5, 20, 60] {
+            for _ in 0..50 {
+                // Small integers, so that sums are exact and ties happen
+                let scores: Vec<f64> = (0..len).map(|_| rng.gen_range(-4..=3) as f64).collect();
+                assert_eq!(maximal_segments(&scores), naive_maximal_segments(&scores), "scores {:?}", scores);
+            }
+        }
+    }
+
+    #[test]
+    fn drop_off_segmenter_closes_after_a_big_enough_fall() {
+        let scores = [2.0, 3.0, -1.0, 4.0, -6.0, -2.0, 1.0, 1.0, -5.0, 3.0];
+        let mut segmenter = DropOffSegmenter::new(5.0, 2.0);
+        let mut segments = Vec::new();
+        for (i, score) in scores.iter().enumerate() {
+            if let Step::Closed(Some(segment)) = segmenter.push(i + 1, *score) {
+                segments.push(segment);
+            }
+        }
+        segments.extend(segmenter.finish());
+        let found: Vec<(usize, usize, f64)> = segments.iter().map(|s| (s.start, s.end, s.score)).collect();
+        assert_eq!(found, vec![(1, 5, 8.0), (7, 9, 2.0), (10, 11, 3.0)]);
+    }
+
+    #[test]
+    fn classifying_segmenter_counts_classes_up_to_each_segment_end() {
+        // Class 1 scores up and class 0 down, so the run of ones is a segment and the trailing zeros aren't part of it
+        let classes = [0, 1, 1, 0, 1, 0, 0, 0, 0, 1];
+        let mut segmenter = ClassifyingSegmenter::<2>::new(2.5, 2.0);
+        let mut segments = Vec::new();
+        for (i, class) in classes.iter().enumerate() {
+            let score = if *class == 1 { 1.0 } else { -1.0 };
+            segments.extend(segmenter.push(i, score, *class));
+        }
+        let (last, counts) = segmenter.finish();
+        segments.extend(last);
+        assert_eq!(segments, vec![Segment { start: 1, end: 5, score: 2.0 }]);
+        assert_eq!(counts.total, [6, 4]);
+        assert_eq!(counts.in_segments, [1, 3]);
+        assert_eq!(counts.outside_segments(), [5, 1]);
+    }
+}
@@ -0,0 +1,177 @@
+/// Grid scores are rounded to when working out `K`, which needs them on a lattice.
+const LATTICE_SCALE: f64 = 100.0;
+/// Most terms of the series for `K` to sum. They shrink geometrically, so this is only reached by scores that barely
+/// drift downwards.
+const MAX_TERMS: usize = 500;
+/// Terms of the series for `K` smaller than this are taken to be the end of it.
+const TERM_TOLERANCE: f64 = 1e-12;
+
+/// Karlin–Altschul statistics of a scoring scheme: how often segments, or local alignments, of a given score turn up
+/// by chance in a random sequence drawn from the background.
+///
+/// The number of segments scoring at least `S` in a search space of `N` positions is Poisson with mean
+/// `E = K N exp(-lambda S)`, where `lambda` and `K` depend only on the scores and how often each turns up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KarlinAltschul {
+    /// Scale of the scores, the positive root of `sum p(s) exp(lambda s) = 1`. Scores that are log odds in base `b`
+    /// against the same background give `ln b`.
+    pub lambda: f64,
+    /// Multiplier of the search space, accounting for high-scoring segments clumping together
+    pub k: f64,
+    /// Relative entropy of the target distribution the scores imply, in nats per position
+    pub h: f64,
+}
+
+impl KarlinAltschul {
+    /// Works out the statistics from each score and the background probability of it, which are normalised to sum to
+    /// one. Returns `None` unless some score is positive and the expected score is negative, as the theory needs.
+    pub fn from_distribution(distribution: &[(f64, f64)]) -> Option<KarlinAltschul> {
+        let total: f64 = distribution.iter().map(|(_, p)| p).sum();
+        let distribution: Vec<(f64, f64)> = distribution
+            .iter()
+            .filter(|(s, p)| *p > 0.0 && s.is_finite())
+            .map(|(s, p)| (*s, p / total))
+            .collect();
+        let mean: f64 = distribution.iter().map(|(s, p)| s * p).sum();
+        if mean >= 0.0 || !distribution.iter().any(|(s, _)| *s > 0.0) {
+            return None;
+        }
+
+        let lambda = solve_lambda(&distribution);
+        let h = lambda * distribution.iter().map(|(s, p)| p * s * (lambda * s).exp()).sum::<f64>();
+        let k = solve_k(&distribution, lambda, h);
+        return Some(KarlinAltschul { lambda, k, h });
+    }
+
+    /// Like `from_distribution`, with the scores and their background frequencies given side by side.
+    pub fn from_scores(scores: &[f64], frequencies: &[f64]) -> Option<KarlinAltschul> {
+        let distribution: Vec<(f64, f64)> = scores.iter().copied().zip(frequencies.iter().copied()).collect();
+        return KarlinAltschul::from_distribution(&distribution);
+    }
+
+    /// Expected number of segments scoring at least `score` by chance in a search space of `search_space` positions,
+    /// e.g. the length of a sequence, or the product of the lengths of sequences aligned to each other.
+    pub fn e_value(&self, score: f64, search_space: f64) -> f64 {
+        return self.k * search_space * (-self.lambda * score).exp();
+    }
+
+    /// Chance of at least one segment scoring `score` or more.
+    pub fn p_value(&self, score: f64, search_space: f64) -> f64 {
+        return -(-self.e_value(score, search_space)).exp_m1();
+    }
+
+    /// The score in bits, normalised so that scores of different schemes can be compared.
+    pub fn bit_score(&self, score: f64) -> f64 {
+        return (self.lambda * score - self.k.ln()) / std::f64::consts::LN_2;
+    }
+}
+
+/// Finds `lambda` by bisection. `sum p(s) exp(x s) - 1` is convex, zero at zero and falls from there when the mean
+/// score is negative, so it has exactly one positive root.
+fn solve_lambda(distribution: &[(f64, f64)]) -> f64 {
+    let f = |x: f64| distribution.iter().map(|(s, p)| p * (x * s).exp()).sum::<f64>() - 1.0;
+    let mut high = 1.0;
+    while f(high) < 0.0 {
+        high *= 2.0;
+    }
+    let mut low = 0.0;
+    for _ in 0..200 {
+        let mid = (low + high) / 2.0;
+        if f(mid) < 0.0 {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    return (low + high) / 2.0;
+}
+
+/// Works out `K` from Karlin and Altschul's series over the distribution of sums of `k` scores,
+/// `sigma = sum 1/k (E[exp(lambda S_k); S_k < 0] + P(S_k >= 0))`, which gives
+/// `K = lambda delta exp(-2 sigma) / (H (1 - exp(-lambda delta)))` for scores on a lattice of span `delta`.
+fn solve_k(distribution: &[(f64, f64)], lambda: f64, h: f64) -> f64 {
+    // Scores on a lattice, in steps of the greatest common divisor of the rounded scores
+    let rounded: Vec<(i64, f64)> = distribution.iter().map(|(s, p)| ((s * LATTICE_SCALE).round() as i64, *p)).collect();
+    let step = rounded.iter().fold(0, |g, (s, _)| gcd(g, s.unsigned_abs() as i64)).max(1);
+    let steps: Vec<(i64, f64)> = rounded.iter().map(|(s, p)| (s / step, *p)).collect();
+    let delta = step as f64 / LATTICE_SCALE;
+    let low = steps.iter().map(|(s, _)| *s).min().unwrap();
+    let high = steps.iter().map(|(s, _)| *s).max().unwrap();
+
+    // Distribution of the sum of k scores, indexed from k * low
+    let mut sum_distribution = vec![1.0];
+    let mut sigma = 0.0;
+    for k in 1..=MAX_TERMS {
+        let mut next = vec![0.0; sum_distribution.len() + (high - low) as usize];
+        for (i, p) in sum_distribution.iter().enumerate() {
+            if *p == 0.0 {
+                continue;
+            }
+            for (s, q) in &steps {
+                next[i + (s - low) as usize] += p * q;
+            }
+        }
+        sum_distribution = next;
+
+        let offset = k as i64 * low;
+        let mut term = 0.0;
+        for (i, p) in sum_distribution.iter().enumerate() {
+            let sum = offset + i as i64;
+            term += if sum < 0 { p * (lambda * sum as f64 * delta).exp() } else { *p };
+        }
+        sigma += term / k as f64;
+        if term / (k as f64) < TERM_TOLERANCE {
+            break;
+        }
+    }
+    return lambda * delta * (-2.0 * sigma).exp() / (h * -(-lambda * delta).exp_m1());
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    return if b == 0 { a } else { gcd(b, a % b) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::LN_2;
+
+    #[test]
+    fn log2_odds_scores_give_lambda_ln_2() {
+        let background: [f64; 4] = [0.3, 0.2, 0.2, 0.3];
+        let target: [f64; 4] = [0.1, 0.4, 0.4, 0.1];
+        let scores: Vec<f64> = (0..4).map(|i| (target[i] / background[i]).log2()).collect();
+        let statistics = KarlinAltschul::from_scores(&scores, &background).unwrap();
+        assert!((statistics.lambda - LN_2).abs() < 1e-9, "lambda {}", statistics.lambda);
+        // The relative entropy of the target against the background, in nats
+        let h: f64 = (0..4).map(|i| target[i] * (target[i] / background[i]).ln()).sum();
+        assert!((statistics.h - h).abs() < 1e-9);
+    }
+
+    #[test]
+    fn plus_minus_one_walk_matches_the_closed_form() {
+        // Scores of +1 with chance p and -1 otherwise have lambda = ln(q / p) and K = (q - p)^2 / q
+        for p in [0.1, 0.25, 0.4] {
+            let q = 1.0 - p;
+            let statistics = KarlinAltschul::from_distribution(&[(1.0, p), (-1.0, q)]).unwrap();
+            assert!((statistics.lambda - (q / p).ln()).abs() < 1e-9);
+            assert!((statistics.k - (q - p) * (q - p) / q).abs() < 1e-6, "p {} K {}", p, statistics.k);
+        }
+    }
+
+    #[test]
+    fn scores_that_drift_upwards_have_no_statistics() {
+        assert_eq!(KarlinAltschul::from_distribution(&[(1.0, 0.5), (-1.0, 0.5)]), None);
+        assert_eq!(KarlinAltschul::from_distribution(&[(-1.0, 1.0)]), None);
+    }
+
+    #[test]
+    fn e_and_p_values_fall_with_score() {
+        let statistics = KarlinAltschul::from_distribution(&[(1.0, 0.25), (-1.0, 0.75)]).unwrap();
+        let (e10, e20) = (statistics.e_value(10.0, 1e6), statistics.e_value(20.0, 1e6));
+        assert!(e20 < e10);
+        assert!((e10 / e20 - (10.0 * statistics.lambda).exp()).abs() < 1e-6 * e10 / e20);
+        assert!(statistics.p_value(20.0, 1e6) <= e20);
+        assert!((statistics.p_value(20.0, 1e6) - (1.0 - (-e20).exp())).abs() < 1e-12);
+    }
+}
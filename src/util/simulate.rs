@@ -0,0 +1,283 @@
+use crate::util::fasta::Writer;
+use crate::util::markov::{Context, MarkovModel};
+use crate::util::nucleotide::Base;
+use rand::{Rng, RngCore};
+use std::io::{self, Write};
+
+/// Bases generated between writes, so a record is streamed out rather than built up in memory.
+const CHUNK_SIZE: usize = 1 << 16;
+
+/// A source of simulated bases.
+pub trait Generator {
+    fn next_base(&mut self, rng: &mut dyn RngCore) -> Base;
+
+    /// Called at the start of every record, so state such as a Markov context doesn't carry over between records.
+    fn reset(&mut self) {}
+}
+
+/// Independent, identically distributed bases.
+pub struct Iid {
+    freqs: [f64; 4],
+}
+
+impl Iid {
+    /// Frequencies of A, C, G and T. They're normalised, so counts work too.
+    pub fn new(freqs: [f64; 4]) -> Iid {
+        let total: f64 = freqs.iter().sum();
+        return Iid {
+            freqs: freqs.map(|f| f / total),
+        };
+    }
+
+    pub fn uniform() -> Iid {
+        return Iid::new([1.0; 4]);
+    }
+}
+
+impl Generator for Iid {
+    fn next_base(&mut self, rng: &mut dyn RngCore) -> Base {
+        return Base::ACGT[draw(&self.freqs, rng)];
+    }
+}
+
+/// Bases drawn from a trained `MarkovModel`.
+pub struct Markov<'a> {
+    model: &'a MarkovModel,
+    context: Context,
+}
+
+impl<'a> Markov<'a> {
+    pub fn new(model: &'a MarkovModel) -> Markov<'a> {
+        return Markov {
+            model,
+            context: Context::default(),
+        };
+    }
+}
+
+impl Generator for Markov<'_> {
+    fn next_base(&mut self, rng: &mut dyn RngCore) -> Base {
+        return self.model.next_base(&mut self.context, rng);
+    }
+
+    fn reset(&mut self) {
+        self.context = Context::default();
+    }
+}
+
+/// Bases emitted by a hidden Markov model, such as the two-state (AT-rich / GC-rich) model fit in `hw8`.
+///
+/// Probabilities are plain, not logs, and emissions are over A, C, G and T. The hidden state path of the current
+/// record is kept so the simulated segments can be compared against a decoding.
+pub struct Hmm {
+    start: Vec<f64>,
+    transitions: Vec<Vec<f64>>,
+    emissions: Vec<[f64; 4]>,
+    states: Vec<usize>,
+}
+
+impl Hmm {
+    pub fn new(start: Vec<f64>, transitions: Vec<Vec<f64>>, emissions: Vec<[f64; 4]>) -> Hmm {
+        assert!(
+            start.len() == transitions.len() && start.len() == emissions.len() && transitions.iter().all(|t| t.len() == start.len()),
+            "HMM parameters don't agree on the number of states"
+        );
+        return Hmm {
+            start,
+            transitions,
+            emissions,
+            states: Vec::new(),
+        };
+    }
+
+    /// Hidden state of every base generated for the current record.
+    pub fn states(&self) -> &[usize] {
+        return &self.states;
+    }
+}
+
+impl Generator for Hmm {
+    fn next_base(&mut self, rng: &mut dyn RngCore) -> Base {
+        let state = match self.states.last() {
+            Some(prev) => draw(&self.transitions[*prev], rng),
+            None => draw(&self.start, rng),
+        };
+        self.states.push(state);
+        return Base::ACGT[draw(&self.emissions[state], rng)];
+    }
+
+    fn reset(&mut self) {
+        self.states.clear();
+    }
+}
+
+/// Plants instances of a motif into a background sequence.
+///
+/// `columns` holds the base frequencies (A, C, G, T) at each motif position, e.g. the frequency matrix `hw3` builds
+/// around start codons. A site starts at each background position with probability `rate`, and the site starts of
+/// every record are kept so a motif finder can be checked against them.
+pub struct PlantedMotif<G: Generator> {
+    background: G,
+    columns: Vec<[f64; 4]>,
+    rate: f64,
+    position: usize,
+    in_site: Option<usize>,
+    sites: Vec<Vec<usize>>,
+}
+
+impl<G: Generator> PlantedMotif<G> {
+    pub fn new(background: G, columns: Vec<[f64; 4]>, rate: f64) -> PlantedMotif<G> {
+        return PlantedMotif {
+            background,
+            columns,
+            rate,
+            position: 0,
+            in_site: None,
+            sites: Vec::new(),
+        };
+    }
+
+    /// 0-based start of every planted site, per record.
+    pub fn sites(&self) -> &[Vec<usize>] {
+        return &self.sites;
+    }
+}
+
+impl<G: Generator> Generator for PlantedMotif<G> {
+    fn next_base(&mut self, rng: &mut dyn RngCore) -> Base {
+        if self.sites.is_empty() {
+            self.reset();
+        }
+        if self.in_site.is_none() && !self.columns.is_empty() && rng.gen::<f64>() < self.rate {
+            self.in_site = Some(0);
+            self.sites.last_mut().unwrap().push(self.position);
+        }
+        self.position += 1;
+
+        let Some(i) = self.in_site else {
+            return self.background.next_base(rng);
+        };
+        self.in_site = if i + 1 < self.columns.len() { Some(i + 1) } else { None };
+        return Base::ACGT[draw(&self.columns[i], rng)];
+    }
+
+    fn reset(&mut self) {
+        self.background.reset();
+        self.position = 0;
+        self.in_site = None;
+        self.sites.push(Vec::new());
+    }
+}
+
+/// Writes one record of `len` simulated bases.
+pub fn simulate_record<W: Write, G: Generator, R: Rng>(
+    writer: &mut Writer<W>,
+    id: &str,
+    description: &str,
+    len: usize,
+    generator: &mut G,
+    rng: &mut R,
+) -> io::Result<()> {
+    writer.write_header(id, description)?;
+    generator.reset();
+    let mut chunk = Vec::with_capacity(CHUNK_SIZE.min(len));
+    let mut remaining = len;
+    while remaining > 0 {
+        chunk.clear();
+        chunk.extend((0..CHUNK_SIZE.min(remaining)).map(|_| generator.next_base(rng).to_u8()));
+        writer.write_seq(&chunk)?;
+        remaining -= chunk.len();
+    }
+    return Ok(());
+}
+
+/// Writes a record per entry of `lengths`, named `{prefix}_1`, `{prefix}_2` and so on, or just `prefix` when there's
+/// only one.
+pub fn simulate_records<W: Write, G: Generator, R: Rng>(
+    writer: &mut Writer<W>,
+    prefix: &str,
+    lengths: &[usize],
+    generator: &mut G,
+    rng: &mut R,
+) -> io::Result<()> {
+    for (i, len) in lengths.iter().enumerate() {
+        let id = if lengths.len() == 1 {
+            prefix.to_string()
+        } else {
+            format!("{}_{}", prefix, i + 1)
+        };
+        simulate_record(writer, &id, &format!("length={}", len), *len, generator, rng)?;
+    }
+    return Ok(());
+}
+
+/// Index drawn from a categorical distribution. The last index absorbs any rounding error.
+fn draw(probs: &[f64], rng: &mut dyn RngCore) -> usize {
+    let mut score: f64 = rng.gen();
+    for (i, p) in probs.iter().enumerate() {
+        score -= p;
+        if score < 0.0 {
+            return i;
+        }
+    }
+    return probs.len() - 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn hmm_emits_from_the_state_it_is_in_and_forgets_the_path_between_records() {
+        // The states take turns, and each only ever emits one base
+        let mut hmm = Hmm::new(
+            vec![1.0, 0.0],
+            vec![vec![0.0, 1.0], vec![1.0, 0.0]],
+            vec![[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0]],
+        );
+        let mut rng = StdRng::seed_from_u64(9);
+        let mut writer = Writer::new(Vec::new());
+        simulate_record(&mut writer, "first", "", 5, &mut hmm, &mut rng).unwrap();
+        simulate_record(&mut writer, "second", "", 3, &mut hmm, &mut rng).unwrap();
+        assert_eq!(hmm.states(), &[0, 1, 0]);
+        let fasta = String::from_utf8(writer.finish().unwrap()).unwrap();
+        assert_eq!(fasta, ">first\nACACA\n>second\nACA\n");
+    }
+
+    #[test]
+    fn planted_motif_records_where_every_site_starts() {
+        let mut planted = PlantedMotif::new(Iid::new([1.0, 0.0, 0.0, 0.0]), vec![[0.0, 0.0, 1.0, 0.0], [0.0, 1.0, 0.0, 0.0]], 0.1);
+        let mut rng = StdRng::seed_from_u64(9);
+        let mut writer = Writer::new(Vec::new()).line_width(50);
+        simulate_records(&mut writer, "sim", &[200, 120], &mut planted, &mut rng).unwrap();
+        let fasta = String::from_utf8(writer.finish().unwrap()).unwrap();
+
+        let records: Vec<(&str, String)> = fasta
+            .split('>')
+            .skip(1)
+            .map(|r| {
+                let (header, seq) = r.split_once('\n').unwrap();
+                assert!(seq.lines().all(|l| l.len() <= 50));
+                (header, seq.replace('\n', ""))
+            })
+            .collect();
+        assert_eq!(records.iter().map(|r| r.0).collect::<Vec<&str>>(), vec!["sim_1 length=200", "sim_2 length=120"]);
+        assert_eq!(planted.sites().len(), 2);
+        for ((_, seq), sites) in records.iter().zip(planted.sites()) {
+            assert!(!sites.is_empty());
+            // Every site is a G followed by a C, unless it's cut off by the end, and the background is nothing but A
+            for (i, c) in seq.chars().enumerate() {
+                let expected = if sites.contains(&i) {
+                    'G'
+                } else if i > 0 && sites.contains(&(i - 1)) {
+                    'C'
+                } else {
+                    'A'
+                };
+                assert_eq!(c, expected, "base {} of {}", i, seq);
+            }
+        }
+    }
+}
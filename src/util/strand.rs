@@ -0,0 +1,16 @@
+use std::fmt;
+
+#[derive(Eq, Hash, PartialEq, Debug, Clone, Copy, PartialOrd, Ord)]
+pub enum Strand {
+    Forward,
+    Reverse,
+}
+
+impl fmt::Display for Strand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Strand::Forward => write!(f, "forward"),
+            Strand::Reverse => write!(f, "reverse"),
+        };
+    }
+}
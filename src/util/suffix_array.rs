@@ -0,0 +1,293 @@
+const EMPTY: usize = usize::MAX;
+const THRESHOLD_NAIVE: usize = 10;
+
+/// Generalized suffix array over one or more byte sequences, built with SA-IS and paired with a Kasai LCP array.
+///
+/// The sequences are concatenated into a single `u32` text where sequence `i` is followed by the unique separator
+/// symbol `i`, and every byte `b` is stored as `b + sequence_count`. Separators sort before every byte and never
+/// match each other, so no common prefix can run across a sequence boundary.
+pub struct SuffixArray {
+    text: Vec<u32>,
+    starts: Vec<usize>,
+    sa: Vec<usize>,
+    lcp: Vec<usize>,
+}
+
+impl SuffixArray {
+    pub fn new(strings: &[&[u8]]) -> SuffixArray {
+        let k = strings.len();
+        let total_size = strings.iter().fold(0, |sum, s| sum + s.len() + 1);
+        let mut text = Vec::with_capacity(total_size);
+        let mut starts = Vec::with_capacity(k);
+        for (i, string) in strings.iter().enumerate() {
+            starts.push(text.len());
+            text.extend(string.iter().map(|b| *b as u32 + k as u32));
+            text.push(i as u32);
+        }
+
+        let mut sa = sa_is(&text, k + u8::MAX as usize);
+        let mut lcp = kasai(&text, &sa);
+
+        // The first `k` suffixes start at the separators themselves and aren't real suffixes of any input
+        sa.drain(..k);
+        lcp.drain(..k);
+        if let Some(first) = lcp.first_mut() {
+            *first = 0;
+        }
+
+        return SuffixArray { text, starts, sa, lcp };
+    }
+
+    /// Number of suffixes, i.e. the combined length of the input sequences.
+    pub fn len(&self) -> usize {
+        return self.sa.len();
+    }
+
+    /// Text position of the suffix with the given rank.
+    pub fn suffix(&self, rank: usize) -> usize {
+        return self.sa[rank];
+    }
+
+    /// Length of the common prefix between the suffixes at `rank - 1` and `rank` (zero for the first rank).
+    pub fn lcp(&self, rank: usize) -> usize {
+        return self.lcp[rank];
+    }
+
+    pub fn suffixes(&self) -> &[usize] {
+        return &self.sa;
+    }
+
+    pub fn lcps(&self) -> &[usize] {
+        return &self.lcp;
+    }
+
+    pub fn text(&self) -> &[u32] {
+        return &self.text;
+    }
+
+    pub fn sequence_count(&self) -> usize {
+        return self.starts.len();
+    }
+
+    /// Maps a text position to the index of the input sequence it falls in and its offset within that sequence.
+    pub fn source(&self, pos: usize) -> (usize, usize) {
+        let seq = self.starts.partition_point(|s| *s <= pos) - 1;
+        return (seq, pos - self.starts[seq]);
+    }
+
+    /// Text position where the given input sequence begins.
+    pub fn sequence_start(&self, seq: usize) -> usize {
+        return self.starts[seq];
+    }
+
+    pub fn sequence_len(&self, seq: usize) -> usize {
+        let end = if seq + 1 < self.starts.len() { self.starts[seq + 1] } else { self.text.len() };
+        return end - self.starts[seq] - 1;
+    }
+}
+
+/// Suffix array construction by induced sorting (Nong, Zhang & Chan 2009) over symbols in `0..=upper`.
+pub fn sa_is(s: &[u32], upper: usize) -> Vec<usize> {
+    let n = s.len();
+    match n {
+        0 => return Vec::new(),
+        1 => return vec![0],
+        2 => return if s[0] < s[1] { vec![0, 1] } else { vec![1, 0] },
+        _ if n < THRESHOLD_NAIVE => return sa_naive(s),
+        _ => {}
+    }
+
+    // ls[i] is true when suffix i is S-type (smaller than suffix i + 1)
+    let mut ls = vec![false; n];
+    for i in (0..n - 1).rev() {
+        ls[i] = if s[i] == s[i + 1] { ls[i + 1] } else { s[i] < s[i + 1] };
+    }
+
+    // Bucket boundaries: sum_l[c] is where the L-type run of bucket c begins, sum_s[c] where its S-type run begins
+    let mut sum_l = vec![0; upper + 1];
+    let mut sum_s = vec![0; upper + 1];
+    for i in 0..n {
+        if !ls[i] {
+            sum_s[s[i] as usize] += 1;
+        } else {
+            sum_l[s[i] as usize + 1] += 1;
+        }
+    }
+    for c in 0..=upper {
+        sum_s[c] += sum_l[c];
+        if c < upper {
+            sum_l[c + 1] += sum_s[c];
+        }
+    }
+
+    let mut sa = vec![EMPTY; n];
+    let induce = |sa: &mut Vec<usize>, lms: &[usize]| {
+        sa.fill(EMPTY);
+        let mut buf = sum_s.clone();
+        for &d in lms {
+            if d == n {
+                continue;
+            }
+            sa[buf[s[d] as usize]] = d;
+            buf[s[d] as usize] += 1;
+        }
+        buf.copy_from_slice(&sum_l);
+        sa[buf[s[n - 1] as usize]] = n - 1;
+        buf[s[n - 1] as usize] += 1;
+        for i in 0..n {
+            let v = sa[i];
+            if v != EMPTY && v >= 1 && !ls[v - 1] {
+                sa[buf[s[v - 1] as usize]] = v - 1;
+                buf[s[v - 1] as usize] += 1;
+            }
+        }
+        buf.copy_from_slice(&sum_l);
+        for i in (0..n).rev() {
+            let v = sa[i];
+            if v != EMPTY && v >= 1 && ls[v - 1] {
+                // S-type symbols are never the largest, and their run ends where the next bucket's L-type run begins
+                let c = s[v - 1] as usize + 1;
+                buf[c] -= 1;
+                sa[buf[c]] = v - 1;
+            }
+        }
+    };
+
+    let mut lms_map = vec![EMPTY; n + 1];
+    let mut lms = Vec::new();
+    for i in 1..n {
+        if !ls[i - 1] && ls[i] {
+            lms_map[i] = lms.len();
+            lms.push(i);
+        }
+    }
+    let m = lms.len();
+
+    induce(&mut sa, &lms);
+
+    if m > 0 {
+        let sorted_lms: Vec<usize> = sa.iter().filter(|v| **v != EMPTY && lms_map[**v] != EMPTY).copied().collect();
+        let mut rec_s = vec![0; m];
+        let mut rec_upper = 0;
+        rec_s[lms_map[sorted_lms[0]]] = 0;
+        for i in 1..m {
+            let (mut l, mut r) = (sorted_lms[i - 1], sorted_lms[i]);
+            let end_l = if lms_map[l] + 1 < m { lms[lms_map[l] + 1] } else { n };
+            let end_r = if lms_map[r] + 1 < m { lms[lms_map[r] + 1] } else { n };
+            let mut same = true;
+            if end_l - l != end_r - r {
+                same = false;
+            } else {
+                while l < end_l && s[l] == s[r] {
+                    l += 1;
+                    r += 1;
+                }
+                if l == n || r == n || s[l] != s[r] {
+                    same = false;
+                }
+            }
+            if !same {
+                rec_upper += 1;
+            }
+            rec_s[lms_map[sorted_lms[i]]] = rec_upper as u32;
+        }
+
+        let rec_sa = sa_is(&rec_s, rec_upper);
+        let sorted_lms: Vec<usize> = rec_sa.iter().map(|i| lms[*i]).collect();
+        induce(&mut sa, &sorted_lms);
+    }
+
+    return sa;
+}
+
+/// Longest-common-prefix array (Kasai et al. 2001): `lcp[i]` is the common prefix length of suffixes `sa[i - 1]` and `sa[i]`.
+pub fn kasai(s: &[u32], sa: &[usize]) -> Vec<usize> {
+    let n = s.len();
+    let mut rank = vec![0; n];
+    for (i, p) in sa.iter().enumerate() {
+        rank[*p] = i;
+    }
+
+    let mut lcp = vec![0; n];
+    let mut h: usize = 0;
+    for i in 0..n {
+        if rank[i] == 0 {
+            h = 0;
+            continue;
+        }
+        let j = sa[rank[i] - 1];
+        while i + h < n && j + h < n && s[i + h] == s[j + h] {
+            h += 1;
+        }
+        lcp[rank[i]] = h;
+        h = h.saturating_sub(1);
+    }
+
+    return lcp;
+}
+
+fn sa_naive(s: &[u32]) -> Vec<usize> {
+    let mut sa: Vec<usize> = (0..s.len()).collect();
+    sa.sort_by(|a, b| s[*a..].cmp(&s[*b..]));
+    return sa;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn naive_lcp(s: &[u32], sa: &[usize]) -> Vec<usize> {
+        let mut lcp = vec![0; sa.len()];
+        for r in 1..sa.len() {
+            lcp[r] = s[sa[r - 1]..].iter().zip(&s[sa[r]..]).take_while(|(a, b)| a == b).count();
+        }
+        return lcp;
+    }
+
+    #[test]
+    fn sa_is_and_kasai_match_naive_sorting() {
+        let mut rng = StdRng::seed_from_u64(2);
+        for upper in [1, 3, 20] {
+            for len in [0, 1, 2, 5, 9, 10, 11, 50, 500] {
+                let s: Vec<u32> = (0..len).map(|_| rng.gen_range(0..=upper)).collect();
+                let sa = sa_is(&s, upper as usize);
+                assert_eq!(sa, sa_naive(&s), "upper {} text {:?}", upper, s);
+                assert_eq!(kasai(&s, &sa), naive_lcp(&s, &sa));
+            }
+        }
+        // Long runs of one symbol make every suffix but the last LMS-free
+        let run = vec![1; 100];
+        assert_eq!(sa_is(&run, 1), sa_naive(&run));
+    }
+
+    #[test]
+    fn generalized_array_sorts_every_suffix_and_stops_prefixes_at_separators() {
+        let strings: [&[u8]; 3] = [b"BANANA", b"ANANAS", b"NAN"];
+        let suffix_array = SuffixArray::new(&strings);
+        assert_eq!(suffix_array.len(), 15);
+        assert_eq!(suffix_array.sequence_count(), 3);
+
+        let mut expected: Vec<(&[u8], usize, usize)> = Vec::new();
+        for (seq, string) in strings.iter().enumerate() {
+            for offset in 0..string.len() {
+                expected.push((&string[offset..], seq, offset));
+            }
+        }
+        for rank in 0..suffix_array.len() {
+            let (seq, offset) = suffix_array.source(suffix_array.suffix(rank));
+            assert_eq!(suffix_array.sequence_start(seq) + offset, suffix_array.suffix(rank));
+            assert!(expected.contains(&(&strings[seq][offset..], seq, offset)));
+            if rank > 0 {
+                let (prev_seq, prev_offset) = suffix_array.source(suffix_array.suffix(rank - 1));
+                let (prev, this) = (&strings[prev_seq][prev_offset..], &strings[seq][offset..]);
+                assert!(prev <= this);
+                let common = prev.iter().zip(this).take_while(|(a, b)| a == b).count();
+                assert_eq!(suffix_array.lcp(rank), common);
+            }
+        }
+        assert_eq!(suffix_array.lcp(0), 0);
+        assert_eq!((0..3).map(|s| suffix_array.sequence_len(s)).collect::<Vec<usize>>(), vec![6, 6, 3]);
+    }
+}
@@ -0,0 +1,212 @@
+use crate::util::genetic_code::GeneticCode;
+use crate::util::location;
+use crate::util::nucleotide::Base;
+use gb_io::qualifier_key;
+use gb_io::seq::{Feature, Seq};
+use std::io::{self, Error, ErrorKind};
+
+/// Translates nucleotide sequences into protein with one of the genetic codes.
+///
+/// Codons with IUPAC ambiguity codes translate to an amino acid when every base they could stand for gives the same
+/// one, e.g. `GCN` is alanine. Otherwise they give `B` (D or N), `Z` (E or Q) or `J` (I or L) when they can, and `X`
+/// when they can't. Bases left over at the end of the sequence are ignored.
+#[derive(Debug, Clone)]
+pub struct Translator {
+    code: &'static GeneticCode,
+    frame: usize,
+    start_codon: bool,
+    trim_stop: bool,
+}
+
+impl Translator {
+    pub fn new(code: &'static GeneticCode) -> Translator {
+        return Translator {
+            code,
+            frame: 0,
+            start_codon: false,
+            trim_stop: false,
+        };
+    }
+
+    /// Number of bases, 0 to 2, skipped before the first codon. GenBank's `/codon_start` is one more than this.
+    pub fn frame(mut self, frame: usize) -> io::Result<Self> {
+        if frame > 2 {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("frame {} isn't 0, 1 or 2", frame)));
+        }
+        self.frame = frame;
+        return Ok(self);
+    }
+
+    /// Reads the first codon as a start codon, which is methionine whatever it codes for elsewhere, e.g. `GTG`.
+    pub fn start_codon(mut self) -> Self {
+        self.start_codon = true;
+        return self;
+    }
+
+    /// Leaves off a final stop codon, as GenBank `/translation` qualifiers do.
+    pub fn trim_stop(mut self) -> Self {
+        self.trim_stop = true;
+        return self;
+    }
+
+    pub fn translate(&self, seq: &[u8]) -> Vec<u8> {
+        let mut protein: Vec<u8> = seq
+            .get(self.frame..)
+            .unwrap_or(&[])
+            .chunks_exact(3)
+            .map(|c| translate_codon(self.code, c))
+            .collect();
+        if self.start_codon && seq.len() >= self.frame + 3 && self.code.is_start(&seq[self.frame..self.frame + 3]) {
+            protein[0] = b'M';
+        }
+        if self.trim_stop && protein.last() == Some(&b'*') {
+            protein.pop();
+        }
+        return protein;
+    }
+}
+
+/// One-letter amino acid of a codon, which may hold IUPAC ambiguity codes. See `Translator` for how those are read.
+pub fn translate_codon(code: &GeneticCode, codon: &[u8]) -> u8 {
+    if let Some(amino_acid) = code.amino_acid(codon) {
+        return amino_acid;
+    }
+    let Some(masks) = codon.iter().map(|b| Base::from_u8(*b).map(|base| base as u8)).collect::<Option<Vec<u8>>>() else {
+        return b'X';
+    };
+    if masks.len() != 3 {
+        return b'X';
+    }
+
+    // Every amino acid the codon could stand for, as a sorted, deduplicated list
+    let bases = |mask: u8| Base::ACGT.into_iter().filter(move |b| *b as u8 & mask != 0).map(|b| b.to_u8());
+    let mut amino_acids: Vec<u8> = Vec::new();
+    for first in bases(masks[0]) {
+        for second in bases(masks[1]) {
+            for third in bases(masks[2]) {
+                amino_acids.push(code.amino_acid(&[first, second, third]).unwrap());
+            }
+        }
+    }
+    amino_acids.sort_unstable();
+    amino_acids.dedup();
+    return match amino_acids.as_slice() {
+        [amino_acid] => *amino_acid,
+        b"DN" => b'B',
+        b"EQ" => b'Z',
+        b"IL" => b'J',
+        _ => b'X',
+    };
+}
+
+/// A protein cut short at its first stop, with `J` written as `X`, so it only holds symbols of `Alphabet::PROTEIN`
+/// and can be read back by the alignment homework. Also returns whether a stop was cut off.
+pub fn for_alignment(protein: &[u8]) -> (Vec<u8>, bool) {
+    let end = protein.iter().position(|a| *a == b'*');
+    let protein = protein[..end.unwrap_or(protein.len())]
+        .iter()
+        .map(|a| if *a == b'J' { b'X' } else { *a })
+        .collect();
+    return (protein, end.is_some());
+}
+
+/// A CDS feature's translation next to the one its `/translation` qualifier gives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeatureTranslation {
+    pub protein: Vec<u8>,
+    pub expected: Option<String>,
+}
+
+impl FeatureTranslation {
+    /// Whether the translation matches the qualifier, or `None` if the feature has none.
+    pub fn matches(&self) -> Option<bool> {
+        return self.expected.as_ref().map(|e| e.as_bytes() == self.protein.as_slice());
+    }
+}
+
+/// Translates a CDS feature the way GenBank does: with its `/transl_table` if it has one and `default_code` if not,
+/// from its `/codon_start`, with its first codon read as a start codon unless its 5' end is partial, and without its
+/// final stop. Codons changed by `/transl_except`, such as selenocysteine, aren't applied, so those features won't
+/// match their `/translation`.
+pub fn translate_feature(seq: &Seq, feature: &Feature, default_code: &'static GeneticCode) -> io::Result<FeatureTranslation> {
+    let qualifier = |key| feature.qualifier_values(key).next();
+    let code = match qualifier(qualifier_key!("transl_table")) {
+        Some(id) => id
+            .parse()
+            .ok()
+            .and_then(GeneticCode::from_id)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("unknown /transl_table '{}'", id)))?,
+        None => default_code,
+    };
+    let frame = match qualifier(qualifier_key!("codon_start")) {
+        Some(start) => match start.parse::<usize>() {
+            Ok(start @ 1..=3) => start - 1,
+            _ => return Err(Error::new(ErrorKind::InvalidData, format!("/codon_start '{}' isn't 1, 2 or 3", start))),
+        },
+        None => 0,
+    };
+
+    let bases = location::extract(seq, &feature.location)?;
+    let mut translator = Translator::new(code).frame(frame)?.trim_stop();
+    if !location::spans(&feature.location)?[0].partial_five_prime {
+        translator = translator.start_codon();
+    }
+    return Ok(FeatureTranslation {
+        protein: translator.translate(&bases),
+        expected: qualifier(qualifier_key!("translation")).map(|t| t.split_whitespace().collect()),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::genetic_code::BACTERIAL;
+    use crate::util::nucleotide::reverse_complement;
+
+    fn code(id: u8) -> &'static GeneticCode {
+        return GeneticCode::from_id(id).unwrap();
+    }
+
+    #[test]
+    fn stops_and_starts_follow_the_chosen_table() {
+        let seq = b"ATGGCNTGAAGATAA";
+        assert_eq!(Translator::new(code(1)).translate(seq), b"MA*R*");
+        assert_eq!(Translator::new(code(1)).trim_stop().translate(seq), b"MA*R");
+        // Vertebrate mitochondria read TGA as tryptophan and AGA as a stop
+        assert_eq!(Translator::new(code(2)).translate(seq), b"MAW**");
+
+        assert_eq!(Translator::new(code(BACTERIAL)).translate(b"GTGAAA"), b"VK");
+        assert_eq!(Translator::new(code(BACTERIAL)).start_codon().translate(b"GTGAAA"), b"MK");
+        assert_eq!(Translator::new(code(1)).start_codon().translate(b"GCGAAA"), b"AK");
+    }
+
+    #[test]
+    fn reverse_frames_skip_leading_bases_and_ignore_a_trailing_partial_codon() {
+        // ATG AAA CCC on the reverse strand, one base into it and with two left over
+        let forward = b"AAGGGTTTCATC";
+        let reverse = reverse_complement(forward);
+        assert_eq!(Translator::new(code(1)).frame(1).unwrap().translate(&reverse), b"MKP");
+        assert_eq!(Translator::new(code(1)).frame(2).unwrap().translate(&reverse), b"*NP");
+        assert_eq!(Translator::new(code(1)).translate(b"ATGAA"), b"M");
+        assert_eq!(Translator::new(code(1)).frame(2).unwrap().start_codon().translate(b"ATGA"), b"");
+        assert_eq!(Translator::new(code(1)).frame(3).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn ambiguous_codons_translate_when_their_bases_agree() {
+        let standard = code(1);
+        assert_eq!(translate_codon(standard, b"ATH"), b'I');
+        assert_eq!(translate_codon(standard, b"YTA"), b'L');
+        assert_eq!(translate_codon(standard, b"MTT"), b'J');
+        assert_eq!(translate_codon(standard, b"RAY"), b'B');
+        assert_eq!(translate_codon(standard, b"SAR"), b'Z');
+        assert_eq!(translate_codon(standard, b"NNN"), b'X');
+        assert_eq!(translate_codon(standard, b"A-G"), b'X');
+    }
+
+    #[test]
+    fn proteins_for_alignment_stop_at_the_first_stop_and_read_j_as_x() {
+        assert_eq!(for_alignment(b"MJKL"), (b"MXKL".to_vec(), false));
+        assert_eq!(for_alignment(b"MJK*LL*"), (b"MXK".to_vec(), true));
+    }
+}